@@ -1,23 +1,78 @@
 use std::{
-    io::{self, Read},
-    sync::{Arc, Mutex},
+    cell::Cell,
+    io::{Read, Write},
+    ops::{Deref, DerefMut},
+    sync::{mpsc, Arc, Mutex, MutexGuard, OnceLock},
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
-use serde::{Serialize, Deserialize};
+use serde::Serialize;
+use tauri::{Emitter, Manager};
 
 pub mod adc {
     use std::{
-        collections::VecDeque,
-        time::{Duration, SystemTime, UNIX_EPOCH},
+        collections::{HashMap, VecDeque},
+        sync::mpsc,
+        time::{Duration, Instant, SystemTime, UNIX_EPOCH},
     };
     use serde::{Serialize, Deserialize};
 
     const START_BYTE: u8 = 0xAA;  // 10101010
     const STOP_BYTE: u8 = 0x55;   // 01010101
     const TRIGGER_BYTE: u8 = 0xCC; // 11001100 - marks trigger point
+    const REPLY_FLAG: u8 = 0x80;  // Set in the channel byte of command replies
+    const RAW_HISTORY_LEN: usize = 4096;  // Recent raw bytes kept for diagnostics
+    const PACKET_SIZE_HISTORY_LEN: usize = 4096;  // Recent decoded packet sizes kept for diagnostics
+    const MAX_RAW_CAPTURE_BYTES: usize = 16 << 20;  // A capture_raw window stops recording past this
+    const CHANNEL_HISTORY_LEN: usize = 4096;  // Recent samples kept per channel for measurements
+    const RESYNC_WINDOW: Duration = Duration::from_secs(1);
+    const THROUGHPUT_WINDOW: Duration = Duration::from_secs(2);
+    const BITS_PER_SERIAL_BYTE: u32 = 10;  // 8N1: start bit, 8 data bits, stop bit
+    const MIN_PACKET_LEN: usize = 5;  // AA ch hi lo 55, without a trigger byte
+    const DEFAULT_BUFFER_CAP: usize = 1 << 20;  // 1 MiB of unparsed bytes
+    const MIN_BUFFER_CAP: usize = 64;
+    const DC_EMA_ALPHA: f32 = 0.001;  // DC tracking for AC coupling, ~1000 sample time constant
+    const TRIGGER_HF_ALPHA: f32 = 0.05;  // HF reject low-pass, ~20 sample time constant
+    const TRIGGER_LF_ALPHA: f32 = 0.01;  // LF reject baseline, ~100 sample time constant
+    const NOISE_REJECT_LEN: usize = 5;  // Median window for noise reject
+    const MAX_SMOOTHING_WIDTH: usize = 1024;
+    const MAX_LUT_POINTS: usize = 4096;
+    const TRIGGER_RECENT_WINDOW: Duration = Duration::from_millis(250);  // How long the trigger "LED" stays lit
+    const ALARM_MIN_INTERVAL: Duration = Duration::from_millis(500);  // Per-channel alarm rate limit
+    const DEFAULT_PACKET_TIMEOUT: Duration = Duration::from_millis(100);  // Before a stalled partial packet is dropped
+    const MISALIGN_THRESHOLD: u32 = 3;  // Implausible packets in a row before forcing a resync
+    const RESYNC_CHURN_RATIO: f32 = 0.2;  // Discarding this fraction of bytes suggests a wrong baud
+    const MAX_MARKERS: usize = 256;
+    const MAX_MARKER_LABEL: usize = 200;
+    const CAPTURE_RATE_TOLERANCE: f32 = 0.1;  // Rate drift that re-derives a time-based capture length
 
-    #[derive(Debug, Clone, Serialize)]
+    pub const DEFAULT_BAUD_RATE: u32 = 1000000;  // Must match the firmware's Serial.begin()
+    pub const FULL_SCALE_VOLTS: f32 = 10.0;
+    const CODES_PER_FULL_SCALE: f32 = 32768.0;  // 16-bit codes; FULL_SCALE_VOLTS is half the span
+    pub const DIFF_CHANNEL: u8 = 4;  // Virtual channel carrying channel_a - channel_b
+    pub const MAX_OPCODE: u8 = 0x7F;
+    pub const VERTICAL_DIVISIONS: f32 = 8.0;  // The screen spans +-4 divisions around zero
+    pub const STREAM_STOP_OPCODE: u8 = 0x10;
+    pub const STREAM_START_OPCODE: u8 = 0x11;
+    // Capability queries, each answered by one 16-bit reply frame
+    pub const CAP_CHANNELS_OPCODE: u8 = 0x20;     // Number of analog inputs
+    pub const CAP_SAMPLE_RATE_OPCODE: u8 = 0x21;  // Max per-channel sample rate in units of 100 S/s
+    pub const CAP_BAUD_OPCODE: u8 = 0x22;         // Max baud rate in units of 100 baud
+    pub const CAP_RESOLUTION_OPCODE: u8 = 0x23;   // Bit n set: samples can be n + 1 bits wide
+    pub const CAP_FEATURES_OPCODE: u8 = 0x24;     // CAP_FEATURE_* flags
+    pub const CAP_FEATURE_CHECKSUM: u16 = 1 << 0;
+    pub const CAP_FEATURE_SEQUENCE: u16 = 1 << 1;
+    pub const CAPABILITY_OPCODES: [u8; 5] = [
+        CAP_CHANNELS_OPCODE, CAP_SAMPLE_RATE_OPCODE, CAP_BAUD_OPCODE, CAP_RESOLUTION_OPCODE, CAP_FEATURES_OPCODE,
+    ];
+    pub const DEFAULT_REPLY_TIMEOUT: Duration = Duration::from_millis(500);
+    pub const MAX_RAW_CAPTURE_MS: u64 = 10_000;
+
+    pub const STANDARD_BAUD_RATES: [u32; 12] = [
+        9600, 19200, 38400, 57600, 115200, 230400, 250000, 460800, 500000, 921600, 1000000, 2000000,
+    ];
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
     pub struct AdcSample {
         pub channel: u8,
         pub raw_value: u16,
@@ -32,428 +87,8119 @@ pub mod adc {
         pub channel: u8,
         pub level: f32,
         pub rising_edge: bool,
+        /// Compare raw ADC codes against this instead of `level`, skipping calibration
+        #[serde(default)]
+        pub level_raw: Option<u16>,
+        /// Fire on a combination of channel states instead of a single level
+        #[serde(default)]
+        pub pattern: Option<PatternTrigger>,
+        /// Filtering of the level trigger's input; display and logging are unaffected
+        #[serde(default)]
+        pub coupling: TriggerCoupling,
+        /// Fire once on each crossing of `level` in either direction, ignoring `rising_edge`
+        #[serde(default)]
+        pub any_edge: bool,
+        /// Level triggers of each channel's own, evaluated alongside the one above
+        #[serde(default)]
+        pub channel_triggers: [ChannelTrigger; 4],
     }
 
-    pub struct PacketProcessor {
-        buffer: VecDeque<u8>,
-        samples_received: usize,
-        channel_counts: [usize; 4],
-        active_channels: [bool; 4],
-        trigger_config: TriggerConfig,
+    /// One channel's own level trigger, which keeps its level while switched off.
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+    pub struct ChannelTrigger {
+        pub enabled: bool,
+        pub level: f32,
+        pub rising_edge: bool,
     }
 
-    impl PacketProcessor {
-        pub fn new() -> Self {
-            Self {
-                buffer: VecDeque::with_capacity(1024),
-                samples_received: 0,
-                channel_counts: [0; 4],
-                active_channels: [true, true, false, false], // Default: channels 1 and 2 active
-                trigger_config: TriggerConfig {
-                    enabled: false,
-                    channel: 0,
-                    level: 0.0,
-                    rising_edge: true,
-                },
+    impl ChannelTrigger {
+        /// Compared in volts on the trigger path, past the level like the main level trigger.
+        fn fired(&self, voltage: f32) -> bool {
+            if self.rising_edge {
+                voltage > self.level
+            } else {
+                voltage < self.level
             }
         }
+    }
 
-        pub fn add_bytes(&mut self, bytes: &[u8]) {
-            self.buffer.extend(bytes);
-        }
+    /// Filter applied to the trigger channel before it is compared against the level.
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+    pub enum TriggerCoupling {
+        #[default]
+        Dc,
+        HfReject,     // Low-pass, ignores fast glitches
+        LfReject,     // High-pass, ignores drift and hum
+        NoiseReject,  // Running median, ignores isolated spikes
+    }
 
-        pub fn set_active_channels(&mut self, channels: [bool; 4]) {
-            // Ensure only 2 channels are active
-            let active_count = channels.iter().filter(|&&x| x).count();
-            if active_count > 2 {
-                // If more than 2 channels selected, keep only the first two
-                let mut new_channels = [false; 4];
-                let mut count = 0;
-                for (i, &active) in channels.iter().enumerate() {
-                    if active && count < 2 {
-                        new_channels[i] = true;
-                        count += 1;
+    /// Running state of the trigger-path filter, reset whenever the trigger is reconfigured.
+    #[derive(Debug, Default)]
+    struct TriggerFilter {
+        was_above: Option<bool>,  // Whether the last value was past the level, for edge detection
+        lowpass: Option<f32>,
+        baseline: Option<f32>,
+        recent: VecDeque<f32>,
+    }
+
+    impl TriggerFilter {
+        fn apply(&mut self, coupling: TriggerCoupling, value: f32) -> f32 {
+            match coupling {
+                TriggerCoupling::Dc => value,
+                TriggerCoupling::HfReject => {
+                    let filtered = self.lowpass.map_or(value, |lp| lp + TRIGGER_HF_ALPHA * (value - lp));
+                    self.lowpass = Some(filtered);
+                    filtered
+                }
+                TriggerCoupling::LfReject => {
+                    let baseline = self.baseline.map_or(value, |b| b + TRIGGER_LF_ALPHA * (value - b));
+                    self.baseline = Some(baseline);
+                    value - baseline
+                }
+                TriggerCoupling::NoiseReject => {
+                    if self.recent.len() == NOISE_REJECT_LEN {
+                        self.recent.pop_front();
                     }
+                    self.recent.push_back(value);
+                    let mut sorted: Vec<f32> = self.recent.iter().copied().collect();
+                    sorted.sort_by(f32::total_cmp);
+                    sorted[sorted.len() / 2]
                 }
-                self.active_channels = new_channels;
-            } else {
-                self.active_channels = channels;
             }
         }
+    }
 
-        pub fn configure_trigger(&mut self, config: TriggerConfig) {
-            // Clone config before moving it
-            self.trigger_config = config.clone();
-            
-            // Send trigger configuration to firmware
-            let mut cmd = [START_BYTE, 0xFE, 0, 0, 0, STOP_BYTE];  // Full 6-byte command
-            cmd[2] = (config.enabled as u8) << 4;  // Enable/disable in upper 4 bits
-            cmd[2] |= config.channel & 0x03;  // Channel in lower 2 bits
-            cmd[3] = ((config.level * 32768.0 / 10.0) as i16 >> 8) as u8;  // Level high byte
-            cmd[4] = ((config.level * 32768.0 / 10.0) as i16 & 0xFF) as u8;  // Level low byte
-            cmd[5] = if config.rising_edge { 0x01 } else { 0x00 };  // Edge type
-            
-            // Send the command to the firmware
-            if let Ok(mut port) = serialport::new("COM3", 1000000).open() {
-                let _ = port.write_all(&cmd);
-            }
-        }
+    /// Per-channel smoothing, configured separately for the trigger and display paths.
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    pub enum SmoothingFilter {
+        #[default]
+        None,
+        MovingAverage { width: usize },  // Mean of the last `width` samples
+        Exponential { alpha: f32 },      // One-pole low-pass; smaller alpha smooths more
+    }
 
-        pub fn process_packets(&mut self) -> Vec<AdcSample> {
-            let mut samples = Vec::new();
-            let mut triggered = false;
-            
-            // Process all available bytes
-            while self.buffer.len() >= 5 {  // Need at least 5 bytes: start + channel + data(2) + stop
-                // Look for start byte
-                while self.buffer.len() >= 5 && self.buffer[0] != START_BYTE {
-                    self.buffer.pop_front();  // Skip until we find start byte
-                }
-                
-                if self.buffer.len() < 5 {
-                    break;  // Not enough bytes for a complete packet
-                }
-                
-                // Check if we have a trigger marker
-                let has_trigger = self.buffer.len() >= 6 && self.buffer[4] == TRIGGER_BYTE;
-                let packet_size = if has_trigger { 6 } else { 5 };
-                
-                if self.buffer.len() < packet_size {
-                    break;  // Not enough bytes for complete packet
-                }
-                
-                // Verify stop byte
-                let stop_pos = if has_trigger { 5 } else { 4 };
-                if self.buffer[stop_pos] != STOP_BYTE {
-                    self.buffer.pop_front();  // Skip invalid packet
-                    continue;
+    impl SmoothingFilter {
+        fn validate(&self) -> Result<(), String> {
+            match *self {
+                SmoothingFilter::None => Ok(()),
+                SmoothingFilter::MovingAverage { width } if (1..=MAX_SMOOTHING_WIDTH).contains(&width) => Ok(()),
+                SmoothingFilter::MovingAverage { width } => {
+                    Err(format!("Smoothing width must be 1 to {} samples, got {}", MAX_SMOOTHING_WIDTH, width))
                 }
-                
-                // Extract packet data
-                let mut packet = vec![0u8; packet_size];
-                for i in 0..packet_size {
-                    packet[i] = self.buffer.pop_front().unwrap();
+                SmoothingFilter::Exponential { alpha } if alpha > 0.0 && alpha <= 1.0 => Ok(()),
+                SmoothingFilter::Exponential { alpha } => {
+                    Err(format!("Smoothing alpha must be above 0 and at most 1, got {}", alpha))
                 }
-                
-                if let Some(sample) = self.decode_packet(&packet) {
-                    // Only process samples for active channels
-                    if self.active_channels[sample.channel as usize] {
-                        // If trigger is enabled and this is the trigger channel,
-                        // check if it's a trigger point
-                        let is_trigger_point = if self.trigger_config.enabled && 
-                            sample.channel == self.trigger_config.channel {
-                            let voltage = sample.voltage;
-                            if self.trigger_config.rising_edge {
-                                voltage > self.trigger_config.level
-                            } else {
-                                voltage < self.trigger_config.level
-                            }
-                        } else {
-                            sample.is_trigger
-                        };
-
-                        // If we see a trigger point, mark that we're triggered
-                        if is_trigger_point {
-                            triggered = true;
-                        }
+            }
+        }
+    }
 
-                        // Only add samples if we're not using trigger or if we're triggered
-                        if !self.trigger_config.enabled || triggered {
-                            // Create a new sample with the trigger status
-                            let sample = AdcSample {
-                                is_trigger: is_trigger_point,
-                                ..sample
-                            };
+    /// Running state of one channel's smoothing on one path, reset when its filter changes.
+    #[derive(Debug, Default)]
+    struct SmoothingState {
+        recent: VecDeque<f32>,
+        average: Option<f32>,
+    }
 
-                            samples.push(sample.clone());  // Clone the sample before moving it
-                            self.samples_received += 1;
-                            self.channel_counts[sample.channel as usize] += 1;
-                        }
+    impl SmoothingState {
+        fn apply(&mut self, filter: SmoothingFilter, value: f32) -> f32 {
+            match filter {
+                SmoothingFilter::None => value,
+                SmoothingFilter::MovingAverage { width } => {
+                    if self.recent.len() >= width {
+                        self.recent.pop_front();
                     }
+                    self.recent.push_back(value);
+                    self.recent.iter().sum::<f32>() / self.recent.len() as f32
+                }
+                SmoothingFilter::Exponential { alpha } => {
+                    let average = self.average.map_or(value, |a| a + alpha * (value - a));
+                    self.average = Some(average);
+                    average
                 }
             }
-            samples
         }
+    }
 
-        fn decode_packet(&self, packet: &[u8]) -> Option<AdcSample> {
-            // Verify start and stop bytes
-            if packet[0] != START_BYTE || packet[packet.len() - 1] != STOP_BYTE {
-                return None;
-            }
-            
-            // Extract channel and data
-            let channel = packet[1] & 0x03;  // Channel number (0-3)
-            let raw_data = ((packet[2] as u16) << 8) | (packet[3] as u16);
-            let is_trigger = packet.len() > 5 && packet[4] == TRIGGER_BYTE;
+    /// Required state of one channel in a pattern trigger.
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "lowercase")]
+    pub enum PatternLevel {
+        High,
+        Low,
+        #[default]
+        DontCare,
+    }
 
-            let voltage = (raw_data as i16 as f32) * (10.0 / 32768.0);
-            let timestamp = SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap_or(Duration::from_secs(0))
-                .as_nanos();
+    /// Triggers while every channel that isn't `DontCare` is past its threshold.
+    #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+    pub struct PatternTrigger {
+        pub conditions: [PatternLevel; 4],
+        pub thresholds: [f32; 4],
+    }
 
-            Some(AdcSample { 
-                channel, 
-                raw_value: raw_data, 
-                voltage, 
-                timestamp,
-                is_trigger,
-            })
+    impl TriggerConfig {
+        /// Whether samples on `channel` are checked for this trigger.
+        fn watches(&self, channel: u8) -> bool {
+            match &self.pattern {
+                Some(pattern) => pattern.conditions[channel as usize] != PatternLevel::DontCare,
+                None => channel == self.channel,
+            }
         }
 
-        pub fn get_stats(&self) -> (usize, [usize; 4]) {
-            (self.samples_received, self.channel_counts)
+        /// Whether any trigger, the main one or a channel's own, is switched on.
+        fn armed(&self) -> bool {
+            self.enabled || self.channel_triggers.iter().any(|t| t.enabled)
         }
 
-        pub fn get_active_channels(&self) -> [bool; 4] {
-            self.active_channels
+        /// Whether an enabled trigger can fire on `channel`.
+        fn fires_on(&self, channel: u8) -> bool {
+            (self.enabled && self.watches(channel))
+                || self.channel_triggers.get(channel as usize).is_some_and(|t| t.enabled)
         }
+    }
 
-        pub fn get_trigger_config(&self) -> TriggerConfig {
-            self.trigger_config.clone()
-        }
+    /// A raw code as a number, read as two's complement when `signed`.
+    pub fn code_number(raw: u16, signed: bool) -> f32 {
+        code_int(raw, signed) as f32
     }
-}
 
-mod logger {
-    use std::{
-        fs::File,
-        io::Write,
-        sync::{atomic::{AtomicBool, Ordering}, Mutex},
-    };
-    use crate::adc;  // Import the adc module
+    /// A raw code as an integer, read as two's complement when `signed`.
+    fn code_int(raw: u16, signed: bool) -> i32 {
+        if signed {
+            raw as i16 as i32
+        } else {
+            raw as i32
+        }
+    }
 
-    pub struct Logger {
-        recording: AtomicBool,
-        file: Mutex<Option<File>>,
+    /// The side of one boundary code on which a level test holds.
+    #[derive(Debug, Clone, Copy)]
+    struct CodeThreshold {
+        boundary: i32,
+        ascending: bool,  // Holds from `boundary` up, otherwise from `boundary` down
     }
 
-    impl Logger {
-        pub fn new() -> Self {
-            Self {
-                recording: AtomicBool::new(false),
-                file: Mutex::new(None),
+    impl CodeThreshold {
+        /// Finds where `test` starts holding over `lo..=hi`; it must be monotonic in the code.
+        fn find(lo: i32, hi: i32, ascending: bool, test: impl Fn(i32) -> bool) -> Self {
+            // Binary search for the first code, scanning upward, where the answer flips
+            let flips = |code: i32| test(code) == ascending;
+            let (mut low, mut high) = (lo, hi + 1);
+            while low < high {
+                let mid = low + (high - low) / 2;
+                if flips(mid) {
+                    high = mid;
+                } else {
+                    low = mid + 1;
+                }
             }
+            let boundary = if ascending { low } else { low - 1 };
+            Self { boundary, ascending }
         }
 
-        pub fn start(&self) -> Result<(), String> {
-            let file = File::create("log.csv")
-                .map_err(|e| format!("Failed to create file: {}", e))?;
-
-            writeln!(&file, "timestamp,channel,voltage,raw_value")
-                .map_err(|e| format!("Failed to write CSV header: {}", e))?;
+        fn holds(&self, code: i32) -> bool {
+            if self.ascending {
+                code >= self.boundary
+            } else {
+                code <= self.boundary
+            }
+        }
+    }
 
-            let mut lock = self.file.lock()
-                .map_err(|_| "Failed to lock file".to_string())?;
-            *lock = Some(file);
+    /// The trigger level converted to codes once, so the per-sample check compares integers.
+    ///
+    /// Decoded voltage is monotonic in the code, so `voltage > level` holds on one side
+    /// of a boundary code. Searching for it with the decoding arithmetic itself makes
+    /// the integer tests agree exactly with comparing the decoded floats.
+    #[derive(Debug, Clone, Copy)]
+    struct TriggerCodes {
+        above: CodeThreshold,
+        below: CodeThreshold,
+    }
 
-            self.recording.store(true, Ordering::Relaxed);
-            Ok(())
+    impl Default for TriggerCodes {
+        fn default() -> Self {
+            let never = CodeThreshold { boundary: i32::MAX, ascending: true };
+            Self { above: never, below: never }
         }
+    }
 
-        pub fn stop(&self) -> Result<(), String> {
-            self.recording.store(false, Ordering::Relaxed);
-            let mut lock = self.file.lock()
-                .map_err(|_| "Failed to lock file".to_string())?;
-            *lock = None;
-            Ok(())
+    /// Uncalibrated voltage of a raw code, read as two's complement when `signed`.
+    pub fn code_to_volts(raw: u16, signed: bool) -> f32 {
+        code_number(raw, signed) * (FULL_SCALE_VOLTS / CODES_PER_FULL_SCALE)
+    }
+
+    /// Nearest raw code to an uncalibrated voltage, saturating at the ends of the
+    /// code range rather than wrapping round to the other rail.
+    pub fn volts_to_code(volts: f32, signed: bool) -> u16 {
+        let value = (volts * (CODES_PER_FULL_SCALE / FULL_SCALE_VOLTS)).round();
+        // Float to int `as` casts saturate
+        if signed {
+            value as i16 as u16
+        } else {
+            value as u16
         }
+    }
 
-        pub fn is_recording(&self) -> bool {
-            self.recording.load(Ordering::Relaxed)
+    /// Whether a raw code sits at either end of the code range, i.e. the input clipped.
+    pub fn code_at_rail(raw: u16, signed: bool) -> bool {
+        if signed {
+            raw == i16::MIN as u16 || raw == i16::MAX as u16
+        } else {
+            raw == 0 || raw == u16::MAX
         }
+    }
 
-        pub fn log_sample(&self, sample: &adc::AdcSample) -> Result<(), String> {
-            if !self.is_recording() {
-                return Ok(());
+    /// Best guess at the framing of an unknown byte stream.
+    #[derive(Debug, Clone, Serialize)]
+    pub struct FramingGuess {
+        pub packet_size: usize,
+        pub start_byte: u8,
+        pub stop_byte: u8,
+        pub confidence: f32,  // Fraction of frames matching start/stop (0-1)
+    }
+
+    /// Scans raw bytes for the most likely repeating frame structure.
+    ///
+    /// For every candidate packet size the most common (first, last) byte pair at
+    /// that distance is taken as the start/stop guess, then the stream is parsed
+    /// greedily with it. Confidence is the fraction of bytes that ended up inside
+    /// a frame, so occasional longer packets (trigger markers) only cost a little.
+    /// Smaller sizes win ties so that a multiple of the real packet size isn't
+    /// reported. Returns `None` when there are too few bytes to see a pattern.
+    pub fn auto_detect_framing(bytes: &[u8]) -> Option<FramingGuess> {
+        const MIN_FRAMES: usize = 8;
+        let mut best: Option<FramingGuess> = None;
+        let mut pair_counts = vec![0u32; 256 * 256];
+
+        for size in 3..=16 {
+            if bytes.len() < size * MIN_FRAMES {
+                break;
             }
 
-            if let Ok(mut lock) = self.file.lock() {
-                if let Some(file) = lock.as_mut() {
-                    let csv_line = format!("{},{},{:.4},{}\n",
-                        sample.timestamp,
-                        sample.channel,
-                        sample.voltage,
-                        sample.raw_value
-                    );
+            pair_counts.iter_mut().for_each(|c| *c = 0);
+            for window in bytes.windows(size) {
+                pair_counts[(window[0] as usize) << 8 | window[size - 1] as usize] += 1;
+            }
+            let (pair, _) = pair_counts.iter()
+                .enumerate()
+                .max_by_key(|&(_, &count)| count)
+                .unwrap_or((0, &0));
+            let start_byte = (pair >> 8) as u8;
+            let stop_byte = (pair & 0xFF) as u8;
 
-                    if let Err(e) = file.write_all(csv_line.as_bytes()) {
-                        return Err(format!("Failed to write to log file: {}", e));
-                    }
-                    if let Err(e) = file.flush() {
-                        return Err(format!("Failed to flush log file: {}", e));
-                    }
+            let mut framed = 0;
+            let mut i = 0;
+            while i + size <= bytes.len() {
+                if bytes[i] == start_byte && bytes[i + size - 1] == stop_byte {
+                    framed += size;
+                    i += size;
+                } else {
+                    i += 1;
                 }
             }
-            Ok(())
+            let confidence = framed as f32 / bytes.len() as f32;
+
+            // Require a clear improvement so larger multiples don't displace the real size
+            if best.as_ref().is_none_or(|b| confidence > b.confidence + 0.05) {
+                best = Some(FramingGuess { packet_size: size, start_byte, stop_byte, confidence });
+            }
         }
+        best
     }
-}
 
-static mut BUFFER: Option<Arc<Mutex<Vec<String>>>> = None;
-static mut ADC_PROCESSOR: Option<Arc<Mutex<adc::PacketProcessor>>> = None;
-static mut LOGGER: Option<Arc<logger::Logger>> = None;
+    /// Space-separated hex with start bytes opened by `[` and stop bytes closed by `]`.
+    ///
+    /// Markers are applied by value, so a data byte that happens to equal a
+    /// framing byte is bracketed too; that ambiguity is often the bug being hunted.
+    pub fn hex_dump(bytes: &[u8]) -> String {
+        bytes.iter()
+            .map(|&b| match b {
+                START_BYTE => format!("[{:02X}", b),
+                STOP_BYTE => format!("{:02X}]", b),
+                _ => format!("{:02X}", b),
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
 
-#[cfg_attr(mobile, tauri::mobile_entry_point)]
-pub fn run() {
-    let buffer = Arc::new(Mutex::new(Vec::new()));
-    let adc_processor = Arc::new(Mutex::new(adc::PacketProcessor::new()));
-    let logger = Arc::new(logger::Logger::new());
-    
-    unsafe {
-        BUFFER = Some(buffer.clone());
-        ADC_PROCESSOR = Some(adc_processor.clone());
-        LOGGER = Some(logger.clone());
+    /// Parser throughput over synthetic packets, from `benchmark_parser`.
+    #[derive(Debug, Clone, Serialize)]
+    pub struct ParserBenchmark {
+        pub packets: usize,
+        pub elapsed_s: f64,
+        pub packets_per_sec: f64,
+        pub mismatches: usize,  // Packets that didn't decode to what was sent; should be 0
     }
 
-    thread::spawn(move || {
-        loop {
-            if let Err(e) = read_serial_into_buffer(
-                buffer.clone(), 
-                adc_processor.clone(),
-                logger.clone(),
-                1024
-            ) {
-                eprintln!("Serial read error: {}", e);
-                thread::sleep(Duration::from_secs(1));
-            }
-        }
-    });
+    pub const MAX_BENCHMARK_PACKETS: usize = 10_000_000;
+    const BENCHMARK_CHUNK: usize = 4096;  // Packets per add_bytes call, well under the buffer cap
 
-    tauri::Builder::default()
-        .invoke_handler(tauri::generate_handler![
-            get_serial_data,
-            toggle_log,
-            set_active_channels,
-            configure_trigger
-        ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
-}
+    /// Parses `num_packets` generated packets with a fresh processor and times it.
+    ///
+    /// Packets alternate between the two default channels, with every 16th carrying
+    /// a trigger marker, and each decoded sample is checked against what was sent.
+    pub fn benchmark_parser(num_packets: usize) -> Result<ParserBenchmark, String> {
+        if num_packets == 0 || num_packets > MAX_BENCHMARK_PACKETS {
+            return Err(format!("Benchmark needs 1 to {} packets, got {}", MAX_BENCHMARK_PACKETS, num_packets));
+        }
+        let expected = |i: usize| ((i % 2) as u8, (i.wrapping_mul(7919) & 0xFFFF) as u16, i.is_multiple_of(16));
+        let chunks: Vec<Vec<u8>> = (0..num_packets)
+            .step_by(BENCHMARK_CHUNK)
+            .map(|start| {
+                let mut bytes = Vec::with_capacity(BENCHMARK_CHUNK * 6);
+                for i in start..(start + BENCHMARK_CHUNK).min(num_packets) {
+                    let (channel, code, trigger) = expected(i);
+                    let [hi, lo] = code.to_be_bytes();
+                    bytes.extend_from_slice(&[START_BYTE, channel, hi, lo]);
+                    if trigger {
+                        bytes.push(TRIGGER_BYTE);
+                    }
+                    bytes.push(STOP_BYTE);
+                }
+                bytes
+            })
+            .collect();
 
-#[tauri::command]
-fn toggle_log(enable: bool) -> Result<(), String> {
-    unsafe {
-        if let Some(logger) = &LOGGER {
-            if enable {
-                logger.start()?;
-            } else {
-                logger.stop()?;
+        let mut processor = PacketProcessor::new();
+        let (mut decoded, mut mismatches) = (0, 0);
+        let started = Instant::now();
+        for chunk in &chunks {
+            processor.add_bytes(chunk);
+            for sample in processor.process_packets() {
+                if (sample.channel, sample.raw_value, sample.is_trigger) != expected(decoded) {
+                    mismatches += 1;
+                }
+                decoded += 1;
             }
+            processor.take_display_samples();  // Keep the display queue from growing
         }
+        let elapsed_s = started.elapsed().as_secs_f64();
+        Ok(ParserBenchmark {
+            packets: num_packets,
+            elapsed_s,
+            packets_per_sec: num_packets as f64 / elapsed_s.max(f64::MIN_POSITIVE),
+            mismatches: mismatches + num_packets.abs_diff(decoded),
+        })
     }
-    Ok(())
-}
 
-#[tauri::command]
-fn get_serial_data() -> Result<Vec<String>, String> {
-    unsafe {
-        if let Some(buffer) = &BUFFER {
-            let mut buf = buffer.lock().map_err(|_| "Failed to lock buffer".to_string())?;
-            let data = buf.clone();
-            //println!("Sending {} data points to frontend", data.len());
-            buf.clear();
-            Ok(data)
-        } else {
-            Err("Buffer not initialized".to_string())
-        }
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct DifferentialConfig {
+        pub enabled: bool,
+        pub channel_a: u8,
+        pub channel_b: u8,
     }
-}
 
-#[tauri::command]
-fn set_active_channels(channels: [bool; 4]) -> Result<(), String> {
-    unsafe {
-        if let Some(processor) = &ADC_PROCESSOR {
-            let mut proc = processor.lock().map_err(|_| "Failed to lock ADC processor".to_string())?;
-            proc.set_active_channels(channels);
-        }
+    /// What sample and marker timestamps count from.
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum TimestampClock {
+        #[default]
+        Monotonic,  // Nanoseconds since acquisition started; never steps backward
+        WallClock,  // Nanoseconds since the Unix epoch; follows NTP and manual clock changes
     }
-    Ok(())
-}
 
-#[tauri::command]
-fn configure_trigger(config: adc::TriggerConfig) -> Result<(), String> {
-    unsafe {
-        if let Some(processor) = &ADC_PROCESSOR {
-            let mut proc = processor.lock().map_err(|_| "Failed to lock ADC processor".to_string())?;
-            proc.configure_trigger(config);
-        }
+    /// How each batch of display samples is sent to the frontend.
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+    #[serde(rename_all = "lowercase")]
+    pub enum StreamEncoding {
+        #[default]
+        Json,    // A `samples` event with the samples as JSON objects
+        Binary,  // Packed by `encode_samples` over the `subscribe_samples` channel
     }
-    Ok(())
-}
 
-fn read_serial_into_buffer(
-    buffer: Arc<Mutex<Vec<String>>>, 
-    adc_processor: Arc<Mutex<adc::PacketProcessor>>,
-    logger: Arc<logger::Logger>,
-    buffer_size: usize
-) -> Result<(), String> {
-    let available_ports = serialport::available_ports()
-        .map_err(|e| format!("Failed to list ports: {}", e))?;
+    pub const ENCODED_SAMPLE_LEN: usize = 15;
 
-    println!("Available ports: {:?}", available_ports);
+    /// Packs samples little-endian, 15 bytes each: channel u8, raw u16, voltage f32,
+    /// then the timestamp as u64 nanoseconds.
+    pub fn encode_samples(samples: &[AdcSample]) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(samples.len() * ENCODED_SAMPLE_LEN);
+        for sample in samples {
+            bytes.push(sample.channel);
+            bytes.extend_from_slice(&sample.raw_value.to_le_bytes());
+            bytes.extend_from_slice(&sample.voltage.to_le_bytes());
+            bytes.extend_from_slice(&(sample.timestamp as u64).to_le_bytes());
+        }
+        bytes
+    }
 
-    let port_info = available_ports
-        .into_iter()
-        .find(|p| p.port_name.to_uppercase().starts_with("COM"))
-        .ok_or("No COM ports found")?;
+    /// Trigger capture geometry, applied together by `configure_capture`.
+    #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+    pub struct CaptureConfig {
+        pub length: Option<usize>,     // Samples per channel; None with no duration is free-running
+        pub duration_us: Option<u64>,  // Length as a time span instead, converted with the sample rate
+        pub pre_trigger: usize,        // Samples kept before the trigger in `get_capture`
+        pub position: f32,             // Where the trigger sits on screen, 0 = left edge, 1 = right
+    }
+
+    /// What the display buffer does once it is full, set by the acquisition mode.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+    pub enum DisplayFill {
+        Scroll,  // Evict the oldest line; free-running
+        Hold,    // Refuse new lines so a triggered or single-shot capture stays intact
+    }
+
+    /// What to do with incoming bytes once the unparsed buffer reaches its cap.
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+    pub enum BackpressurePolicy {
+        #[default]
+        DropOldest,  // Keep the newest bytes, like a scrolling display
+        DropNewest,  // Keep what's already buffered and refuse the excess
+        Block,       // Stop reading from the port until the parser catches up
+    }
+
+    #[derive(Debug, Clone, Serialize)]
+    pub struct BufferStats {
+        pub buffered_bytes: usize,
+        pub capacity: usize,
+        pub policy: BackpressurePolicy,
+        pub dropped_bytes: u64,
+    }
+
+    /// How long the recently decoded sample packets were.
+    #[derive(Debug, Clone, Serialize)]
+    pub struct PacketSizes {
+        pub packets: usize,
+        pub counts: Vec<(usize, usize)>,  // (size in bytes, packets), smallest size first
+        pub most_common: Option<usize>,
+    }
+
+    /// Bytes actually read from the source, measured over a sliding window.
+    #[derive(Debug, Clone, Serialize)]
+    pub struct Throughput {
+        pub bytes_per_sec: f32,
+        pub link_bytes_per_sec: Option<f32>,  // What the baud rate allows, None off serial
+        pub utilization: Option<f32>,  // bytes_per_sec / link_bytes_per_sec
+    }
+
+    /// Expected against measured per-channel sample rate.
+    #[derive(Debug, Clone, Serialize)]
+    pub struct RateHealth {
+        pub expected_hz: Option<f32>,    // Configured, or the mock's rate; None if unknown
+        pub measured_hz: Option<f32>,    // From the buffered sample timestamps
+        pub link_limit_hz: Option<f32>,  // Most the baud rate can carry per active channel
+        pub link_utilization: Option<f32>,
+        pub discrepancy_pct: Option<f32>,  // Positive when fewer samples arrive than expected
+    }
+
+    /// A named point in time inserted by the user.
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct Marker {
+        pub timestamp: u128,
+        pub label: String,
+    }
+
+    /// Snapshot of acquisition state for attaching to bug reports.
+    #[derive(Debug, Clone, Serialize)]
+    pub struct DiagnosticsReport {
+        pub connection: Option<String>,  // Description of the open source, None while disconnected
+        pub last_error: Option<String>,
+        pub samples_received: usize,
+        pub channel_counts: [usize; 4],
+        pub sample_rates: [Option<f32>; 4],  // Measured from each channel's buffered samples
+        pub bytes_received: u64,
+        pub bytes_discarded: u64,            // Skipped while looking for valid packets
+        pub parse_error_rate: f32,           // bytes_discarded / bytes_received
+        pub resync: ResyncStats,
+        pub buffer: BufferStats,
+        pub config: FullConfig,
+    }
+
+    /// Physical quantity a channel measures.
+    #[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+    #[serde(tag = "type", rename_all = "lowercase")]
+    pub enum Unit {
+        #[default]
+        Volt,
+        Amp,
+        Custom { label: String },
+    }
+
+    impl Unit {
+        pub fn symbol(&self) -> &str {
+            match self {
+                Unit::Volt => "V",
+                Unit::Amp => "A",
+                Unit::Custom { label } => label,
+            }
+        }
+    }
+
+    /// A channel's unit and probe factor; the physical value is `voltage * factor`.
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct ChannelUnit {
+        pub unit: Unit,
+        pub factor: f32,
+    }
+
+    impl Default for ChannelUnit {
+        fn default() -> Self {
+            Self { unit: Unit::Volt, factor: 1.0 }
+        }
+    }
+
+    impl ChannelUnit {
+        fn validate(&self) -> Result<(), String> {
+            if !self.factor.is_finite() || self.factor == 0.0 {
+                return Err(format!("Probe factor must be finite and non-zero, got {}", self.factor));
+            }
+            if self.unit.symbol().trim().is_empty() {
+                return Err("Custom unit needs a label".to_string());
+            }
+            Ok(())
+        }
+    }
+
+    /// Input coupling. AC removes the channel's running DC level before display and measurement.
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+    pub enum Coupling {
+        #[default]
+        Dc,
+        Ac,
+    }
+
+    /// Linear correction applied to each channel's decoded voltage.
+    #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+    pub struct Calibration {
+        pub gain: f32,
+        pub offset: f32,
+    }
+
+    impl Default for Calibration {
+        fn default() -> Self {
+            Self { gain: 1.0, offset: 0.0 }
+        }
+    }
+
+    impl Calibration {
+        fn apply(&self, volts: f32) -> f32 {
+            volts * self.gain + self.offset
+        }
+
+        fn remove(&self, volts: f32) -> f32 {
+            (volts - self.offset) / self.gain
+        }
+
+        fn validate(&self, channel: usize) -> Result<(), String> {
+            if !self.gain.is_finite() || self.gain == 0.0 {
+                return Err(format!("Channel {} calibration gain must be finite and non-zero", channel));
+            }
+            if !self.offset.is_finite() {
+                return Err(format!("Channel {} calibration offset must be finite", channel));
+            }
+            Ok(())
+        }
+    }
+
+    /// Piecewise-linear map from raw codes to a channel's physical value, used in
+    /// place of its calibration. Values must be monotonic in the code so trigger
+    /// levels can still be converted to codes.
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Lut {
+        points: Vec<(i32, f32)>,  // Code numbers ascending
+    }
+
+    impl Lut {
+        pub fn new(table: &[(u16, f32)], signed: bool) -> Result<Self, String> {
+            if table.len() < 2 || table.len() > MAX_LUT_POINTS {
+                return Err(format!("Lookup table needs 2 to {} points, got {}", MAX_LUT_POINTS, table.len()));
+            }
+            if let Some(&(code, value)) = table.iter().find(|(_, v)| !v.is_finite()) {
+                return Err(format!("Lookup table value {} for code {} isn't finite", value, code));
+            }
+            let mut points: Vec<(i32, f32)> = table.iter().map(|&(code, v)| (code_int(code, signed), v)).collect();
+            points.sort_by_key(|&(code, _)| code);
+            if let Some(pair) = points.windows(2).find(|pair| pair[0].0 == pair[1].0) {
+                return Err(format!("Lookup table has two values for code {}", pair[0].0 as u16));
+            }
+            let rising = points[1].1 > points[0].1;
+            if !points.windows(2).all(|pair| if rising { pair[1].1 > pair[0].1 } else { pair[1].1 < pair[0].1 }) {
+                return Err("Lookup table values must strictly increase or decrease with the code".to_string());
+            }
+            Ok(Self { points })
+        }
+
+        /// The table as (raw code, value) pairs, in code order.
+        pub fn table(&self) -> Vec<(u16, f32)> {
+            self.points.iter().map(|&(code, v)| (code as u16, v)).collect()
+        }
+
+        fn ascending(&self) -> bool {
+            self.points[1].1 > self.points[0].1
+        }
+
+        /// Lowest and highest value the table maps to.
+        fn range(&self) -> (f32, f32) {
+            let (first, last) = (self.points[0].1, self.points[self.points.len() - 1].1);
+            (first.min(last), first.max(last))
+        }
+
+        /// Value of a code number, interpolated between points and clamped outside them.
+        fn apply(&self, code: i32) -> f32 {
+            let next = self.points.partition_point(|&(c, _)| c < code);
+            let Some(&(c1, v1)) = self.points.get(next) else {
+                return self.points[self.points.len() - 1].1;
+            };
+            if next == 0 {
+                return v1;
+            }
+            let (c0, v0) = self.points[next - 1];
+            v0 + (v1 - v0) * (code - c0) as f32 / (c1 - c0) as f32
+        }
+
+        /// Nearest code number to `value`, clamped to the table's codes.
+        fn remove(&self, value: f32) -> i32 {
+            let rising = self.ascending();
+            let next = self.points.partition_point(|&(_, v)| if rising { v < value } else { v > value });
+            let Some(&(c1, v1)) = self.points.get(next) else {
+                return self.points[self.points.len() - 1].0;
+            };
+            if next == 0 {
+                return c1;
+            }
+            let (c0, v0) = self.points[next - 1];
+            c0 + ((value - v0) / (v1 - v0) * (c1 - c0) as f32).round() as i32
+        }
+    }
+
+    /// Every acquisition setting, applied together by `apply_config`.
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct FullConfig {
+        pub active_channels: [bool; 4],
+        pub trigger: TriggerConfig,
+        pub calibration: [Calibration; 4],
+        pub baud_rate: u32,
+        pub differential: DifferentialConfig,
+        #[serde(default)]
+        pub coupling: [Coupling; 4],
+        #[serde(default)]
+        pub transport: crate::transport::Transport,
+        #[serde(default)]
+        pub skew_ns: [i64; 4],
+        #[serde(default)]
+        pub deadband: [f32; 4],
+        #[serde(default = "default_signed_samples")]
+        pub signed_samples: bool,
+        #[serde(default)]
+        pub units: [ChannelUnit; 4],
+        #[serde(default)]
+        pub expected_channel_order: Option<Vec<u8>>,
+        #[serde(default = "default_measurement_gate")]
+        pub measurement_gate: (f32, f32),
+        #[serde(default)]
+        pub scope_view: ScopeView,
+    }
+
+    /// Vertical scale the frontend draws each channel at, in the channel's unit per division.
+    #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+    pub struct ScopeView {
+        pub volts_per_div: [f32; 4],
+    }
+
+    impl Default for ScopeView {
+        fn default() -> Self {
+            Self { volts_per_div: [1.0; 4] }
+        }
+    }
+
+    /// Smallest 1-2-5 volts/div that keeps `min..=max` on a screen centred on zero.
+    pub fn fit_volts_per_div(min: f32, max: f32) -> f32 {
+        let needed = min.abs().max(max.abs()) / (VERTICAL_DIVISIONS / 2.0);
+        (-3..=3)
+            .flat_map(|exp| [1.0, 2.0, 5.0].map(|step| step * 10f32.powi(exp)))
+            .find(|&volts| volts >= needed)
+            .unwrap_or(5000.0)
+    }
+
+    fn default_signed_samples() -> bool {
+        true
+    }
+
+    fn default_measurement_gate() -> (f32, f32) {
+        (0.0, 1.0)
+    }
+
+    #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+    pub struct VoltageAlarm {
+        pub threshold: f32,
+        pub above: bool,  // Alarm when the voltage rises above (true) or falls below (false)
+    }
+
+    /// Which trigger fired.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum TriggerSource {
+        Main,     // The `TriggerConfig` level or pattern trigger
+        Channel,  // A channel's own `ChannelTrigger`
+        Device,   // A trigger marker from the firmware, with no host trigger on the channel
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum TriggerEdge {
+        Rising,
+        Falling,
+        Pattern,  // The pattern condition became true; there's no single edge
+    }
+
+    /// The most recent trigger, from `get_last_trigger_info`.
+    #[derive(Debug, Clone, Serialize)]
+    pub struct TriggerInfo {
+        pub channel: u8,
+        pub timestamp: u128,
+        pub edge: TriggerEdge,
+        pub source: TriggerSource,
+    }
+
+    #[derive(Debug, Clone, Serialize)]
+    pub struct TriggerStatus {
+        pub recently_fired: bool,
+        pub last_timestamp: Option<u128>,
+        pub last_channel: Option<u8>,
+    }
+
+    /// Payload of the `alarm` event.
+    #[derive(Debug, Clone, Serialize)]
+    pub struct AlarmEvent {
+        pub channel: u8,
+        pub value: f32,
+        pub threshold: f32,
+        pub timestamp: u128,
+    }
+
+    /// Bytes thrown away while hunting for frame boundaries.
+    #[derive(Debug, Clone, Serialize)]
+    pub struct ResyncStats {
+        pub total_discarded: u64,
+        pub discarded_per_sec: f32,  // Over the last completed window
+        pub discard_ratio: f32,      // Discarded / received over the last completed window
+        pub misalignment_corrections: u64,
+        pub interleave_corrections: u64,  // Samples relabeled to match the expected channel order
+        pub packet_timeouts: u64,  // Partial packets dropped after the rest never arrived
+    }
+
+    /// Other standard baud rates worth trying when resync churn is high, closest first.
+    ///
+    /// A mismatched baud rate garbles every byte, so the parser keeps discarding
+    /// while it looks for start/stop bytes. Returns an empty list when churn is normal.
+    pub fn suggest_baud(stats: &ResyncStats, current: u32) -> Vec<u32> {
+        if stats.discard_ratio < RESYNC_CHURN_RATIO {
+            return Vec::new();
+        }
+        let mut rates: Vec<u32> = STANDARD_BAUD_RATES.iter()
+            .copied()
+            .filter(|&rate| rate != current)
+            .collect();
+        rates.sort_by_key(|&rate| (rate as f64 / current as f64).ln().abs().to_bits());
+        rates
+    }
+
+    /// What the connected firmware reports it supports.
+    #[derive(Debug, Clone, PartialEq, Serialize)]
+    pub struct DeviceCapabilities {
+        pub channels: u8,
+        pub max_sample_rate: u32,  // Per channel, in samples per second
+        pub max_baud_rate: u32,
+        pub resolutions: Vec<u8>,  // Supported sample widths in bits
+        pub checksum: bool,
+        pub sequence_numbers: bool,
+    }
+
+    impl DeviceCapabilities {
+        /// Decodes the replies to `CAPABILITY_OPCODES`, in that order.
+        pub fn from_replies(replies: [u16; 5]) -> Self {
+            let [channels, sample_rate, baud, resolutions, features] = replies;
+            Self {
+                channels: channels.min(u8::MAX as u16) as u8,
+                max_sample_rate: sample_rate as u32 * 100,
+                max_baud_rate: baud as u32 * 100,
+                resolutions: (0..16).filter(|bit| resolutions & (1 << bit) != 0).map(|bit| bit + 1).collect(),
+                checksum: features & CAP_FEATURE_CHECKSUM != 0,
+                sequence_numbers: features & CAP_FEATURE_SEQUENCE != 0,
+            }
+        }
+
+        /// Settings in `config` this device can't honour; empty when it's compatible.
+        pub fn incompatibilities(&self, config: &FullConfig) -> Vec<String> {
+            let mut problems = Vec::new();
+            let missing = |ch: u8| ch >= self.channels;
+            for ch in (0..4u8).filter(|&ch| config.active_channels[ch as usize] && missing(ch)) {
+                problems.push(format!("Channel {} is active but the device has {} channels", ch + 1, self.channels));
+            }
+            if config.trigger.enabled && config.trigger.pattern.is_none() && missing(config.trigger.channel) {
+                problems.push(format!("Trigger channel {} doesn't exist on the device", config.trigger.channel + 1));
+            }
+            if let Some(pattern) = &config.trigger.pattern {
+                let dont_care = |ch: usize| pattern.conditions[ch] == PatternLevel::DontCare;
+                if config.trigger.enabled && (0..4u8).any(|ch| !dont_care(ch as usize) && missing(ch)) {
+                    problems.push("Pattern trigger tests a channel the device doesn't have".to_string());
+                }
+            }
+            for (ch, _) in config.trigger.channel_triggers.iter().enumerate().filter(|(_, t)| t.enabled) {
+                if missing(ch as u8) {
+                    problems.push(format!("Channel {} trigger is on but the device has {} channels", ch + 1, self.channels));
+                }
+            }
+            let diff = &config.differential;
+            if diff.enabled && (missing(diff.channel_a) || missing(diff.channel_b)) {
+                problems.push(format!(
+                    "Differential pair {}-{} needs a channel the device doesn't have",
+                    diff.channel_a + 1, diff.channel_b + 1
+                ));
+            }
+            if let Some(order) = config.expected_channel_order.as_ref().filter(|order| order.iter().any(|&ch| missing(ch))) {
+                problems.push(format!("Expected channel order {:?} names a channel the device doesn't have", order));
+            }
+            let serial = matches!(config.transport, crate::transport::Transport::Serial { .. });
+            if serial && config.baud_rate > self.max_baud_rate {
+                problems.push(format!(
+                    "Baud rate {} is above the device's maximum of {}",
+                    config.baud_rate, self.max_baud_rate
+                ));
+            }
+            problems
+        }
+    }
+
+    pub struct PacketProcessor {
+        buffer: VecDeque<u8>,
+        buffer_cap: usize,
+        backpressure: BackpressurePolicy,
+        dropped_bytes: u64,
+        raw_history: VecDeque<u8>,
+        raw_capture: Option<Vec<u8>>,  // Every byte read while a capture_raw window is open
+        packet_sizes: VecDeque<u8>,  // Lengths of the last PACKET_SIZE_HISTORY_LEN decoded packets
+        total_discarded: u64,
+        total_received: u64,
+        window_start: Instant,
+        window_received: u64,
+        window_discarded: u64,
+        resync_stats: ResyncStats,
+        misalignment_streak: u32,
+        misalignment_corrections: u64,
+        channel_order: Option<Vec<u8>>,  // Round-robin order the firmware streams channels in
+        interleave_pos: Option<usize>,   // Index in channel_order of the last sample, once locked
+        interleave_corrections: u64,
+        packet_timeout: Option<Duration>,  // None waits forever for the rest of a packet
+        partial_since: Option<Instant>,    // When the buffer last stopped making progress
+        packet_timeouts: u64,
+        max_packets_per_call: Option<usize>,  // None drains the whole buffer each call
+        alarms: [Option<VoltageAlarm>; 4],
+        low_latency_channel: Option<u8>,
+        fft_window: crate::analysis::WindowFn,
+        noise_floors: [Option<crate::analysis::Spectrum>; 5],  // Background spectra, by channel
+        measurement_gate: (f32, f32),  // Fractions of the triggered capture that measurements use
+        scope_view: ScopeView,
+        display_gain: [f32; 5],  // Per channel, then the differential channel
+        alarm_tripped: [bool; 4],
+        alarm_last_fired: [Option<Instant>; 4],
+        samples_received: usize,
+        channel_counts: [usize; 4],
+        history: [VecDeque<AdcSample>; 4],
+        calibration: [Calibration; 4],
+        luts: [Option<Lut>; 4],  // Replace the calibration on channels that have one
+        baud_rate: u32,
+        transport: crate::transport::Transport,
+        mock_fallback: bool,
+        retry_policy: crate::transport::RetryPolicy,
+        connection: Option<String>,
+        connection_id: u64,  // Bumped on every connect and disconnect
+        capabilities: Option<DeviceCapabilities>,  // Reported by the current connection's firmware
+        connected_at: Instant,
+        reads: VecDeque<(Instant, usize)>,  // Reads inside THROUGHPUT_WINDOW
+        read_window_bytes: usize,
+        expected_sample_rate: Option<f32>,  // Per channel, as the firmware is configured
+        last_error: Option<String>,
+        differential: DifferentialConfig,
+        diff_history: VecDeque<AdcSample>,
+        pending_display: Vec<AdcSample>,
+        display_interval_ns: u128,  // Zero disables display throttling
+        stream_encoding: StreamEncoding,
+        per_channel_events: bool,  // JSON samples go out as adc-samples-ch<n> instead of one combined event
+        clock: TimestampClock,
+        clock_start: Instant,  // Zero of the monotonic clock
+        envelopes: [Option<Envelope>; 5],
+        references: [Option<crate::analysis::ReferenceTrace>; 5],  // Per channel, then the differential channel
+        coupling: [Coupling; 4],
+        skew_ns: [i64; 4],
+        deadband: [f32; 4],
+        last_displayed: [Option<f32>; 4],
+        units: [ChannelUnit; 4],
+        signed_samples: bool,  // Raw codes are two's complement (true) or straight unsigned
+        dc_estimate: [Option<f32>; 4],
+        active_channels: [bool; 4],
+        trigger_config: TriggerConfig,
+        trigger_filter: TriggerFilter,
+        trigger_codes: TriggerCodes,
+        trigger_smoothing: [SmoothingFilter; 4],
+        trigger_smoothing_state: [SmoothingState; 4],
+        display_smoothing: [SmoothingFilter; 4],
+        display_smoothing_state: [SmoothingState; 4],
+        last_trigger: Option<(Instant, TriggerInfo)>,
+        pattern_recent: [VecDeque<AdcSample>; 4],  // Last two decoded samples, for pattern alignment
+        capture_length: Option<usize>,  // None is free-running; Some is single-shot per channel
+        capture_duration: Option<(Duration, Option<f32>)>,  // Set in time, with the rate it was last converted at
+        capture_started: bool,
+        pre_trigger: usize,
+        trigger_position: f32,
+        capture_counts: [usize; 4],
+        rearm_delay: Duration,  // Dead time after a capture fills before a trigger can start the next
+        capture_filled_at: Option<u128>,  // Timestamp of the sample that filled the last capture
+        single_capture: Vec<AdcSample>,
+        markers: VecDeque<Marker>,
+        new_markers: Vec<Marker>,  // Not yet handed to the output thread
+        outbound: VecDeque<Vec<u8>>,
+        pending_replies: HashMap<u8, VecDeque<(u64, mpsc::Sender<u16>)>>,  // Keyed by opcode, with request ids
+        next_request_id: u64,
+        heartbeat_timeout: Option<Duration>,  // None disables the watchdog
+        last_heartbeat: Instant,
+        stream_paused: bool,
+    }
+
+    impl PacketProcessor {
+        pub fn new() -> Self {
+            Self {
+                buffer: VecDeque::with_capacity(1024),
+                buffer_cap: DEFAULT_BUFFER_CAP,
+                backpressure: BackpressurePolicy::default(),
+                dropped_bytes: 0,
+                raw_history: VecDeque::with_capacity(RAW_HISTORY_LEN),
+                raw_capture: None,
+                packet_sizes: VecDeque::with_capacity(PACKET_SIZE_HISTORY_LEN),
+                total_discarded: 0,
+                total_received: 0,
+                window_start: Instant::now(),
+                window_received: 0,
+                window_discarded: 0,
+                resync_stats: ResyncStats {
+                    total_discarded: 0,
+                    discarded_per_sec: 0.0,
+                    discard_ratio: 0.0,
+                    misalignment_corrections: 0,
+                    interleave_corrections: 0,
+                    packet_timeouts: 0,
+                },
+                misalignment_streak: 0,
+                misalignment_corrections: 0,
+                channel_order: None,
+                interleave_pos: None,
+                interleave_corrections: 0,
+                packet_timeout: Some(DEFAULT_PACKET_TIMEOUT),
+                partial_since: None,
+                packet_timeouts: 0,
+                max_packets_per_call: None,
+                alarms: [None; 4],
+                low_latency_channel: None,
+                fft_window: crate::analysis::WindowFn::default(),
+                noise_floors: Default::default(),
+                measurement_gate: (0.0, 1.0),
+                scope_view: ScopeView::default(),
+                display_gain: [1.0; 5],
+                alarm_tripped: [false; 4],
+                alarm_last_fired: [None; 4],
+                samples_received: 0,
+                channel_counts: [0; 4],
+                history: std::array::from_fn(|_| VecDeque::with_capacity(CHANNEL_HISTORY_LEN)),
+                calibration: [Calibration::default(); 4],
+                luts: Default::default(),
+                baud_rate: DEFAULT_BAUD_RATE,
+                transport: crate::transport::Transport::default(),
+                mock_fallback: false,
+                retry_policy: crate::transport::RetryPolicy::default(),
+                connection: None,
+                connection_id: 0,
+                capabilities: None,
+                connected_at: Instant::now(),
+                reads: VecDeque::new(),
+                read_window_bytes: 0,
+                expected_sample_rate: None,
+                last_error: None,
+                differential: DifferentialConfig {
+                    enabled: false,
+                    channel_a: 0,
+                    channel_b: 1,
+                },
+                diff_history: VecDeque::with_capacity(CHANNEL_HISTORY_LEN),
+                pending_display: Vec::new(),
+                display_interval_ns: 0,
+                stream_encoding: StreamEncoding::default(),
+                per_channel_events: false,
+                clock: TimestampClock::default(),
+                clock_start: Instant::now(),
+                envelopes: Default::default(),
+                references: Default::default(),
+                coupling: [Coupling::Dc; 4],
+                skew_ns: [0; 4],
+                deadband: [0.0; 4],
+                last_displayed: [None; 4],
+                units: Default::default(),
+                signed_samples: true,
+                dc_estimate: [None; 4],
+                active_channels: [true, true, false, false], // Default: channels 1 and 2 active
+                trigger_config: TriggerConfig {
+                    enabled: false,
+                    channel: 0,
+                    level: 0.0,
+                    rising_edge: true,
+                    level_raw: None,
+                    pattern: None,
+                    coupling: TriggerCoupling::Dc,
+                    any_edge: false,
+                    channel_triggers: [ChannelTrigger::default(); 4],
+                },
+                trigger_filter: TriggerFilter::default(),
+                trigger_codes: TriggerCodes::default(),
+                trigger_smoothing: [SmoothingFilter::None; 4],
+                trigger_smoothing_state: Default::default(),
+                display_smoothing: [SmoothingFilter::None; 4],
+                display_smoothing_state: Default::default(),
+                last_trigger: None,
+                pattern_recent: Default::default(),
+                capture_length: None,
+                capture_duration: None,
+                capture_started: false,
+                pre_trigger: 0,
+                trigger_position: 0.5,
+                capture_counts: [0; 4],
+                rearm_delay: Duration::ZERO,
+                capture_filled_at: None,
+                single_capture: Vec::new(),
+                markers: VecDeque::new(),
+                new_markers: Vec::new(),
+                outbound: VecDeque::new(),
+                pending_replies: HashMap::new(),
+                next_request_id: 0,
+                heartbeat_timeout: None,
+                last_heartbeat: Instant::now(),
+                stream_paused: false,
+            }
+        }
+
+        pub fn add_bytes(&mut self, bytes: &[u8]) {
+            // Before backpressure, so the capture sees exactly what was read
+            if let Some(capture) = &mut self.raw_capture {
+                let room = MAX_RAW_CAPTURE_BYTES - capture.len();
+                capture.extend_from_slice(&bytes[..bytes.len().min(room)]);
+            }
+            match self.backpressure {
+                BackpressurePolicy::DropOldest | BackpressurePolicy::Block => {
+                    // Block only lets in what `read_budget` allowed, so this won't trim it
+                    self.buffer.extend(bytes);
+                    let excess = self.buffer.len().saturating_sub(self.buffer_cap);
+                    self.buffer.drain(..excess);
+                    self.dropped_bytes += excess as u64;
+                }
+                BackpressurePolicy::DropNewest => {
+                    let room = self.buffer_cap.saturating_sub(self.buffer.len());
+                    let accepted = bytes.len().min(room);
+                    self.buffer.extend(&bytes[..accepted]);
+                    self.dropped_bytes += (bytes.len() - accepted) as u64;
+                }
+            }
+            self.window_received += bytes.len() as u64;
+            self.total_received += bytes.len() as u64;
+
+            self.raw_history.extend(bytes);
+            let excess = self.raw_history.len().saturating_sub(RAW_HISTORY_LEN);
+            self.raw_history.drain(..excess);
+        }
+
+        pub fn set_backpressure(&mut self, policy: BackpressurePolicy, capacity: usize) -> Result<(), String> {
+            if capacity < MIN_BUFFER_CAP {
+                return Err(format!("Buffer capacity must be at least {} bytes", MIN_BUFFER_CAP));
+            }
+            self.backpressure = policy;
+            self.buffer_cap = capacity;
+            Ok(())
+        }
+
+        /// Most bytes the reader may take off the port right now; only `Block` limits it.
+        pub fn read_budget(&self) -> usize {
+            match self.backpressure {
+                BackpressurePolicy::Block => self.buffer_cap.saturating_sub(self.buffer.len()),
+                _ => usize::MAX,
+            }
+        }
+
+        pub fn get_buffer_stats(&self) -> BufferStats {
+            BufferStats {
+                buffered_bytes: self.buffer.len(),
+                capacity: self.buffer_cap,
+                policy: self.backpressure,
+                dropped_bytes: self.dropped_bytes,
+            }
+        }
+
+        /// Last few thousand bytes read from the device, regardless of whether they parsed.
+        pub fn recent_raw_bytes(&self) -> Vec<u8> {
+            self.raw_history.iter().copied().collect()
+        }
+
+        /// Starts recording every byte read until `finish_raw_capture`.
+        pub fn start_raw_capture(&mut self) -> Result<(), String> {
+            if self.raw_capture.is_some() {
+                return Err("A raw capture is already running".to_string());
+            }
+            self.raw_capture = Some(Vec::new());
+            Ok(())
+        }
+
+        /// The bytes read since `start_raw_capture`, at most `MAX_RAW_CAPTURE_BYTES`.
+        pub fn finish_raw_capture(&mut self) -> Vec<u8> {
+            self.raw_capture.take().unwrap_or_default()
+        }
+
+        /// The newest `count` raw bytes, oldest first.
+        pub fn last_raw_bytes(&self, count: usize) -> Vec<u8> {
+            let skip = self.raw_history.len().saturating_sub(count);
+            self.raw_history.iter().skip(skip).copied().collect()
+        }
+
+        /// Enables the given channels, at most the first two.
+        ///
+        /// A channel that was off starts with an empty history so stale samples from
+        /// before it was disabled don't reappear; channels that stay on keep theirs.
+        pub fn set_active_channels(&mut self, channels: [bool; 4]) {
+            let previous = self.active_channels;
+            // Channels the device reported it doesn't have stay off
+            let available = self.capabilities.as_ref().map_or(4, |caps| caps.channels as usize);
+            let channels: [bool; 4] = std::array::from_fn(|ch| channels[ch] && ch < available);
+            // Ensure only 2 channels are active
+            let active_count = channels.iter().filter(|&&x| x).count();
+            if active_count > 2 {
+                // If more than 2 channels selected, keep only the first two
+                let mut new_channels = [false; 4];
+                let mut count = 0;
+                for (i, &active) in channels.iter().enumerate() {
+                    if active && count < 2 {
+                        new_channels[i] = true;
+                        count += 1;
+                    }
+                }
+                self.active_channels = new_channels;
+            } else {
+                self.active_channels = channels;
+            }
+
+            for ch in (0..4).filter(|&ch| self.active_channels[ch] && !previous[ch]) {
+                self.history[ch].clear();
+                self.dc_estimate[ch] = None;
+                self.last_displayed[ch] = None;
+                self.pattern_recent[ch].clear();
+                if self.differential.channel_a as usize == ch || self.differential.channel_b as usize == ch {
+                    self.diff_history.clear();
+                }
+            }
+        }
+
+        pub fn configure_trigger(&mut self, config: TriggerConfig) -> Result<(), String> {
+            validate_trigger(&config, self.signed_samples, &self.calibration, &self.luts)?;
+            // Clone config before moving it
+            self.trigger_config = config.clone();
+            self.trigger_filter = TriggerFilter::default();
+            self.refresh_trigger_codes();
+            
+            // Send trigger configuration to firmware
+            let mut cmd = [START_BYTE, 0xFE, 0, 0, 0, STOP_BYTE];  // Full 6-byte command
+            cmd[2] = (config.enabled as u8) << 4;  // Enable/disable in upper 4 bits
+            cmd[2] |= config.channel & 0x03;  // Channel in lower 2 bits
+            let [level_hi, level_lo] = self.trigger_level_code(&config).to_be_bytes();
+            cmd[3] = level_hi;
+            cmd[4] = level_lo;
+            cmd[5] = if config.rising_edge { 0x01 } else { 0x00 };  // Edge type
+            
+            // The reader writes it to whichever source is connected
+            self.outbound.push_back(cmd.to_vec());
+            Ok(())
+        }
+
+        /// The trigger level as the raw code the firmware compares against.
+        fn trigger_level_code(&self, config: &TriggerConfig) -> u16 {
+            config.level_raw.unwrap_or_else(|| self.volts_to_code(config.channel, config.level))
+        }
+
+        /// Replaces the level trigger with a pattern, or goes back to it with None.
+        pub fn set_pattern_trigger(&mut self, pattern: Option<PatternTrigger>) -> Result<(), String> {
+            let config = TriggerConfig { pattern, ..self.trigger_config.clone() };
+            self.configure_trigger(config)
+        }
+
+        /// Replaces one channel's own level trigger, leaving the rest of the trigger as it is.
+        pub fn set_channel_trigger(&mut self, channel: u8, trigger: ChannelTrigger) -> Result<(), String> {
+            if channel > 3 {
+                return Err(format!("Invalid channel: {}", channel));
+            }
+            let mut config = self.trigger_config.clone();
+            config.channel_triggers[channel as usize] = trigger;
+            self.configure_trigger(config)
+        }
+
+        /// Smooths what the trigger sees on `channel`; display and logging are unaffected.
+        pub fn set_trigger_filter(&mut self, channel: u8, filter: SmoothingFilter) -> Result<(), String> {
+            if channel > 3 {
+                return Err(format!("Invalid channel: {}", channel));
+            }
+            filter.validate()?;
+            self.trigger_smoothing[channel as usize] = filter;
+            self.trigger_smoothing_state[channel as usize] = SmoothingState::default();
+            Ok(())
+        }
+
+        /// Smooths the displayed trace of `channel`; triggering, history and logging are unaffected.
+        pub fn set_display_filter(&mut self, channel: u8, filter: SmoothingFilter) -> Result<(), String> {
+            if channel > 3 {
+                return Err(format!("Invalid channel: {}", channel));
+            }
+            filter.validate()?;
+            self.display_smoothing[channel as usize] = filter;
+            self.display_smoothing_state[channel as usize] = SmoothingState::default();
+            Ok(())
+        }
+
+        /// The sample as the trigger sees it, after the channel's trigger smoothing.
+        fn trigger_path(&mut self, sample: &AdcSample) -> AdcSample {
+            let ch = sample.channel as usize;
+            let filter = self.trigger_smoothing[ch];
+            if filter == SmoothingFilter::None {
+                return sample.clone();
+            }
+            let voltage = self.trigger_smoothing_state[ch].apply(filter, sample.voltage);
+            // Level comparisons can be on codes, so those follow the smoothed value
+            AdcSample { voltage, raw_value: self.volts_to_code(sample.channel, voltage), ..sample.clone() }
+        }
+
+        pub fn process_packets(&mut self) -> Vec<AdcSample> {
+            let mut samples = Vec::new();
+            let mut triggered = false;
+            self.expire_partial_packet();
+            let start_len = self.buffer.len();
+            let mut budget = self.max_packets_per_call.unwrap_or(usize::MAX);
+            
+            // Process all available bytes
+            while self.buffer.len() >= 5 {  // Need at least 5 bytes: start + channel + data(2) + stop
+                if budget == 0 {
+                    break;  // The rest waits for the next call so the lock isn't held through a burst
+                }
+                // Look for start byte
+                while self.buffer.len() >= 5 && self.buffer[0] != START_BYTE {
+                    self.buffer.pop_front();  // Skip until we find start byte
+                    self.window_discarded += 1;
+                }
+                
+                if self.buffer.len() < 5 {
+                    break;  // Not enough bytes for a complete packet
+                }
+                
+                // Check if we have a trigger marker
+                let has_trigger = self.buffer.len() >= 6 && self.buffer[4] == TRIGGER_BYTE;
+                let packet_size = if has_trigger { 6 } else { 5 };
+                
+                if self.buffer.len() < packet_size {
+                    break;  // Not enough bytes for complete packet
+                }
+                
+                // Verify stop byte
+                let stop_pos = if has_trigger { 5 } else { 4 };
+                if self.buffer[stop_pos] != STOP_BYTE {
+                    self.buffer.pop_front();  // Skip invalid packet
+                    self.window_discarded += 1;
+                    continue;
+                }
+                
+                // Extract packet data
+                let packet: Vec<u8> = self.buffer.drain(..packet_size).collect();
+                budget -= 1;
+
+                // A dropped byte shifts every following packet, which shows up as channel
+                // bytes out of range. Channels merely inactive on the host are skipped below,
+                // since the device is never told which ones those are.
+                let channel_byte = packet[1];
+                if channel_byte & REPLY_FLAG != 0 && self.dispatch_reply(channel_byte & !REPLY_FLAG, &packet) {
+                    self.misalignment_streak = 0;
+                    continue;
+                }
+                if channel_byte > 3 {
+                    self.misalignment_streak += 1;
+                    if self.misalignment_streak >= MISALIGN_THRESHOLD {
+                        self.force_resync();
+                    }
+                    continue;
+                }
+                self.misalignment_streak = 0;
+
+                // Fix the label first so the right channel's calibration decodes it
+                let mut packet = packet;
+                packet[1] = self.follow_interleave(channel_byte);
+
+                if let Some(sample) = self.decode_packet(&packet) {
+                    if self.packet_sizes.len() == PACKET_SIZE_HISTORY_LEN {
+                        self.packet_sizes.pop_front();
+                    }
+                    self.packet_sizes.push_back(packet.len() as u8);
+
+                    // Only process samples for active channels
+                    if self.active_channels[sample.channel as usize] {
+                        // If trigger is enabled and this is the trigger channel,
+                        // check if it's a trigger point
+                        let trigger_sample = self.trigger_path(&sample);
+                        let main = self.trigger_config.enabled && self.trigger_config.watches(sample.channel);
+                        let own = self.trigger_config.channel_triggers[sample.channel as usize];
+                        let fired = if main || own.enabled {
+                            let main_fired = main && match self.trigger_config.pattern {
+                                Some(pattern) => self.pattern_met(&pattern, &trigger_sample),
+                                None => self.level_crossed(&trigger_sample),
+                            };
+                            if main_fired {
+                                Some((TriggerSource::Main, self.main_trigger_edge()))
+                            } else if own.enabled && own.fired(trigger_sample.voltage) {
+                                Some((TriggerSource::Channel, if own.rising_edge { TriggerEdge::Rising } else { TriggerEdge::Falling }))
+                            } else {
+                                None
+                            }
+                        } else {
+                            let edge = if self.trigger_config.rising_edge { TriggerEdge::Rising } else { TriggerEdge::Falling };
+                            sample.is_trigger.then_some((TriggerSource::Device, edge))
+                        };
+                        let is_trigger_point = fired.is_some();
+
+                        if self.trigger_config.pattern.is_some() {
+                            let recent = &mut self.pattern_recent[sample.channel as usize];
+                            if recent.len() == 2 {
+                                recent.pop_front();
+                            }
+                            recent.push_back(trigger_sample);
+                        }
+
+                        // If we see a trigger point, mark that we're triggered
+                        if let Some((source, edge)) = fired {
+                            triggered = true;
+                            let info = TriggerInfo { channel: sample.channel, timestamp: sample.timestamp, edge, source };
+                            self.last_trigger = Some((Instant::now(), info));
+                        }
+
+                        // Only add samples if we're not using trigger or if we're triggered
+                        if !self.trigger_config.armed() || triggered {
+                            // Create a new sample with the trigger status
+                            let sample = AdcSample {
+                                is_trigger: is_trigger_point,
+                                ..sample
+                            };
+
+                            samples.push(sample.clone());  // Clone the sample before moving it
+                            self.samples_received += 1;
+                            self.channel_counts[sample.channel as usize] += 1;
+                            self.record_sample(sample);
+                        }
+                    }
+                }
+            }
+
+            // Nothing is added while parsing, so a shorter buffer means the head moved.
+            // Whole packets held back by the cap aren't a stall either.
+            if self.buffer.is_empty() || budget == 0 {
+                self.partial_since = None;
+            } else if self.buffer.len() < start_len || self.partial_since.is_none() {
+                self.partial_since = Some(Instant::now());
+            }
+            samples
+        }
+
+        /// Drops the start byte of a packet whose remaining bytes never arrived.
+        ///
+        /// Without this the stalled head waits for bytes that will only be misparsed
+        /// against it, holding up every packet behind it.
+        fn expire_partial_packet(&mut self) {
+            let stalled = self.packet_timeout
+                .zip(self.partial_since)
+                .is_some_and(|(timeout, since)| since.elapsed() >= timeout);
+            if !stalled || self.buffer.is_empty() {
+                return;
+            }
+            self.buffer.pop_front();
+            self.window_discarded += 1;
+            while self.buffer.front().is_some_and(|&b| b != START_BYTE) {
+                self.buffer.pop_front();
+                self.window_discarded += 1;
+            }
+            self.packet_timeouts += 1;
+            self.partial_since = None;
+        }
+
+        pub fn set_packet_timeout(&mut self, timeout: Option<Duration>) -> Result<(), String> {
+            if timeout == Some(Duration::ZERO) {
+                return Err("Packet timeout must be greater than zero".to_string());
+            }
+            self.packet_timeout = timeout;
+            Ok(())
+        }
+
+        /// Caps how many packets one `process_packets` call parses, leaving the rest buffered.
+        pub fn set_max_packets_per_call(&mut self, max: Option<usize>) -> Result<(), String> {
+            if max == Some(0) {
+                return Err("Packet cap must be at least one packet".to_string());
+            }
+            self.max_packets_per_call = max;
+            Ok(())
+        }
+
+        /// Recomputes `trigger_codes` after the level, calibration or signedness changes.
+        fn refresh_trigger_codes(&mut self) {
+            let (lo, hi) = if self.signed_samples { (i16::MIN as i32, i16::MAX as i32) } else { (0, u16::MAX as i32) };
+            let config = &self.trigger_config;
+            self.trigger_codes = match config.level_raw {
+                Some(code) => {
+                    let level = code_int(code, self.signed_samples);
+                    TriggerCodes {
+                        above: CodeThreshold { boundary: level + 1, ascending: true },
+                        below: CodeThreshold { boundary: level - 1, ascending: false },
+                    }
+                }
+                None => {
+                    let Some(cal) = self.calibration.get(config.channel as usize) else {
+                        return;  // Rejected by validation; nothing can match
+                    };
+                    let volts = |code: i32| self.code_to_volts(config.channel, code as u16);
+                    let ascending = match &self.luts[config.channel as usize] {
+                        Some(lut) => lut.ascending(),
+                        None => cal.gain > 0.0,
+                    };
+                    TriggerCodes {
+                        above: CodeThreshold::find(lo, hi, ascending, |code| volts(code) > config.level),
+                        below: CodeThreshold::find(lo, hi, !ascending, |code| volts(code) < config.level),
+                    }
+                }
+            };
+        }
+
+        /// Edge of a main trigger that just fired; any-edge reads it from the new side of the level.
+        fn main_trigger_edge(&self) -> TriggerEdge {
+            if self.trigger_config.pattern.is_some() {
+                return TriggerEdge::Pattern;
+            }
+            let rising = if self.trigger_config.any_edge {
+                self.trigger_filter.was_above == Some(true)
+            } else {
+                self.trigger_config.rising_edge
+            };
+            if rising { TriggerEdge::Rising } else { TriggerEdge::Falling }
+        }
+
+        fn level_crossed(&mut self, sample: &AdcSample) -> bool {
+            let (above, below) = if self.trigger_config.coupling == TriggerCoupling::Dc {
+                let code = code_int(sample.raw_value, self.signed_samples);
+                (self.trigger_codes.above.holds(code), self.trigger_codes.below.holds(code))
+            } else {
+                // Filtered values fall between codes, so these compare as floats
+                let (value, level) = match self.trigger_config.level_raw {
+                    Some(code) => (self.code_value(sample.raw_value), self.code_value(code)),
+                    None => (sample.voltage, self.trigger_config.level),
+                };
+                let value = self.trigger_filter.apply(self.trigger_config.coupling, value);
+                (value > level, value < level)
+            };
+            let was_above = self.trigger_filter.was_above.replace(above);
+            if self.trigger_config.any_edge {
+                return was_above.is_some_and(|was_above| was_above != above);
+            }
+            if self.trigger_config.rising_edge {
+                above
+            } else {
+                below
+            }
+        }
+
+        /// Checks every pattern condition at the instant of `sample`.
+        ///
+        /// Other channels are interpolated to the same skew-corrected time from their
+        /// last two decoded samples, which are kept whether or not they were recorded.
+        fn pattern_met(&mut self, pattern: &PatternTrigger, sample: &AdcSample) -> bool {
+            let instant = apply_skew(sample.timestamp, self.skew_ns[sample.channel as usize]);
+            (0..4).all(|ch| {
+                let voltage = if ch == sample.channel as usize {
+                    sample.voltage
+                } else if pattern.conditions[ch] == PatternLevel::DontCare {
+                    return true;
+                } else {
+                    let time = apply_skew(instant, self.skew_ns[ch].saturating_neg());
+                    match crate::analysis::sample_at(self.pattern_recent[ch].make_contiguous(), time) {
+                        Some(v) => v,
+                        None => return false,
+                    }
+                };
+                match pattern.conditions[ch] {
+                    PatternLevel::High => voltage > pattern.thresholds[ch],
+                    PatternLevel::Low => voltage < pattern.thresholds[ch],
+                    PatternLevel::DontCare => true,
+                }
+            })
+        }
+
+        /// Length of the well-formed packet starting at `pos`, if there is one.
+        fn packet_len_at(&self, pos: usize) -> Option<usize> {
+            if self.buffer.get(pos) != Some(&START_BYTE) || *self.buffer.get(pos + 1)? > 3 {
+                return None;
+            }
+            match (self.buffer.get(pos + 4), self.buffer.get(pos + 5)) {
+                (Some(&STOP_BYTE), _) => Some(5),
+                (Some(&TRIGGER_BYTE), Some(&STOP_BYTE)) => Some(6),
+                _ => None,
+            }
+        }
+
+        /// Hands a reply frame to the oldest caller still waiting on its opcode.
+        ///
+        /// Returns false when nobody asked, so a stray high channel byte is still
+        /// treated as misalignment rather than swallowed as a reply.
+        fn dispatch_reply(&mut self, opcode: u8, packet: &[u8]) -> bool {
+            let value = ((packet[2] as u16) << 8) | (packet[3] as u16);
+            let Some(waiters) = self.pending_replies.get_mut(&opcode) else {
+                return false;
+            };
+            let mut delivered = false;
+            while let Some((_, waiter)) = waiters.pop_front() {
+                // A send fails if that caller already gave up waiting
+                if waiter.send(value).is_ok() {
+                    delivered = true;
+                    break;
+                }
+            }
+            if waiters.is_empty() {
+                self.pending_replies.remove(&opcode);
+            }
+            delivered
+        }
+
+        /// Queues a command frame to be written by the reader thread.
+        pub fn send_command(&mut self, opcode: u8) -> Result<(), String> {
+            if opcode > MAX_OPCODE {
+                return Err(format!("Invalid opcode: {:#04x}", opcode));
+            }
+            self.outbound.push_back(vec![START_BYTE, opcode, STOP_BYTE]);
+            Ok(())
+        }
+
+        /// Sends `opcode` and returns a receiver for the device's reply.
+        ///
+        /// Replies come back as frames whose channel byte is `0x80 | opcode` and are
+        /// matched to callers in the order they asked. The returned id lets a caller
+        /// that stops waiting withdraw with `cancel_request`.
+        pub fn request(&mut self, opcode: u8) -> Result<(u64, mpsc::Receiver<u16>), String> {
+            self.send_command(opcode)?;
+            let (tx, rx) = mpsc::channel();
+            let id = self.next_request_id;
+            self.next_request_id += 1;
+            self.pending_replies.entry(opcode).or_default().push_back((id, tx));
+            Ok((id, rx))
+        }
+
+        /// Forgets a request whose caller gave up, so unanswered opcodes don't pile up.
+        pub fn cancel_request(&mut self, opcode: u8, id: u64) {
+            if let Some(waiters) = self.pending_replies.get_mut(&opcode) {
+                waiters.retain(|(waiter, _)| *waiter != id);
+                if waiters.is_empty() {
+                    self.pending_replies.remove(&opcode);
+                }
+            }
+        }
+
+        /// Stops the device streaming once the UI goes quiet for `timeout`; None turns this off.
+        pub fn set_heartbeat_timeout(&mut self, timeout: Option<Duration>) -> Result<(), String> {
+            if timeout == Some(Duration::ZERO) {
+                return Err("Heartbeat timeout must be greater than zero".to_string());
+            }
+            self.heartbeat_timeout = timeout;
+            self.heartbeat()
+        }
+
+        /// Records that the UI is alive, restarting the stream if the watchdog stopped it.
+        pub fn heartbeat(&mut self) -> Result<(), String> {
+            self.last_heartbeat = Instant::now();
+            if self.stream_paused {
+                self.send_command(STREAM_START_OPCODE)?;
+                self.stream_paused = false;
+            }
+            Ok(())
+        }
+
+        /// Queues a stream stop if the heartbeat has lapsed; returns true when it does.
+        pub fn check_heartbeat(&mut self) -> bool {
+            let Some(timeout) = self.heartbeat_timeout else {
+                return false;
+            };
+            if self.stream_paused || self.last_heartbeat.elapsed() < timeout {
+                return false;
+            }
+            self.stream_paused = self.send_command(STREAM_STOP_OPCODE).is_ok();
+            self.stream_paused
+        }
+
+        /// Command frames waiting to go out to the device.
+        pub fn take_outbound(&mut self) -> Vec<Vec<u8>> {
+            self.outbound.drain(..).collect()
+        }
+
+        /// Drops bytes until two consecutive well-formed packets line up.
+        ///
+        /// If the buffer runs too short to confirm, normal start-byte scanning takes over.
+        fn force_resync(&mut self) {
+            tracing::debug!("Resyncing after {} misaligned packets", self.misalignment_streak);
+            self.misalignment_streak = 0;
+            self.misalignment_corrections += 1;
+            while self.buffer.len() >= 12 {
+                if let Some(len) = self.packet_len_at(0) {
+                    if self.packet_len_at(len).is_some() {
+                        break;  // Already aligned; don't throw away a good packet
+                    }
+                }
+                self.buffer.pop_front();
+                self.window_discarded += 1;
+            }
+        }
+
+        /// Feeds an accepted sample into the measurement history and display queue.
+        ///
+        /// AC coupling is applied here, so both of those see the coupled value while
+        /// the samples returned from `process_packets` (and logged) stay DC coupled.
+        fn record_sample(&mut self, sample: AdcSample) {
+            let channel = sample.channel;
+            let ch = channel as usize;
+
+            let dc = match self.dc_estimate[ch] {
+                Some(dc) => dc + DC_EMA_ALPHA * (sample.voltage - dc),
+                None => sample.voltage,
+            };
+            self.dc_estimate[ch] = Some(dc);
+            let sample = match self.coupling[ch] {
+                Coupling::Dc => sample,
+                Coupling::Ac => AdcSample { voltage: sample.voltage - dc, ..sample },
+            };
+
+            push_bounded(&mut self.history[ch], sample.clone());
+            self.record_capture(&sample);
+
+            // Display and cross-channel alignment use skew-corrected time; history keeps raw time
+            let aligned = AdcSample {
+                timestamp: apply_skew(sample.timestamp, self.skew_ns[ch]),
+                ..sample
+            };
+            if !self.is_differential_input(channel) {
+                self.push_display(aligned.clone());
+            }
+
+            // Each channel_a sample is paired with channel_b interpolated to the same instant
+            let diff = &self.differential;
+            if diff.enabled && channel == diff.channel_a {
+                let b_skew = self.skew_ns[diff.channel_b as usize];
+                let other = self.history[diff.channel_b as usize].make_contiguous();
+                let b_time = apply_skew(aligned.timestamp, b_skew.saturating_neg());
+                if let Some(b) = crate::analysis::sample_at(other, b_time) {
+                    let voltage = aligned.voltage - b;
+                    let diff_sample = AdcSample {
+                        channel: DIFF_CHANNEL,
+                        raw_value: volts_to_code(voltage, self.signed_samples),
+                        voltage,
+                        ..aligned
+                    };
+                    push_bounded(&mut self.diff_history, diff_sample.clone());
+                    self.push_display(diff_sample);
+                }
+            }
+        }
+
+        /// Adds a sample to the single-shot capture once it has triggered, until it is full.
+        fn record_capture(&mut self, sample: &AdcSample) {
+            let Some(length) = self.capture_length else {
+                return;
+            };
+            if !self.capture_started {
+                let fired = sample.is_trigger && self.trigger_config.fires_on(sample.channel);
+                if self.trigger_config.armed() && !fired {
+                    return;
+                }
+                let rearmed_at = self.capture_filled_at.map(|ts| ts + self.rearm_delay.as_nanos());
+                if self.trigger_config.armed() && rearmed_at.is_some_and(|ts| sample.timestamp < ts) {
+                    return;  // Still the tail of the burst the last capture caught
+                }
+                self.capture_started = true;
+            }
+            let count = &mut self.capture_counts[sample.channel as usize];
+            if *count < length {
+                *count += 1;
+                self.single_capture.push(sample.clone());
+                if *count == length && self.capture_counts.iter().filter(|&&c| c == length).count() == 1 {
+                    self.capture_filled_at = Some(sample.timestamp);
+                }
+            }
+        }
+
+        /// How long after a capture fills before a trigger edge can start the next one.
+        pub fn set_rearm_delay(&mut self, delay: Duration) {
+            self.rearm_delay = delay;
+        }
+
+        pub fn rearm_delay(&self) -> Duration {
+            self.rearm_delay
+        }
+
+        /// Switches to single-shot captures of `length` samples per channel, or back to
+        /// free-running with None. Either way the capture is re-armed.
+        pub fn set_capture_length(&mut self, length: Option<usize>) -> Result<(), String> {
+            if length == Some(0) {
+                return Err("Capture length must be at least one sample".to_string());
+            }
+            self.capture_length = length;
+            self.capture_duration = None;
+            self.arm_capture();
+            Ok(())
+        }
+
+        pub fn capture_length(&self) -> Option<usize> {
+            self.capture_length
+        }
+
+        /// Validates and applies all of the capture geometry at once, re-arming the capture.
+        ///
+        /// The trigger sample needs a slot of its own, so `pre_trigger` must be below the
+        /// length. A duration is checked against the length it converts to at the
+        /// current rate, if one has been measured.
+        pub fn configure_capture(&mut self, config: CaptureConfig) -> Result<(), String> {
+            let mut errors = Vec::new();
+            if config.length.is_some() && config.duration_us.is_some() {
+                errors.push("Set either a length or a duration, not both".to_string());
+            }
+            if config.length == Some(0) {
+                errors.push("Capture length must be at least one sample".to_string());
+            }
+            if config.duration_us == Some(0) {
+                errors.push("Capture duration must be greater than zero".to_string());
+            }
+            let length = config.length.or_else(|| {
+                let rate = self.measured_sample_rate()?;
+                Some((config.duration_us? as f64 * 1e-6 * rate as f64).round() as usize)
+            });
+            if let Some(length) = length.filter(|&length| config.pre_trigger >= length) {
+                errors.push(format!(
+                    "Pre-trigger length {} must be less than the capture length of {} samples",
+                    config.pre_trigger, length
+                ));
+            }
+            if !(0.0..=1.0).contains(&config.position) {
+                errors.push(format!("Trigger position must be between 0 and 1, got {}", config.position));
+            }
+            if !errors.is_empty() {
+                return Err(format!("Invalid capture configuration: {}", errors.join("; ")));
+            }
+
+            match config.duration_us {
+                Some(us) => self.set_capture_duration(Duration::from_micros(us))?,
+                None => self.set_capture_length(config.length)?,
+            }
+            self.arm_capture();
+            self.pre_trigger = config.pre_trigger;
+            self.trigger_position = config.position;
+            Ok(())
+        }
+
+        pub fn capture_config(&self) -> CaptureConfig {
+            CaptureConfig {
+                length: self.capture_length.filter(|_| self.capture_duration.is_none()),
+                duration_us: self.capture_duration.map(|(duration, _)| duration.as_micros() as u64),
+                pre_trigger: self.pre_trigger,
+                position: self.trigger_position,
+            }
+        }
+
+        /// Hold in single-shot and triggered modes, scroll while free-running.
+        pub fn display_fill(&self) -> DisplayFill {
+            if self.capture_length.is_some() || self.trigger_config.armed() {
+                DisplayFill::Hold
+            } else {
+                DisplayFill::Scroll
+            }
+        }
+
+        /// Sets the single-shot capture length as a time span.
+        ///
+        /// It is converted with the measured sample rate, now and again whenever that
+        /// rate drifts by more than `CAPTURE_RATE_TOLERANCE`. Until a rate has been
+        /// measured the previous length stays in effect.
+        pub fn set_capture_duration(&mut self, duration: Duration) -> Result<(), String> {
+            if duration.is_zero() {
+                return Err("Capture duration must be greater than zero".to_string());
+            }
+            self.capture_duration = Some((duration, None));
+            self.refresh_capture_duration();
+            Ok(())
+        }
+
+        /// The configured capture duration, or the length converted to time if it was set in samples.
+        pub fn get_capture_duration(&self) -> Option<Duration> {
+            if let Some((duration, _)) = self.capture_duration {
+                return Some(duration);
+            }
+            let rate = self.measured_sample_rate()?;
+            Some(Duration::from_secs_f64(self.capture_length? as f64 / rate as f64))
+        }
+
+        /// Re-derives a time-based capture length if the sample rate has moved significantly.
+        pub fn refresh_capture_duration(&mut self) {
+            let Some((duration, derived_at)) = self.capture_duration else {
+                return;
+            };
+            let Some(rate) = self.measured_sample_rate() else {
+                return;
+            };
+            if derived_at.is_some_and(|old| ((rate - old) / old).abs() < CAPTURE_RATE_TOLERANCE) {
+                return;
+            }
+            self.capture_duration = Some((duration, Some(rate)));
+            let length = ((duration.as_secs_f64() * rate as f64).round() as usize).max(1);
+            if self.capture_length != Some(length) {
+                self.capture_length = Some(length);
+                self.arm_capture();
+            }
+        }
+
+        /// Highest per-channel rate measured over the buffered history.
+        fn measured_sample_rate(&self) -> Option<f32> {
+            (0..4).filter_map(|ch| self.history_rate(ch)).reduce(f32::max)
+        }
+
+        /// Same as `estimate_sample_rate`, without copying the history out.
+        fn history_rate(&self, channel: usize) -> Option<f32> {
+            let history = &self.history[channel];
+            let span_ns = history.back()?.timestamp.checked_sub(history.front()?.timestamp)?;
+            (span_ns > 0).then(|| (history.len() - 1) as f32 * 1e9 / span_ns as f32)
+        }
+
+        /// Discards the current single-shot capture and waits for the next trigger.
+        pub fn arm_capture(&mut self) {
+            self.single_capture.clear();
+            self.capture_counts = [0; 4];
+            self.capture_started = false;
+        }
+
+        /// Fill level of the single-shot capture from 0 to 1; always 1 when free-running.
+        pub fn get_capture_progress(&self) -> f32 {
+            match self.capture_length {
+                Some(length) => *self.capture_counts.iter().max().unwrap_or(&0) as f32 / length as f32,
+                None => 1.0,
+            }
+        }
+
+        /// Samples of the single-shot capture so far, all channels interleaved in arrival order.
+        pub fn single_capture(&self) -> &[AdcSample] {
+            &self.single_capture
+        }
+
+        /// Enables or disables the `channel_a - channel_b` virtual channel.
+        pub fn set_differential(&mut self, config: DifferentialConfig) -> Result<(), String> {
+            validate_differential(&config)?;
+            self.differential = config;
+            self.diff_history.clear();
+            Ok(())
+        }
+
+        pub fn get_differential(&self) -> DifferentialConfig {
+            self.differential.clone()
+        }
+
+        /// Whether a channel is currently folded into the differential trace.
+        pub fn is_differential_input(&self, channel: u8) -> bool {
+            self.differential.enabled
+                && (channel == self.differential.channel_a || channel == self.differential.channel_b)
+        }
+
+        /// Samples to display since the last call: coupled channel samples, with
+        /// differential inputs replaced by their `DIFF_CHANNEL` trace.
+        pub fn take_display_samples(&mut self) -> Vec<AdcSample> {
+            // Frames that have ended in wall-clock time won't see another sample to close them
+            if let Some(current) = self.now().checked_div(self.display_interval_ns) {
+                for slot in self.envelopes.iter_mut() {
+                    if slot.as_ref().is_some_and(|env| env.bucket < current) {
+                        if let Some(env) = slot.take() {
+                            env.flush_into(&mut self.pending_display);
+                        }
+                    }
+                }
+            }
+            std::mem::take(&mut self.pending_display)
+        }
+
+        /// Queues a sample for display, folding it into its frame's envelope when throttled.
+        fn push_display(&mut self, mut sample: AdcSample) {
+            if let Some(&filter) = self.display_smoothing.get(sample.channel as usize) {
+                let state = &mut self.display_smoothing_state[sample.channel as usize];
+                sample.voltage = state.apply(filter, sample.voltage);
+            }
+
+            // Hold the last displayed value until the signal moves past the deadband
+            if let Some(&deadband) = self.deadband.get(sample.channel as usize) {
+                let last = &mut self.last_displayed[sample.channel as usize];
+                match *last {
+                    Some(held) if (sample.voltage - held).abs() <= deadband => sample.voltage = held,
+                    _ => *last = Some(sample.voltage),
+                }
+            }
+
+            if self.display_interval_ns == 0 {
+                self.pending_display.push(sample);
+                return;
+            }
+
+            let bucket = sample.timestamp / self.display_interval_ns;
+            let slot = &mut self.envelopes[sample.channel as usize];
+            match slot {
+                Some(env) if env.bucket == bucket => env.include(sample),
+                _ => {
+                    if let Some(previous) = slot.replace(Envelope::new(bucket, sample)) {
+                        previous.flush_into(&mut self.pending_display);
+                    }
+                }
+            }
+        }
+
+        pub fn set_stream_encoding(&mut self, encoding: StreamEncoding) {
+            self.stream_encoding = encoding;
+        }
+
+        pub fn stream_encoding(&self) -> StreamEncoding {
+            self.stream_encoding
+        }
+
+        pub fn set_per_channel_events(&mut self, enabled: bool) {
+            self.per_channel_events = enabled;
+        }
+
+        pub fn per_channel_events(&self) -> bool {
+            self.per_channel_events
+        }
+
+        /// Switches the timestamp clock.
+        ///
+        /// Buffered samples, markers and the single-shot capture are discarded on a
+        /// change, since their timestamps can't be compared with the new clock's.
+        pub fn set_timestamp_clock(&mut self, clock: TimestampClock) {
+            if clock == self.clock {
+                return;
+            }
+            self.clock = clock;
+            self.history.iter_mut().for_each(VecDeque::clear);
+            self.diff_history.clear();
+            self.pattern_recent.iter_mut().for_each(VecDeque::clear);
+            self.markers.clear();
+            self.capture_filled_at = None;
+            for env in self.envelopes.iter_mut().filter_map(Option::take) {
+                env.flush_into(&mut self.pending_display);
+            }
+            self.arm_capture();
+        }
+
+        pub fn timestamp_clock(&self) -> TimestampClock {
+            self.clock
+        }
+
+        /// The current time on the selected clock, in nanoseconds.
+        fn now(&self) -> u128 {
+            match self.clock {
+                TimestampClock::Monotonic => self.clock_start.elapsed().as_nanos(),
+                TimestampClock::WallClock => now_nanos(),
+            }
+        }
+
+        /// Coalesces display samples into a min/max pair per channel per frame.
+        ///
+        /// At 16 ms that's at most ~125 display points per channel per second no matter
+        /// how fast the device samples, while peaks and glitches stay visible. Logging
+        /// and measurements keep every sample. `None` turns throttling off.
+        pub fn set_display_throttle(&mut self, interval: Option<Duration>) -> Result<(), String> {
+            let interval_ns = interval.map_or(0, |i| i.as_nanos());
+            if interval.is_some() && interval_ns == 0 {
+                return Err("Display frame interval must be non-zero".to_string());
+            }
+            for env in self.envelopes.iter_mut().filter_map(Option::take) {
+                env.flush_into(&mut self.pending_display);
+            }
+            self.display_interval_ns = interval_ns;
+            Ok(())
+        }
+
+        pub fn set_coupling(&mut self, channel: u8, coupling: Coupling) -> Result<(), String> {
+            if channel > 3 {
+                return Err(format!("Invalid channel: {}", channel));
+            }
+            self.coupling[channel as usize] = coupling;
+            Ok(())
+        }
+
+        pub fn get_coupling(&self) -> [Coupling; 4] {
+            self.coupling
+        }
+
+        /// Running DC level of a channel, the same estimate AC coupling subtracts.
+        pub fn get_dc_offset(&self, channel: u8) -> Result<Option<f32>, String> {
+            if channel > 3 {
+                return Err(format!("Invalid channel: {}", channel));
+            }
+            Ok(self.dc_estimate[channel as usize])
+        }
+
+        /// Compensates probe/cable delay by shifting a channel's timestamps.
+        ///
+        /// Positive skew moves the channel later. Only display and cross-channel
+        /// alignment see the shift; logged samples keep their arrival time.
+        pub fn set_channel_skew(&mut self, channel: u8, skew_ns: i64) -> Result<(), String> {
+            if channel > 3 {
+                return Err(format!("Invalid channel: {}", channel));
+            }
+            self.skew_ns[channel as usize] = skew_ns;
+            Ok(())
+        }
+
+        pub fn get_channel_skew(&self) -> [i64; 4] {
+            self.skew_ns
+        }
+
+        /// Suppresses display jitter smaller than `volts`; zero shows every change.
+        ///
+        /// Only the display path holds values, measurements and logging stay raw.
+        pub fn set_deadband(&mut self, channel: u8, volts: f32) -> Result<(), String> {
+            if channel > 3 {
+                return Err(format!("Invalid channel: {}", channel));
+            }
+            validate_deadband(volts)?;
+            self.deadband[channel as usize] = volts;
+            self.last_displayed[channel as usize] = None;
+            Ok(())
+        }
+
+        /// Sets what a channel measures, e.g. a 100 mV/A current probe is `Amp` with factor 10.
+        pub fn set_channel_unit(&mut self, channel: u8, unit: Unit, factor: f32) -> Result<(), String> {
+            if channel > 3 {
+                return Err(format!("Invalid channel: {}", channel));
+            }
+            let unit = ChannelUnit { unit, factor };
+            unit.validate()?;
+            self.units[channel as usize] = unit;
+            Ok(())
+        }
+
+        pub fn get_channel_units(&self) -> [ChannelUnit; 4] {
+            self.units.clone()
+        }
+
+        /// Multiplier from volts to the channel's unit; the differential trace stays in volts.
+        pub fn unit_factor(&self, channel: u8) -> f32 {
+            self.units.get(channel as usize).map_or(1.0, |u| u.factor)
+        }
+
+        /// Calibrated voltage of a raw code on `channel`, or its lookup table's value.
+        pub(crate) fn code_to_volts(&self, channel: u8, raw: u16) -> f32 {
+            match &self.luts[channel as usize] {
+                Some(lut) => lut.apply(code_int(raw, self.signed_samples)),
+                None => self.calibration[channel as usize].apply(code_to_volts(raw, self.signed_samples)),
+            }
+        }
+
+        /// Raw code that decodes to `volts` on `channel`.
+        pub(crate) fn volts_to_code(&self, channel: u8, volts: f32) -> u16 {
+            match &self.luts[channel as usize] {
+                Some(lut) => lut.remove(volts) as u16,
+                None => volts_to_code(self.calibration[channel as usize].remove(volts), self.signed_samples),
+            }
+        }
+
+        /// A raw code as a number, honoring the signed/unsigned setting.
+        fn code_value(&self, raw: u16) -> f32 {
+            code_number(raw, self.signed_samples)
+        }
+
+        /// Treats raw codes as `i16` (the default) or `u16` before scaling.
+        ///
+        /// Unsigned codes scale to 0..20 V; a calibration offset recenters them.
+        /// Whether a decoded sample hit the ADC's rails; always false for the differential trace.
+        pub fn is_clipped(&self, sample: &AdcSample) -> bool {
+            sample.channel != DIFF_CHANNEL && code_at_rail(sample.raw_value, self.signed_samples)
+        }
+
+        pub fn set_signed_samples(&mut self, signed: bool) {
+            if signed != self.signed_samples {
+                self.signed_samples = signed;
+                self.dc_estimate = [None; 4];  // The old baseline is meaningless after the jump
+                // Tables are ordered by code number, which the signedness changes
+                for (ch, slot) in self.luts.iter_mut().enumerate() {
+                    if let Some(lut) = slot.take() {
+                        match Lut::new(&lut.table(), signed) {
+                            Ok(lut) => *slot = Some(lut),
+                            Err(e) => tracing::warn!("Dropped channel {} lookup table: {}", ch, e),
+                        }
+                    }
+                }
+                self.refresh_trigger_codes();
+            }
+        }
+
+        /// Checks a packet's channel label against the expected round-robin order
+        /// and returns the label to decode it under.
+        ///
+        /// Locks onto the order at the first packet. When a label breaks the sequence,
+        /// the next buffered packet tells a corrupted label (the stream carries on as
+        /// expected, so the label is corrected) from a lost packet (the stream carries
+        /// on from this label, so the lock follows it). With nothing buffered to peek
+        /// at, the label is trusted unless it isn't in the order at all.
+        fn follow_interleave(&mut self, channel: u8) -> u8 {
+            let Some(order) = &self.channel_order else {
+                return channel;
+            };
+            let position = order.iter().position(|&c| c == channel);
+            let Some(expected) = self.interleave_pos.map(|pos| (pos + 1) % order.len()) else {
+                self.interleave_pos = position;
+                return channel;
+            };
+            if position == Some(expected) {
+                self.interleave_pos = Some(expected);
+                return channel;
+            }
+
+            let next_label = self.packet_len_at(0).map(|_| self.buffer[1]);
+            if position.is_none() || next_label == Some(order[(expected + 1) % order.len()]) {
+                self.interleave_pos = Some(expected);
+                self.interleave_corrections += 1;
+                order[expected]
+            } else {
+                self.interleave_pos = position;
+                channel
+            }
+        }
+
+        /// Sets the order the firmware interleaves channels in; `None` trusts every label.
+        pub fn set_expected_channel_order(&mut self, order: Option<Vec<u8>>) -> Result<(), String> {
+            if let Some(order) = &order {
+                validate_channel_order(order)?;
+            }
+            self.channel_order = order;
+            self.interleave_pos = None;
+            Ok(())
+        }
+
+        fn decode_packet(&self, packet: &[u8]) -> Option<AdcSample> {
+            // Verify start and stop bytes
+            if packet[0] != START_BYTE || packet[packet.len() - 1] != STOP_BYTE {
+                return None;
+            }
+            
+            // Extract channel and data
+            let channel = packet[1] & 0x03;  // Channel number (0-3)
+            let raw_data = ((packet[2] as u16) << 8) | (packet[3] as u16);
+            let is_trigger = packet.len() > 5 && packet[4] == TRIGGER_BYTE;
+
+            let voltage = self.code_to_volts(channel, raw_data);
+            let timestamp = self.now();
+
+            Some(AdcSample { 
+                channel, 
+                raw_value: raw_data, 
+                voltage, 
+                timestamp,
+                is_trigger,
+            })
+        }
+
+        pub fn get_stats(&self) -> (usize, [usize; 4]) {
+            (self.samples_received, self.channel_counts)
+        }
+
+        /// Timestamps an annotation at the current time.
+        pub fn add_marker(&mut self, label: &str) -> Result<Marker, String> {
+            // Single line, so it can't break the CSV log
+            let label = label.trim().replace(['\r', '\n'], " ");
+            if label.is_empty() {
+                return Err("Marker label must not be empty".to_string());
+            }
+            if label.chars().count() > MAX_MARKER_LABEL {
+                return Err(format!("Marker label must be at most {} characters", MAX_MARKER_LABEL));
+            }
+            let marker = Marker { timestamp: self.now(), label };
+            self.prune_markers();
+            if self.markers.len() == MAX_MARKERS {
+                self.markers.pop_front();
+            }
+            self.markers.push_back(marker.clone());
+            if self.new_markers.len() == MAX_MARKERS {
+                self.new_markers.remove(0);  // Nothing is draining them while disconnected
+            }
+            self.new_markers.push(marker.clone());
+            Ok(marker)
+        }
+
+        /// Markers still inside the buffered history, oldest first.
+        pub fn get_markers(&mut self) -> Vec<Marker> {
+            self.prune_markers();
+            self.markers.iter().cloned().collect()
+        }
+
+        /// Replaces the buffered history with an exported session's, for offline review.
+        ///
+        /// `history` holds one list per channel followed by the differential channel.
+        pub fn restore_session(
+            &mut self,
+            history: Vec<Vec<AdcSample>>,
+            single_capture: Vec<AdcSample>,
+            markers: Vec<Marker>,
+        ) -> Result<(), String> {
+            if history.len() != self.history.len() + 1 {
+                return Err(format!("Expected {} channel histories, got {}", self.history.len() + 1, history.len()));
+            }
+            for (slot, samples) in history.iter().enumerate() {
+                if let Some(sample) = samples.iter().find(|s| s.channel as usize != slot) {
+                    return Err(format!("Channel {} history contains a sample from channel {}", slot, sample.channel));
+                }
+            }
+            if let Some(sample) = single_capture.iter().find(|s| s.channel > 3) {
+                return Err(format!("Capture contains a sample from invalid channel {}", sample.channel));
+            }
+
+            let mut history = history.into_iter().map(|samples| {
+                let skip = samples.len().saturating_sub(CHANNEL_HISTORY_LEN);
+                samples.into_iter().skip(skip).collect::<VecDeque<_>>()
+            });
+            for slot in self.history.iter_mut() {
+                *slot = history.next().unwrap_or_default();
+            }
+            self.diff_history = history.next().unwrap_or_default();
+            self.single_capture = single_capture;
+            let skip = markers.len().saturating_sub(MAX_MARKERS);
+            self.markers = markers.into_iter().skip(skip).collect();
+            self.dc_estimate = [None; 4];
+            Ok(())
+        }
+
+        pub fn set_reference(&mut self, reference: crate::analysis::ReferenceTrace) -> Result<(), String> {
+            let slot = self.references.get_mut(reference.channel as usize)
+                .ok_or_else(|| format!("Invalid channel: {}", reference.channel))?;
+            *slot = Some(reference);
+            Ok(())
+        }
+
+        pub fn clear_references(&mut self) {
+            self.references = Default::default();
+        }
+
+        pub fn reference(&self, channel: u8) -> Option<&crate::analysis::ReferenceTrace> {
+            self.references.get(channel as usize)?.as_ref()
+        }
+
+        pub fn references(&self) -> Vec<crate::analysis::ReferenceTrace> {
+            self.references.iter().flatten().cloned().collect()
+        }
+
+        /// Markers added since the last call.
+        pub fn take_new_markers(&mut self) -> Vec<Marker> {
+            std::mem::take(&mut self.new_markers)
+        }
+
+        /// Drops markers older than every channel's oldest buffered sample.
+        fn prune_markers(&mut self) {
+            let oldest = self.history.iter()
+                .filter_map(|h| h.front().map(|s| s.timestamp))
+                .min();
+            if let Some(oldest) = oldest {
+                while self.markers.front().is_some_and(|m| m.timestamp < oldest) {
+                    self.markers.pop_front();
+                }
+            }
+        }
+
+        /// Records what the reader is connected to; None once it disconnects.
+        pub fn set_connection(&mut self, description: Option<String>) {
+            self.connection = description;
+            self.connection_id += 1;
+            self.capabilities = None;
+            self.connected_at = Instant::now();
+            self.reads.clear();
+            self.read_window_bytes = 0;
+        }
+
+        pub fn connection_id(&self) -> u64 {
+            self.connection_id
+        }
+
+        /// Stores what the device on `connection_id` reported, turning off channels it lacks.
+        ///
+        /// Returns false, storing nothing, if that connection has since been replaced.
+        pub fn set_capabilities(&mut self, connection_id: u64, capabilities: DeviceCapabilities) -> bool {
+            if connection_id != self.connection_id {
+                return false;
+            }
+            self.capabilities = Some(capabilities);
+            self.set_active_channels(self.active_channels);
+            true
+        }
+
+        pub fn capabilities(&self) -> Option<DeviceCapabilities> {
+            self.capabilities.clone()
+        }
+
+        /// Counts bytes read from the source towards `get_throughput`.
+        pub fn record_read(&mut self, bytes: usize) {
+            let now = Instant::now();
+            if bytes > 0 {
+                self.reads.push_back((now, bytes));
+                self.read_window_bytes += bytes;
+            }
+            self.expire_reads(now);
+        }
+
+        fn expire_reads(&mut self, now: Instant) {
+            while let Some(&(at, bytes)) = self.reads.front() {
+                if now.duration_since(at) <= THROUGHPUT_WINDOW {
+                    break;
+                }
+                self.reads.pop_front();
+                self.read_window_bytes -= bytes;
+            }
+        }
+
+        pub fn get_throughput(&mut self) -> Throughput {
+            let now = Instant::now();
+            self.expire_reads(now);
+            // Right after connecting the window hasn't filled yet
+            let span = now.duration_since(self.connected_at).min(THROUGHPUT_WINDOW).as_secs_f32();
+            let bytes_per_sec = if span > 0.0 { self.read_window_bytes as f32 / span } else { 0.0 };
+            let link_bytes_per_sec = match self.transport {
+                crate::transport::Transport::Serial { .. } => Some((self.baud_rate / BITS_PER_SERIAL_BYTE) as f32),
+                _ => None,
+            };
+            Throughput {
+                bytes_per_sec,
+                link_bytes_per_sec,
+                utilization: link_bytes_per_sec.map(|link| bytes_per_sec / link),
+            }
+        }
+
+        /// Sets the per-channel rate the firmware should deliver; None falls back to the
+        /// mock's configured rate, or leaves the expectation unknown.
+        pub fn set_expected_sample_rate(&mut self, rate: Option<f32>) -> Result<(), String> {
+            if rate.is_some_and(|r| !r.is_finite() || r <= 0.0) {
+                return Err(format!("Expected sample rate must be positive, got {:?}", rate));
+            }
+            self.expected_sample_rate = rate;
+            Ok(())
+        }
+
+        /// How far the measured sample rate falls short of the expected one.
+        ///
+        /// Alongside, the link's ceiling and utilization tell a saturated serial link
+        /// apart from a host that can't keep up.
+        pub fn get_rate_health(&mut self) -> RateHealth {
+            let expected_hz = self.expected_sample_rate.or(match &self.transport {
+                crate::transport::Transport::Mock(params) => Some(params.sample_rate as f32),
+                _ => None,
+            });
+            let measured_hz = self.measured_sample_rate();
+            let throughput = self.get_throughput();
+            let active = self.active_channels.iter().filter(|&&on| on).count().max(1);
+            RateHealth {
+                expected_hz,
+                measured_hz,
+                link_limit_hz: throughput.link_bytes_per_sec.map(|link| link / (MIN_PACKET_LEN * active) as f32),
+                link_utilization: throughput.utilization,
+                discrepancy_pct: expected_hz.zip(measured_hz)
+                    .map(|(expected, measured)| (expected - measured) / expected * 100.0),
+            }
+        }
+
+        pub fn set_last_error(&mut self, error: String) {
+            self.last_error = Some(error);
+        }
+
+        pub fn get_diagnostics_report(&self) -> DiagnosticsReport {
+            let bytes_discarded = self.total_discarded + self.window_discarded;
+            DiagnosticsReport {
+                connection: self.connection.clone(),
+                last_error: self.last_error.clone(),
+                samples_received: self.samples_received,
+                channel_counts: self.channel_counts,
+                sample_rates: std::array::from_fn(|ch| self.history_rate(ch)),
+                bytes_received: self.total_received,
+                bytes_discarded,
+                parse_error_rate: if self.total_received > 0 {
+                    bytes_discarded as f32 / self.total_received as f32
+                } else {
+                    0.0
+                },
+                resync: self.get_resync_stats(),
+                buffer: self.get_buffer_stats(),
+                config: self.get_full_config(),
+            }
+        }
+
+        pub fn set_calibration(&mut self, channel: u8, calibration: Calibration) -> Result<(), String> {
+            if channel > 3 {
+                return Err(format!("Invalid channel: {}", channel));
+            }
+            calibration.validate(channel as usize)?;
+            self.calibration[channel as usize] = calibration;
+            self.refresh_trigger_codes();
+            Ok(())
+        }
+
+        /// Moves the channel's calibration offset to cancel its current mean, so a
+        /// grounded input reads zero, and returns the new offset.
+        ///
+        /// Needs DC coupling, since AC coupling already hides the mean.
+        pub fn calibrate_zero(&mut self, channel: u8) -> Result<f32, String> {
+            if channel > 3 {
+                return Err(format!("Invalid channel: {}", channel));
+            }
+            let ch = channel as usize;
+            if matches!(self.coupling[ch], Coupling::Ac) {
+                return Err(format!("Channel {} must be DC coupled to calibrate zero", channel));
+            }
+            let history = &self.history[ch];
+            if history.is_empty() {
+                return Err(format!("No samples on channel {} to calibrate from", channel));
+            }
+            let mean = history.iter().map(|s| s.voltage).sum::<f32>() / history.len() as f32;
+
+            let offset = self.calibration[ch].offset - mean;
+            self.calibration[ch].offset = offset;
+            self.refresh_trigger_codes();
+            self.dc_estimate[ch] = None;
+            // Old samples carry the old offset; measuring them again would subtract the mean twice
+            self.history[ch].clear();
+            if self.differential.channel_a == channel || self.differential.channel_b == channel {
+                self.diff_history.clear();
+            }
+            Ok(offset)
+        }
+
+        pub fn get_calibration(&self) -> [Calibration; 4] {
+            self.calibration
+        }
+
+        /// Maps the channel's raw codes through `table` instead of its calibration;
+        /// None goes back to the calibration.
+        pub fn set_channel_lut(&mut self, channel: u8, table: Option<&[(u16, f32)]>) -> Result<(), String> {
+            if channel > 3 {
+                return Err(format!("Invalid channel: {}", channel));
+            }
+            self.luts[channel as usize] = table.map(|t| Lut::new(t, self.signed_samples)).transpose()?;
+            self.refresh_trigger_codes();
+            Ok(())
+        }
+
+        pub fn channel_lut(&self, channel: u8) -> Option<Vec<(u16, f32)>> {
+            self.luts.get(channel as usize)?.as_ref().map(Lut::table)
+        }
+
+        pub fn set_baud_rate(&mut self, baud_rate: u32) -> Result<(), String> {
+            if baud_rate == 0 {
+                return Err("Baud rate must be non-zero".to_string());
+            }
+            self.baud_rate = baud_rate;
+            Ok(())
+        }
+
+        /// Baud rate the reader should open the port at; it reconnects when this changes.
+        pub fn baud_rate(&self) -> u32 {
+            self.baud_rate
+        }
+
+        pub fn set_transport(&mut self, transport: crate::transport::Transport) -> Result<(), String> {
+            validate_transport(&transport)?;
+            self.transport = transport;
+            Ok(())
+        }
+
+        /// Source the reader should connect to; it reconnects when this changes.
+        pub fn transport(&self) -> &crate::transport::Transport {
+            &self.transport
+        }
+
+        /// Start the mock device when the serial transport finds no ports.
+        pub fn set_mock_fallback(&mut self, enabled: bool) {
+            self.mock_fallback = enabled;
+        }
+
+        pub fn mock_fallback(&self) -> bool {
+            self.mock_fallback
+        }
+
+        pub fn set_retry_policy(&mut self, policy: crate::transport::RetryPolicy) -> Result<(), String> {
+            policy.validate()?;
+            self.retry_policy = policy;
+            Ok(())
+        }
+
+        pub fn retry_policy(&self) -> crate::transport::RetryPolicy {
+            self.retry_policy
+        }
+
+        /// Validates an entire configuration and applies it in one step.
+        ///
+        /// Every problem found is reported together and nothing is changed unless
+        /// the whole configuration is valid, so callers never see a half-applied preset.
+        pub fn apply_config(&mut self, config: FullConfig) -> Result<(), String> {
+            let mut errors = Vec::new();
+
+            let active = config.active_channels.iter().filter(|&&x| x).count();
+            if active > 2 {
+                errors.push(format!("At most 2 channels can be active, got {}", active));
+            }
+            if let Err(e) = validate_trigger(&config.trigger, config.signed_samples, &config.calibration, &self.luts) {
+                errors.push(e);
+            }
+            for (channel, cal) in config.calibration.iter().enumerate() {
+                if let Err(e) = cal.validate(channel) {
+                    errors.push(e);
+                }
+            }
+            if config.baud_rate == 0 {
+                errors.push("Baud rate must be non-zero".to_string());
+            }
+            if let Err(e) = validate_differential(&config.differential) {
+                errors.push(e);
+            }
+            if let Err(e) = validate_transport(&config.transport) {
+                errors.push(e);
+            }
+            for &volts in &config.deadband {
+                if let Err(e) = validate_deadband(volts) {
+                    errors.push(e);
+                }
+            }
+            for unit in &config.units {
+                if let Err(e) = unit.validate() {
+                    errors.push(e);
+                }
+            }
+            if let Some(Err(e)) = config.expected_channel_order.as_deref().map(validate_channel_order) {
+                errors.push(e);
+            }
+            if let Err(e) = validate_measurement_gate(config.measurement_gate) {
+                errors.push(e);
+            }
+            for &volts in &config.scope_view.volts_per_div {
+                if let Err(e) = validate_volts_per_div(volts) {
+                    errors.push(e);
+                }
+            }
+
+            if !errors.is_empty() {
+                return Err(format!("Invalid configuration: {}", errors.join("; ")));
+            }
+
+            self.set_active_channels(config.active_channels);
+            self.calibration = config.calibration;
+            self.baud_rate = config.baud_rate;
+            if config.differential.enabled != self.differential.enabled
+                || config.differential.channel_a != self.differential.channel_a
+                || config.differential.channel_b != self.differential.channel_b
+            {
+                self.differential = config.differential;
+                self.diff_history.clear();
+            }
+            self.coupling = config.coupling;
+            self.transport = config.transport;
+            self.skew_ns = config.skew_ns;
+            self.deadband = config.deadband;
+            self.last_displayed = [None; 4];
+            self.set_signed_samples(config.signed_samples);
+            self.units = config.units;
+            self.measurement_gate = config.measurement_gate;
+            self.scope_view = config.scope_view;
+            if config.expected_channel_order != self.channel_order {
+                self.set_expected_channel_order(config.expected_channel_order)?;
+            }
+            self.configure_trigger(config.trigger)?;
+            Ok(())
+        }
+
+        pub fn get_full_config(&self) -> FullConfig {
+            FullConfig {
+                active_channels: self.active_channels,
+                trigger: self.trigger_config.clone(),
+                calibration: self.calibration,
+                baud_rate: self.baud_rate,
+                differential: self.differential.clone(),
+                coupling: self.coupling,
+                transport: self.transport.clone(),
+                skew_ns: self.skew_ns,
+                deadband: self.deadband,
+                signed_samples: self.signed_samples,
+                units: self.units.clone(),
+                expected_channel_order: self.channel_order.clone(),
+                measurement_gate: self.measurement_gate,
+                scope_view: self.scope_view,
+            }
+        }
+
+        pub fn scope_view(&self) -> ScopeView {
+            self.scope_view
+        }
+
+        pub fn set_volts_per_div(&mut self, channel: u8, volts_per_div: f32) -> Result<(), String> {
+            if channel > 3 {
+                return Err(format!("Invalid channel: {}", channel));
+            }
+            validate_volts_per_div(volts_per_div)?;
+            self.scope_view.volts_per_div[channel as usize] = volts_per_div;
+            Ok(())
+        }
+
+        /// Magnifies a channel's displayed trace; `DIFF_CHANNEL` sets the differential trace's.
+        pub fn set_display_gain(&mut self, channel: u8, factor: f32) -> Result<(), String> {
+            let slot = self.display_gain.get_mut(channel as usize)
+                .ok_or_else(|| format!("Invalid channel: {}", channel))?;
+            if !factor.is_finite() || factor <= 0.0 {
+                return Err(format!("Display gain must be positive, got {}", factor));
+            }
+            *slot = factor;
+            Ok(())
+        }
+
+        pub fn display_gain(&self) -> [f32; 5] {
+            self.display_gain
+        }
+
+        /// Multiplies display samples by their channel's gain, clamping at the screen edge.
+        ///
+        /// Returns which samples were clamped. Channels at unity gain pass through
+        /// untouched, so they're never reported as clipped.
+        pub fn apply_display_gain(&self, samples: &mut [AdcSample]) -> Vec<bool> {
+            samples.iter_mut()
+                .map(|sample| {
+                    let gain = self.display_gain[sample.channel as usize];
+                    if gain == 1.0 {
+                        return false;
+                    }
+                    // The differential trace is drawn in channel_a's slot, in volts
+                    let edge = if sample.channel == DIFF_CHANNEL {
+                        VERTICAL_DIVISIONS / 2.0 * self.scope_view.volts_per_div[self.differential.channel_a as usize]
+                    } else {
+                        VERTICAL_DIVISIONS / 2.0 * self.scope_view.volts_per_div[sample.channel as usize]
+                            / self.unit_factor(sample.channel)
+                    };
+                    let gained = sample.voltage * gain;
+                    sample.voltage = gained.clamp(-edge, edge);
+                    gained.abs() > edge
+                })
+                .collect()
+        }
+
+        pub fn set_voltage_alarm(&mut self, channel: u8, alarm: Option<VoltageAlarm>) -> Result<(), String> {
+            if channel > 3 {
+                return Err(format!("Invalid channel: {}", channel));
+            }
+            if alarm.is_some_and(|a| !a.threshold.is_finite()) {
+                return Err("Alarm threshold must be finite".to_string());
+            }
+            self.alarms[channel as usize] = alarm;
+            self.alarm_tripped[channel as usize] = false;
+            Ok(())
+        }
+
+        /// Checks a sample against its channel's alarm.
+        ///
+        /// Fires only when the signal crosses into the alarm region, and at most
+        /// once per `ALARM_MIN_INTERVAL` per channel so a chattering signal doesn't spam.
+        pub fn check_alarm(&mut self, sample: &AdcSample) -> Option<AlarmEvent> {
+            let ch = sample.channel as usize;
+            let alarm = (*self.alarms.get(ch)?)?;
+
+            let violating = if alarm.above {
+                sample.voltage > alarm.threshold
+            } else {
+                sample.voltage < alarm.threshold
+            };
+            let crossed = violating && !self.alarm_tripped[ch];
+            self.alarm_tripped[ch] = violating;
+
+            let rate_limited = self.alarm_last_fired[ch].is_some_and(|t| t.elapsed() < ALARM_MIN_INTERVAL);
+            if !crossed || rate_limited {
+                return None;
+            }
+
+            self.alarm_last_fired[ch] = Some(Instant::now());
+            Some(AlarmEvent {
+                channel: sample.channel,
+                value: sample.voltage,
+                threshold: alarm.threshold,
+                timestamp: sample.timestamp,
+            })
+        }
+
+        /// Routes one channel around the display buffer for minimum latency.
+        ///
+        /// Its newest sample from every port read goes straight out on the
+        /// `low-latency-sample` event. That means an event per read (hundreds per
+        /// second) instead of the polled batch, so use it for one channel at a time.
+        pub fn set_low_latency_channel(&mut self, channel: Option<u8>) -> Result<(), String> {
+            if let Some(ch) = channel.filter(|&ch| ch > 3) {
+                return Err(format!("Invalid channel: {}", ch));
+            }
+            self.low_latency_channel = channel;
+            Ok(())
+        }
+
+        pub fn low_latency_channel(&self) -> Option<u8> {
+            self.low_latency_channel
+        }
+
+        /// Window used by spectral measurements when a call doesn't pick one.
+        pub fn set_fft_window(&mut self, window: crate::analysis::WindowFn) {
+            self.fft_window = window;
+        }
+
+        pub fn fft_window(&self) -> crate::analysis::WindowFn {
+            self.fft_window
+        }
+
+        /// Stores or, with None, forgets the background spectrum subtracted from a channel's FFTs.
+        pub fn set_noise_floor(&mut self, channel: u8, spectrum: Option<crate::analysis::Spectrum>) -> Result<(), String> {
+            let slot = self.noise_floors.get_mut(channel as usize)
+                .ok_or_else(|| format!("Invalid channel: {}", channel))?;
+            *slot = spectrum;
+            Ok(())
+        }
+
+        pub fn noise_floor(&self, channel: u8) -> Option<&crate::analysis::Spectrum> {
+            self.noise_floors.get(channel as usize)?.as_ref()
+        }
+
+        /// Restricts measurements to a fraction of the triggered capture (see
+        /// `capture_span`), like a scope's gate.
+        pub fn set_measurement_gate(&mut self, start: f32, end: f32) -> Result<(), String> {
+            validate_measurement_gate((start, end))?;
+            self.measurement_gate = (start, end);
+            Ok(())
+        }
+
+        pub fn measurement_gate(&self) -> (f32, f32) {
+            self.measurement_gate
+        }
+
+        /// Timestamps the measurement gate is a fraction of, inclusive.
+        ///
+        /// That's the completed single-shot capture when there is one, else everything
+        /// since the newest trigger edge. `None` (trigger off, or no edge buffered yet)
+        /// gates the whole history instead.
+        pub fn capture_span(&self) -> Option<(u128, u128)> {
+            if self.capture_length.is_some() && self.get_capture_progress() >= 1.0 {
+                if let (Some(first), Some(last)) = (self.single_capture.first(), self.single_capture.last()) {
+                    return Some((first.timestamp, last.timestamp));
+                }
+            }
+            if !self.trigger_config.armed() {
+                return None;
+            }
+            // Level triggering marks every sample past the level; only the first of each run is an edge
+            (0..4u8)
+                .filter(|&ch| self.trigger_config.fires_on(ch))
+                .filter_map(|ch| {
+                    let history = &self.history[ch as usize];
+                    history.iter()
+                        .zip(history.iter().skip(1))
+                        .rev()
+                        .find(|(before, s)| s.is_trigger && !before.is_trigger)
+                        .map(|(_, s)| s.timestamp)
+                })
+                .max()
+                .map(|edge| (edge, u128::MAX))
+        }
+
+        /// Closes the resync window once it has run its length.
+        ///
+        /// Returns the fresh stats when a window completed, `None` otherwise.
+        pub fn update_resync_stats(&mut self) -> Option<ResyncStats> {
+            let elapsed = self.window_start.elapsed();
+            if elapsed < RESYNC_WINDOW {
+                return None;
+            }
+
+            self.total_discarded += self.window_discarded;
+            self.resync_stats = ResyncStats {
+                total_discarded: self.total_discarded,
+                discarded_per_sec: self.window_discarded as f32 / elapsed.as_secs_f32(),
+                discard_ratio: if self.window_received > 0 {
+                    self.window_discarded as f32 / self.window_received as f32
+                } else {
+                    0.0
+                },
+                misalignment_corrections: self.misalignment_corrections,
+                interleave_corrections: self.interleave_corrections,
+                packet_timeouts: self.packet_timeouts,
+            };
+
+            self.window_start = Instant::now();
+            self.window_received = 0;
+            self.window_discarded = 0;
+            Some(self.resync_stats.clone())
+        }
+
+        /// Distribution of the sizes of recently decoded sample packets.
+        ///
+        /// Six-byte packets are the ones carrying a trigger marker.
+        pub fn packet_sizes(&self) -> PacketSizes {
+            let mut counts: Vec<(usize, usize)> = Vec::new();
+            for &size in &self.packet_sizes {
+                match counts.iter_mut().find(|(s, _)| *s == size as usize) {
+                    Some((_, count)) => *count += 1,
+                    None => counts.push((size as usize, 1)),
+                }
+            }
+            counts.sort_unstable();
+            PacketSizes {
+                packets: self.packet_sizes.len(),
+                most_common: counts.iter().max_by_key(|&&(_, count)| count).map(|&(size, _)| size),
+                counts,
+            }
+        }
+
+        pub fn get_resync_stats(&self) -> ResyncStats {
+            ResyncStats {
+                misalignment_corrections: self.misalignment_corrections,
+                interleave_corrections: self.interleave_corrections,
+                packet_timeouts: self.packet_timeouts,
+                ..self.resync_stats.clone()
+            }
+        }
+
+        /// Most recent samples for a channel, oldest first. Empty for invalid channels.
+        ///
+        /// `DIFF_CHANNEL` returns the differential trace while that mode is enabled.
+        pub fn channel_samples(&self, channel: u8) -> Vec<AdcSample> {
+            if channel == DIFF_CHANNEL {
+                return self.diff_history.iter().cloned().collect();
+            }
+            self.history
+                .get(channel as usize)
+                .map(|h| h.iter().cloned().collect())
+                .unwrap_or_default()
+        }
+
+        pub fn get_active_channels(&self) -> [bool; 4] {
+            self.active_channels
+        }
+
+        /// Newest buffered sample of each active channel, in its display unit.
+        pub fn latest_samples(&self) -> [Option<AdcSample>; 4] {
+            std::array::from_fn(|ch| {
+                let mut sample = self.history[ch].back().filter(|_| self.active_channels[ch])?.clone();
+                sample.voltage *= self.units[ch].factor;
+                Some(sample)
+            })
+        }
+
+        pub fn get_trigger_config(&self) -> TriggerConfig {
+            self.trigger_config.clone()
+        }
+
+        /// Whether a trigger fired within the last `TRIGGER_RECENT_WINDOW`, and where.
+        pub fn get_trigger_status(&self) -> TriggerStatus {
+            TriggerStatus {
+                recently_fired: self.last_trigger
+                    .as_ref()
+                    .is_some_and(|(at, _)| at.elapsed() < TRIGGER_RECENT_WINDOW),
+                last_timestamp: self.last_trigger.as_ref().map(|(_, info)| info.timestamp),
+                last_channel: self.last_trigger.as_ref().map(|(_, info)| info.channel),
+            }
+        }
+
+        pub fn last_trigger_info(&self) -> Option<TriggerInfo> {
+            self.last_trigger.as_ref().map(|(_, info)| info.clone())
+        }
+    }
+
+    fn validate_differential(config: &DifferentialConfig) -> Result<(), String> {
+        if config.channel_a > 3 || config.channel_b > 3 || config.channel_a == config.channel_b {
+            return Err(format!(
+                "Differential mode needs two distinct channels, got {} and {}",
+                config.channel_a, config.channel_b
+            ));
+        }
+        Ok(())
+    }
+
+    fn validate_transport(transport: &crate::transport::Transport) -> Result<(), String> {
+        match transport {
+            crate::transport::Transport::Serial { .. } => Ok(()),
+            crate::transport::Transport::Tcp { address } => {
+                if address.rsplit_once(':').is_none_or(|(host, port)| host.is_empty() || port.parse::<u16>().is_err()) {
+                    return Err(format!("TCP address must be host:port, got '{}'", address));
+                }
+                Ok(())
+            }
+            crate::transport::Transport::Mock(params) => params.validate(),
+        }
+    }
+
+    fn validate_channel_order(order: &[u8]) -> Result<(), String> {
+        if order.is_empty() {
+            return Err("Channel order must list at least one channel".to_string());
+        }
+        for (i, &channel) in order.iter().enumerate() {
+            if channel > 3 {
+                return Err(format!("Invalid channel in order: {}", channel));
+            }
+            if order[..i].contains(&channel) {
+                return Err(format!("Channel {} appears more than once in the order", channel));
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_measurement_gate((start, end): (f32, f32)) -> Result<(), String> {
+        if !(0.0..=1.0).contains(&start) || !(0.0..=1.0).contains(&end) || start >= end {
+            return Err(format!("Measurement gate {}..{} must satisfy 0 <= start < end <= 1", start, end));
+        }
+        Ok(())
+    }
+
+    fn validate_volts_per_div(volts: f32) -> Result<(), String> {
+        if !volts.is_finite() || volts <= 0.0 {
+            return Err(format!("Volts/div must be positive, got {}", volts));
+        }
+        Ok(())
+    }
+
+    fn validate_deadband(volts: f32) -> Result<(), String> {
+        if !volts.is_finite() || volts < 0.0 {
+            return Err(format!("Deadband must be a non-negative voltage, got {}", volts));
+        }
+        Ok(())
+    }
+
+    /// Lowest and highest voltage a channel can decode to.
+    fn voltage_range(signed: bool, calibration: &Calibration, lut: Option<&Lut>) -> (f32, f32) {
+        if let Some(lut) = lut {
+            return lut.range();
+        }
+        let (lo, hi) = if signed { (i16::MIN as u16, i16::MAX as u16) } else { (0, u16::MAX) };
+        let (a, b) = (calibration.apply(code_to_volts(lo, signed)), calibration.apply(code_to_volts(hi, signed)));
+        (a.min(b), a.max(b))
+    }
+
+    /// Checks levels against what each channel can actually read with this signedness
+    /// and calibration (unsigned codes reach 0..20 V, for instance).
+    fn validate_trigger(
+        config: &TriggerConfig,
+        signed: bool,
+        calibration: &[Calibration; 4],
+        luts: &[Option<Lut>; 4],
+    ) -> Result<(), String> {
+        if config.channel > 3 {
+            return Err(format!("Invalid trigger channel: {}", config.channel));
+        }
+        let in_range = |channel: usize, volts: f32| {
+            let (lo, hi) = voltage_range(signed, &calibration[channel], luts[channel].as_ref());
+            if volts.is_finite() && (lo..=hi).contains(&volts) {
+                Ok(())
+            } else {
+                Err((lo, hi))
+            }
+        };
+        if let Err((lo, hi)) = in_range(config.channel as usize, config.level) {
+            return Err(format!("Trigger level {} V is outside {} to {} V", config.level, lo, hi));
+        }
+        for (channel, trigger) in config.channel_triggers.iter().enumerate().filter(|(_, t)| t.enabled) {
+            if let Err((lo, hi)) = in_range(channel, trigger.level) {
+                return Err(format!("Channel {} trigger level {} V is outside {} to {} V", channel + 1, trigger.level, lo, hi));
+            }
+        }
+        if let Some(pattern) = &config.pattern {
+            if pattern.conditions.iter().all(|&c| c == PatternLevel::DontCare) {
+                return Err("Pattern trigger needs at least one channel condition".to_string());
+            }
+            for (channel, &t) in pattern.thresholds.iter().enumerate() {
+                if let Err((lo, hi)) = in_range(channel, t) {
+                    return Err(format!("Pattern threshold {} V is outside {} to {} V", t, lo, hi));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Extremes of one channel within one display frame.
+    struct Envelope {
+        bucket: u128,
+        min: AdcSample,
+        max: AdcSample,
+    }
+
+    impl Envelope {
+        fn new(bucket: u128, sample: AdcSample) -> Self {
+            Self { bucket, min: sample.clone(), max: sample }
+        }
+
+        fn include(&mut self, sample: AdcSample) {
+            if sample.voltage < self.min.voltage {
+                self.min = sample;
+            } else if sample.voltage > self.max.voltage {
+                self.max = sample;
+            }
+        }
+
+        /// Emits the extremes in time order, once if they're the same sample.
+        fn flush_into(self, out: &mut Vec<AdcSample>) {
+            let (first, second) = if self.min.timestamp <= self.max.timestamp {
+                (self.min, self.max)
+            } else {
+                (self.max, self.min)
+            };
+            let same = first.timestamp == second.timestamp && first.voltage == second.voltage;
+            out.push(first);
+            if !same {
+                out.push(second);
+            }
+        }
+    }
+
+    /// Shifts a timestamp by a signed skew, saturating at zero.
+    fn apply_skew(timestamp: u128, skew_ns: i64) -> u128 {
+        if skew_ns >= 0 {
+            timestamp + skew_ns as u128
+        } else {
+            timestamp.saturating_sub(skew_ns.unsigned_abs() as u128)
+        }
+    }
+
+    pub(crate) fn now_nanos() -> u128 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or(Duration::from_secs(0))
+            .as_nanos()
+    }
+
+    fn push_bounded(history: &mut VecDeque<AdcSample>, sample: AdcSample) {
+        if history.len() >= CHANNEL_HISTORY_LEN {
+            history.pop_front();
+        }
+        history.push_back(sample);
+    }
+
+    impl Default for PacketProcessor {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+}
+
+pub mod analysis {
+    use std::f32::consts::PI;
+    use serde::{Serialize, Deserialize};
+    use crate::adc::AdcSample;
+
+    pub const MAX_FFT_SIZE: usize = 4096;
+    pub const THD_HARMONICS: usize = 9;  // 2nd through 10th harmonic
+    pub const MIN_SWEEP_BINS: usize = 3;  // Distinct frequencies a group delay sweep must cover
+    const CROSSING_HYSTERESIS: f32 = 0.1;  // Fraction of peak-to-peak a crossing must re-arm by
+    const MIN_JITTER_PERIODS: usize = 3;
+    const AUTOCORRELATION_PEAK: f32 = 0.5;  // A repeat must correlate at least this well
+    const MIN_PEAK_TO_FLOOR: f32 = 100.0;  // Peak power vs median bin power (20 dB)
+    const NOISE_FLOOR_BIN_TOLERANCE: f32 = 0.01;  // Sample rate drift a stored noise floor tolerates
+
+    /// Why a measurement result shouldn't be trusted.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum MeasurementWarning {
+        Clipping,          // A sample in the measurement window sat at an ADC rail
+        InsufficientData,  // Too few samples, or not the signal shape the measurement needs
+    }
+
+    /// A measurement with the warnings raised while taking it; `valid` is true without any.
+    #[derive(Debug, Clone, Serialize)]
+    pub struct Checked<T> {
+        pub value: Option<T>,
+        pub valid: bool,
+        pub warnings: Vec<MeasurementWarning>,
+    }
+
+    impl<T> Checked<T> {
+        /// A missing value is insufficient data; `clipped` says the window hit a rail.
+        pub fn new(value: Option<T>, clipped: bool) -> Self {
+            let mut warnings = Vec::new();
+            if clipped {
+                warnings.push(MeasurementWarning::Clipping);
+            }
+            if value.is_none() {
+                warnings.push(MeasurementWarning::InsufficientData);
+            }
+            Self { value, valid: warnings.is_empty(), warnings }
+        }
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct ThdMeasurement {
+        pub fundamental_hz: f32,
+        pub thd_percent: f32,
+        pub thd_db: f32,
+        pub harmonics: usize,  // Harmonics below Nyquist that were included
+    }
+
+    #[derive(Debug, Clone, Serialize)]
+    pub struct EnobMeasurement {
+        pub fundamental_hz: f32,
+        pub sinad_db: f32,
+        pub enob: f32,
+    }
+
+    #[derive(Debug, Clone, Serialize)]
+    pub struct Spectrum {
+        pub bin_hz: f32,  // Frequency spacing; magnitudes[k] is at k * bin_hz
+        pub magnitudes: Vec<f32>,
+        pub window: WindowFn,
+    }
+
+    /// Delay through the system under test at one frequency of a sweep.
+    #[derive(Debug, Clone, Copy, Serialize)]
+    pub struct GroupDelayPoint {
+        pub frequency_hz: f32,
+        pub delay_s: f32,
+    }
+
+    /// Overlapping spectra over time, oldest frame first; `frames[t][k]` is at `k * bin_hz`.
+    #[derive(Debug, Clone, Serialize)]
+    pub struct Spectrogram {
+        pub bin_hz: f32,
+        pub hop_s: f32,  // Time between the starts of consecutive frames
+        pub fft_size: usize,
+        pub frames: Vec<Vec<f32>>,
+        pub window: WindowFn,
+    }
+
+    #[derive(Debug, Clone, Serialize)]
+    pub struct CrossCorrelation {
+        pub values: Vec<f32>,  // values[i] is the correlation at lag i - max_lag
+        pub max_lag: usize,
+        pub peak_lag: i64,     // Positive when the second channel lags the first
+        pub peak_value: f32,
+        pub delay_s: Option<f32>,
+    }
+
+    #[derive(Debug, Clone, Serialize)]
+    pub struct Autocorrelation {
+        pub values: Vec<f32>,  // values[lag] for lags 0..=max_lag; values[0] is 1
+        pub peak_lag: Option<usize>,  // First strong repeat of the signal
+        pub period_s: Option<f32>,    // From the peak, interpolated between lags
+    }
+
+    /// One point of a trace computed from a channel (derivative, envelope, ...).
+    #[derive(Debug, Clone, Serialize)]
+    pub struct TracePoint {
+        pub timestamp: u128,
+        pub value: f32,
+    }
+
+    /// Area under a channel between two timestamps.
+    #[derive(Debug, Clone, Serialize)]
+    pub struct Integral {
+        pub area: f32,       // Channel unit times seconds: V·s, or A·s for a current probe
+        pub start_ts: u128,  // Actual span covered, clipped to the buffered samples
+        pub end_ts: u128,
+        pub samples: usize,  // Buffered samples strictly inside the span
+    }
+
+    /// How long a step response took to stay inside its tolerance band.
+    #[derive(Debug, Clone, Serialize)]
+    pub struct SettlingTime {
+        pub settling_time_s: f32,
+        pub step_ts: u128,     // Where the signal crossed half the step
+        pub settled_ts: u128,  // First sample after which it never left the band
+        pub initial: f32,
+        pub final_value: f32,
+    }
+
+    /// A frozen copy of a channel for overlaying live data against.
+    #[derive(Debug, Clone, Serialize)]
+    pub struct ReferenceTrace {
+        pub channel: u8,
+        pub captured_at: u128,
+        pub points: Vec<TracePoint>,  // Timestamps are ns after the first point
+    }
+
+    /// Spread of successive periods of a repetitive signal.
+    #[derive(Debug, Clone, Serialize)]
+    pub struct Jitter {
+        pub mean_period_s: f64,
+        pub std_dev_s: f64,
+        pub peak_to_peak_s: f64,  // Longest minus shortest period
+        pub periods: usize,
+    }
+
+    /// Average high and low times of a pulse train.
+    #[derive(Debug, Clone, Serialize)]
+    pub struct PulseWidths {
+        pub positive_s: f64,  // Rising crossing to the next falling one
+        pub negative_s: f64,  // Falling crossing to the next rising one
+        pub positive_pulses: usize,
+        pub negative_pulses: usize,
+    }
+
+    /// AC component riding on a DC level, in the channel's unit.
+    #[derive(Debug, Clone, Serialize)]
+    pub struct Ripple {
+        pub dc: f32,   // Mean over the window, which is removed before the other two
+        pub vpp: f32,
+        pub rms: f32,
+    }
+
+    /// Values on a uniform time grid; `values[i]` is at `start_ts + i / rate` seconds.
+    #[derive(Debug, Clone, Serialize)]
+    pub struct Resampled {
+        pub start_ts: u128,
+        pub rate: f32,
+        pub values: Vec<f32>,
+    }
+
+    /// A window of samples around a trigger.
+    #[derive(Debug, Clone, Serialize)]
+    pub struct Capture {
+        pub samples: Vec<AdcSample>,
+        pub trigger_index: usize,  // Index of the trigger sample, always the requested pre-trigger length
+        pub trigger_ts: u128,
+        pub decimation: usize,
+    }
+
+    /// Decimated capture of `length` points with the trigger sample at index `pre_trigger`.
+    ///
+    /// Decimation keeps every `decimation`th sample counted outward from the first
+    /// sample at or after `trigger_ts`, so the trigger is never the one dropped and
+    /// repeated captures overlay without a phase shift. Returns `None` unless the
+    /// samples cover the whole window.
+    pub fn capture(samples: &[AdcSample], trigger_ts: u128, length: usize, pre_trigger: usize, decimation: usize) -> Option<Capture> {
+        if pre_trigger >= length || decimation == 0 {
+            return None;
+        }
+        let anchor = samples.partition_point(|s| s.timestamp < trigger_ts);
+        let first = anchor.checked_sub(pre_trigger.checked_mul(decimation)?)?;
+        let last = anchor.checked_add((length - pre_trigger - 1).checked_mul(decimation)?)?;
+        if last >= samples.len() {
+            return None;
+        }
+        Some(Capture {
+            samples: samples[first..=last].iter().step_by(decimation).cloned().collect(),
+            trigger_index: pre_trigger,
+            trigger_ts: samples[anchor].timestamp,
+            decimation,
+        })
+    }
+
+    /// The part of `samples` between two fractions of its length.
+    pub fn gate(samples: &[AdcSample], start: f32, end: f32) -> &[AdcSample] {
+        let n = samples.len() as f32;
+        let first = (start * n).floor() as usize;
+        let last = ((end * n).ceil() as usize).min(samples.len());
+        &samples[first.min(last)..last]
+    }
+
+    /// Average sample rate in Hz derived from the first and last timestamps.
+    pub fn estimate_sample_rate(samples: &[AdcSample]) -> Option<f32> {
+        let (first, last) = (samples.first()?, samples.last()?);
+        let span_ns = last.timestamp.checked_sub(first.timestamp)?;
+        if samples.len() < 2 || span_ns == 0 {
+            return None;
+        }
+        Some((samples.len() - 1) as f32 * 1e9 / span_ns as f32)
+    }
+
+    /// Linearly interpolated voltage at `timestamp`, holding the end values outside the span.
+    ///
+    /// Samples must be in timestamp order, as the per-channel histories are.
+    pub fn sample_at(samples: &[AdcSample], timestamp: u128) -> Option<f32> {
+        let next = samples.partition_point(|s| s.timestamp < timestamp);
+        if next == 0 {
+            return samples.first().map(|s| s.voltage);
+        }
+        let Some(after) = samples.get(next) else {
+            return samples.last().map(|s| s.voltage);
+        };
+        let before = &samples[next - 1];
+
+        let span = (after.timestamp - before.timestamp) as f32;
+        if span == 0.0 {
+            return Some(after.voltage);
+        }
+        let t = (timestamp - before.timestamp) as f32 / span;
+        Some(before.voltage + (after.voltage - before.voltage) * t)
+    }
+
+    /// Up to `count` values at `rate` per second, ending at the newest sample.
+    ///
+    /// Each grid point is linearly interpolated between its two neighbouring samples
+    /// by `sample_at`; that keeps edges where they were and adds no overshoot, at the
+    /// cost of slightly rounding off content near Nyquist. The grid never reaches
+    /// before the first sample, so fewer than `count` values come back when the
+    /// samples don't span that long. `None` without a positive span and rate.
+    pub fn resample(samples: &[AdcSample], count: usize, rate: f32) -> Option<Resampled> {
+        let (first, last) = (samples.first()?.timestamp, samples.last()?.timestamp);
+        if last <= first || !rate.is_finite() || rate <= 0.0 {
+            return None;
+        }
+        let period_ns = 1e9 / rate as f64;
+        let fits = ((last - first) as f64 / period_ns) as usize + 1;
+        let count = count.min(fits);
+        let start_ts = last - (count.saturating_sub(1) as f64 * period_ns).round() as u128;
+        let values = (0..count)
+            .map(|i| {
+                let ts = start_ts + (i as f64 * period_ns).round() as u128;
+                sample_at(samples, ts.min(last))
+            })
+            .collect::<Option<Vec<f32>>>()?;
+        Some(Resampled { start_ts, rate, values })
+    }
+
+    /// Normalized cross-correlation of `a` and `b` for lags in `-max_lag..=max_lag`.
+    ///
+    /// Both signals are truncated to their common (most recent) length and have
+    /// their means removed, so values fall in -1..=1. At lag `k`, `a[i]` is paired
+    /// with `b[i + k]`. `max_lag` is clamped below the common length; `None` is
+    /// returned when either signal is flat or too short.
+    pub fn cross_correlate(a: &[f32], b: &[f32], max_lag: usize) -> Option<CrossCorrelation> {
+        let n = a.len().min(b.len());
+        if n < 2 {
+            return None;
+        }
+        let (a, b) = (&a[a.len() - n..], &b[b.len() - n..]);
+        let max_lag = max_lag.min(n - 1);
+
+        let mean_a = a.iter().sum::<f32>() / n as f32;
+        let mean_b = b.iter().sum::<f32>() / n as f32;
+        let a: Vec<f32> = a.iter().map(|v| v - mean_a).collect();
+        let b: Vec<f32> = b.iter().map(|v| v - mean_b).collect();
+
+        let norm = (a.iter().map(|v| v * v).sum::<f32>() * b.iter().map(|v| v * v).sum::<f32>()).sqrt();
+        if norm == 0.0 {
+            return None;
+        }
+
+        let values: Vec<f32> = (-(max_lag as i64)..=max_lag as i64)
+            .map(|lag| {
+                let (a_start, b_start) = if lag >= 0 { (0, lag as usize) } else { ((-lag) as usize, 0) };
+                let len = n - lag.unsigned_abs() as usize;
+                a[a_start..a_start + len].iter()
+                    .zip(&b[b_start..b_start + len])
+                    .map(|(x, y)| x * y)
+                    .sum::<f32>() / norm
+            })
+            .collect();
+
+        let (peak_index, &peak_value) = values.iter()
+            .enumerate()
+            .max_by(|x, y| x.1.total_cmp(y.1))?;
+
+        Some(CrossCorrelation {
+            peak_lag: peak_index as i64 - max_lag as i64,
+            peak_value,
+            values,
+            max_lag,
+            delay_s: None,
+        })
+    }
+
+    /// Normalized autocorrelation for lags `0..=max_lag` and the period it implies.
+    ///
+    /// The period is the first local maximum above `AUTOCORRELATION_PEAK` after the
+    /// curve has dropped below it, refined by fitting a parabola through the peak
+    /// and its neighbours. Noise is uncorrelated from one sample to the next, so
+    /// this holds up where crossing-based measurements start double counting.
+    /// `None` for flat or too-short signals.
+    pub fn autocorrelate(values: &[f32], max_lag: usize, sample_rate: Option<f32>) -> Option<Autocorrelation> {
+        let full = cross_correlate(values, values, max_lag)?;
+        let values = full.values[full.max_lag..].to_vec();
+
+        let dropped = values.iter().position(|&v| v < AUTOCORRELATION_PEAK);
+        let peak_lag = dropped.and_then(|start| {
+            (start.max(1)..values.len().saturating_sub(1)).find(|&lag| {
+                values[lag] >= AUTOCORRELATION_PEAK && values[lag] >= values[lag - 1] && values[lag] > values[lag + 1]
+            })
+        });
+        let period_s = peak_lag.zip(sample_rate).map(|(lag, rate)| {
+            let (a, b, c) = (values[lag - 1], values[lag], values[lag + 1]);
+            let curvature = a - 2.0 * b + c;
+            let offset = if curvature < 0.0 { 0.5 * (a - c) / curvature } else { 0.0 };
+            (lag as f32 + offset) / rate
+        });
+        Some(Autocorrelation { values, peak_lag, period_s })
+    }
+
+    /// Trapezoidal integral of `voltage` over `start_ts..end_ts`, in its unit times seconds.
+    ///
+    /// The window is clipped to the span the samples cover and its ends are
+    /// interpolated, so a window reaching past the buffer integrates what's there and
+    /// reports the span it actually used. `None` if the window misses the samples.
+    pub fn integrate(samples: &[AdcSample], start_ts: u128, end_ts: u128) -> Option<Integral> {
+        let (first, last) = (samples.first()?, samples.last()?);
+        let lo = start_ts.max(first.timestamp);
+        let hi = end_ts.min(last.timestamp);
+        if lo >= hi {
+            return None;
+        }
+
+        let inside = samples.iter().filter(|s| s.timestamp > lo && s.timestamp < hi);
+        let points = std::iter::once((lo, sample_at(samples, lo)?))
+            .chain(inside.map(|s| (s.timestamp, s.voltage)))
+            .chain(std::iter::once((hi, sample_at(samples, hi)?)));
+
+        let mut area = 0.0f64;
+        let mut count = 0;
+        let mut previous: Option<(u128, f32)> = None;
+        for (t, v) in points {
+            if let Some((pt, pv)) = previous {
+                area += (t - pt) as f64 * 1e-9 * (v + pv) as f64 / 2.0;
+            }
+            previous = Some((t, v));
+            count += 1;
+        }
+
+        Some(Integral {
+            area: area as f32,
+            start_ts: lo,
+            end_ts: hi,
+            samples: count - 2,
+        })
+    }
+
+    /// Average of `voltage * current` over the span both cover, in watts.
+    ///
+    /// Current is interpolated to each voltage sample's timestamp, and the product
+    /// is integrated over time so uneven sample spacing doesn't bias the average.
+    pub fn average_power(voltage: &[AdcSample], current: &[AdcSample]) -> Option<f32> {
+        let lo = voltage.first()?.timestamp.max(current.first()?.timestamp);
+        let hi = voltage.last()?.timestamp.min(current.last()?.timestamp);
+        if lo >= hi {
+            return None;
+        }
+        let power: Vec<AdcSample> = voltage.iter()
+            .filter(|s| (lo..=hi).contains(&s.timestamp))
+            .map(|s| Some(AdcSample { voltage: s.voltage * sample_at(current, s.timestamp)?, ..s.clone() }))
+            .collect::<Option<_>>()?;
+        let energy = integrate(&power, lo, hi)?;
+        Some(energy.area / ((energy.end_ts - energy.start_ts) as f32 * 1e-9))
+    }
+
+    /// Centered moving average over `width` samples; widths below 2 leave values as-is.
+    pub fn moving_average(values: &[f32], width: usize) -> Vec<f32> {
+        if width < 2 {
+            return values.to_vec();
+        }
+        let half = width / 2;
+        (0..values.len())
+            .map(|i| {
+                let window = &values[i.saturating_sub(half)..(i + half + 1).min(values.len())];
+                window.iter().sum::<f32>() / window.len() as f32
+            })
+            .collect()
+    }
+
+    /// Numerical derivative, in the samples' unit per second.
+    ///
+    /// Each point is the slope between two neighbouring samples, placed at their
+    /// midpoint in time. Pairs with identical timestamps (same read batch) are
+    /// skipped rather than producing infinite slopes. `smoothing` applies a moving
+    /// average of that many samples first to tame noise.
+    pub fn derivative(samples: &[AdcSample], smoothing: usize) -> Vec<TracePoint> {
+        let voltages: Vec<f32> = samples.iter().map(|s| s.voltage).collect();
+        let smoothed = moving_average(&voltages, smoothing);
+
+        samples.windows(2)
+            .zip(smoothed.windows(2))
+            .filter(|(pair, _)| pair[1].timestamp > pair[0].timestamp)
+            .map(|(pair, v)| {
+                let dt = (pair[1].timestamp - pair[0].timestamp) as f32 * 1e-9;
+                TracePoint {
+                    timestamp: pair[0].timestamp + (pair[1].timestamp - pair[0].timestamp) / 2,
+                    value: (v[1] - v[0]) / dt,
+                }
+            })
+            .collect()
+    }
+
+    /// Root mean square, including any DC component.
+    pub fn rms(values: &[f32]) -> Option<f32> {
+        if values.is_empty() {
+            return None;
+        }
+        Some((values.iter().map(|v| v * v).sum::<f32>() / values.len() as f32).sqrt())
+    }
+
+    /// Largest absolute value.
+    pub fn peak(values: &[f32]) -> Option<f32> {
+        values.iter().map(|v| v.abs()).reduce(f32::max)
+    }
+
+    /// Peak over RMS; `None` for empty or all-zero input.
+    pub fn crest_factor(values: &[f32]) -> Option<f32> {
+        let rms = rms(values)?;
+        (rms > 0.0).then(|| peak(values).unwrap_or(0.0) / rms)
+    }
+
+    /// Peak-to-peak and RMS of `values` once their mean is removed.
+    pub fn ripple(values: &[f32]) -> Option<Ripple> {
+        if values.len() < 2 {
+            return None;
+        }
+        let dc = values.iter().sum::<f32>() / values.len() as f32;
+        let ac: Vec<f32> = values.iter().map(|v| v - dc).collect();
+        let (min, max) = ac.iter().fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), &v| (lo.min(v), hi.max(v)));
+        Some(Ripple { dc, vpp: max - min, rms: rms(&ac)? })
+    }
+
+    /// Snapshot of `samples` with timestamps made relative to the first one.
+    pub fn reference_trace(channel: u8, samples: &[AdcSample]) -> Option<ReferenceTrace> {
+        let first = samples.first()?;
+        Some(ReferenceTrace {
+            channel,
+            captured_at: first.timestamp,
+            points: samples.iter()
+                .map(|s| TracePoint { timestamp: s.timestamp - first.timestamp, value: s.voltage })
+                .collect(),
+        })
+    }
+
+    /// Live minus reference over the newest stretch of `live` as long as the reference.
+    ///
+    /// Live values are interpolated at each reference point's offset, and the result
+    /// carries live timestamps. `None` until `live` covers the reference's span.
+    pub fn reference_difference(live: &[AdcSample], reference: &ReferenceTrace) -> Option<Vec<TracePoint>> {
+        let span = reference.points.last()?.timestamp;
+        let start = live.last()?.timestamp.checked_sub(span)?;
+        if live.first()?.timestamp > start {
+            return None;
+        }
+        reference.points.iter()
+            .map(|point| {
+                let timestamp = start + point.timestamp;
+                sample_at(live, timestamp).map(|value| TracePoint { timestamp, value: value - point.value })
+            })
+            .collect()
+    }
+
+    /// Times of mid-level crossings as `(ns after the first sample, rising)`.
+    ///
+    /// The level is halfway between the extremes, and after each crossing the signal
+    /// must swing back past it by `CROSSING_HYSTERESIS` of the peak-to-peak before the
+    /// next counts, so noise near the level doesn't add crossings. Times are
+    /// interpolated between the two samples either side of the level.
+    pub fn crossings(samples: &[AdcSample]) -> Vec<(f64, bool)> {
+        crossings_at(samples, None)
+    }
+
+    /// `crossings` of `level`, or of the mid-level when that's `None`.
+    fn crossings_at(samples: &[AdcSample], level: Option<f32>) -> Vec<(f64, bool)> {
+        let Some(first) = samples.first() else {
+            return Vec::new();
+        };
+        let min = samples.iter().map(|s| s.voltage).fold(f32::INFINITY, f32::min);
+        let max = samples.iter().map(|s| s.voltage).fold(f32::NEG_INFINITY, f32::max);
+        if max <= min {
+            return Vec::new();
+        }
+        let level = level.unwrap_or((min + max) / 2.0);
+        let margin = (max - min) * CROSSING_HYSTERESIS;
+
+        let mut crossings = Vec::new();
+        let mut side: Option<bool> = None;  // Whether the signal was last seen past the margin above or below
+        let mut last_mid = None;  // Most recent crossing of the level itself
+        for pair in samples.windows(2) {
+            let (a, b) = (&pair[0], &pair[1]);
+            if (a.voltage < level) != (b.voltage < level) {
+                let t = (level - a.voltage) / (b.voltage - a.voltage);
+                let time = (a.timestamp - first.timestamp) as f64 + t as f64 * (b.timestamp - a.timestamp) as f64;
+                last_mid = Some((time, b.voltage >= level));
+            }
+            let now_above = if b.voltage > level + margin {
+                true
+            } else if b.voltage < level - margin {
+                false
+            } else {
+                continue;
+            };
+            // Only a full swing counts, and it's timed where it passed the level
+            if side == Some(!now_above) {
+                if let Some(crossing) = last_mid.filter(|&(_, rising)| rising == now_above) {
+                    crossings.push(crossing);
+                }
+            }
+            side = Some(now_above);
+        }
+        crossings
+    }
+
+    /// Mean positive and negative pulse widths at `threshold` (default mid-level).
+    ///
+    /// Pulses are timed between `crossings`, so a pulse cut off by either end of
+    /// the window isn't counted. `None` unless there is at least one whole pulse of
+    /// each polarity.
+    pub fn pulse_widths(samples: &[AdcSample], threshold: Option<f32>) -> Option<PulseWidths> {
+        let (mut high, mut low) = (Vec::new(), Vec::new());
+        for pair in crossings_at(samples, threshold).windows(2) {
+            let ((start, rising), (end, _)) = (pair[0], pair[1]);
+            let width = (end - start) * 1e-9;
+            if rising { high.push(width) } else { low.push(width) }
+        }
+        if high.is_empty() || low.is_empty() {
+            return None;
+        }
+        let mean = |widths: &[f64]| widths.iter().sum::<f64>() / widths.len() as f64;
+        Some(PulseWidths {
+            positive_s: mean(&high),
+            negative_s: mean(&low),
+            positive_pulses: high.len(),
+            negative_pulses: low.len(),
+        })
+    }
+
+    /// Period jitter from successive rising crossings; `None` with fewer than three periods.
+    pub fn jitter(samples: &[AdcSample]) -> Option<Jitter> {
+        let rising: Vec<f64> = crossings(samples).into_iter()
+            .filter(|&(_, rising)| rising)
+            .map(|(time, _)| time * 1e-9)
+            .collect();
+        let periods: Vec<f64> = rising.windows(2).map(|w| w[1] - w[0]).collect();
+        if periods.len() < MIN_JITTER_PERIODS {
+            return None;
+        }
+        let n = periods.len() as f64;
+        let mean = periods.iter().sum::<f64>() / n;
+        let variance = periods.iter().map(|p| (p - mean).powi(2)).sum::<f64>() / (n - 1.0);
+        let longest = periods.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        let shortest = periods.iter().copied().fold(f64::INFINITY, f64::min);
+        Some(Jitter {
+            mean_period_s: mean,
+            std_dev_s: variance.sqrt(),
+            peak_to_peak_s: longest - shortest,
+            periods: periods.len(),
+        })
+    }
+
+    /// Settling time of a single step in `samples`.
+    ///
+    /// The levels before and after the step are the means of the first and last
+    /// tenth of the window, and the step is placed where the signal first crosses
+    /// halfway between them. The band is `tolerance_pct` percent of the step
+    /// height around the final value. `None` if there is no clear step or the
+    /// signal is still outside the band within the final tenth.
+    pub fn settling_time(samples: &[AdcSample], tolerance_pct: f32) -> Option<SettlingTime> {
+        let edge = samples.len() / 10;
+        if edge < 2 {
+            return None;
+        }
+        let mean = |s: &[AdcSample]| s.iter().map(|s| s.voltage).sum::<f32>() / s.len() as f32;
+        let initial = mean(&samples[..edge]);
+        let final_value = mean(&samples[samples.len() - edge..]);
+        let step = final_value - initial;
+        if step == 0.0 {
+            return None;
+        }
+
+        // Normalized so the signal goes from 0 to 1 regardless of step direction
+        let progress = |s: &AdcSample| (s.voltage - initial) / step;
+        let step_index = samples.iter().position(|s| progress(s) >= 0.5)?;
+        let band = tolerance_pct / 100.0;
+        let settled_index = samples.iter()
+            .rposition(|s| (progress(s) - 1.0).abs() > band)
+            .map_or(step_index, |i| i + 1)
+            .max(step_index);
+        if settled_index >= samples.len() - edge {
+            return None;
+        }
+
+        let (step_ts, settled_ts) = (samples[step_index].timestamp, samples[settled_index].timestamp);
+        Some(SettlingTime {
+            settling_time_s: (settled_ts - step_ts) as f32 * 1e-9,
+            step_ts,
+            settled_ts,
+            initial,
+            final_value,
+        })
+    }
+
+    /// Amplitude envelope by full-wave rectification and a one-pole low-pass.
+    ///
+    /// The mean is removed before rectifying so a DC bias doesn't land in the
+    /// envelope, and the result is scaled by π/2 so a steady sine reads as its
+    /// peak amplitude. `cutoff_hz` wants to sit well below the carrier but above
+    /// the modulation. The filter starts at the first rectified value, so the
+    /// first few time constants are settling. `None` if the rate can't be estimated.
+    pub fn envelope(samples: &[AdcSample], cutoff_hz: f32) -> Option<Vec<TracePoint>> {
+        let sample_rate = estimate_sample_rate(samples)?;
+        let alpha = 1.0 - (-2.0 * PI * cutoff_hz / sample_rate).exp();
+        let mean = samples.iter().map(|s| s.voltage).sum::<f32>() / samples.len() as f32;
+
+        let mut level = (samples[0].voltage - mean).abs();
+        Some(samples.iter()
+            .map(|s| {
+                level += alpha * ((s.voltage - mean).abs() - level);
+                TracePoint { timestamp: s.timestamp, value: level * PI / 2.0 }
+            })
+            .collect())
+    }
+
+    /// Modulation depth `(max - min) / (max + min)` of the envelope at `cutoff_hz`.
+    ///
+    /// Assumes the carrier sits well above `cutoff_hz` and the modulation well below
+    /// it, ideally a decade each way; otherwise carrier ripple or a flattened envelope
+    /// skews the result. The first five filter time constants are skipped as settling.
+    /// `None` if the rate can't be estimated, nothing is left after settling, or the
+    /// envelope is flat at zero.
+    pub fn am_depth(samples: &[AdcSample], cutoff_hz: f32) -> Option<f32> {
+        let envelope = envelope(samples, cutoff_hz)?;
+        let settle_ns = (5.0 / (2.0 * PI * cutoff_hz) * 1e9) as u128;
+        let start = envelope.first()?.timestamp + settle_ns;
+        let settled: Vec<f32> = envelope.iter().filter(|p| p.timestamp >= start).map(|p| p.value).collect();
+        let max = settled.iter().copied().reduce(f32::max)?;
+        let min = settled.iter().copied().reduce(f32::min)?;
+        (max + min > 0.0).then(|| (max - min) / (max + min))
+    }
+
+    /// In-place iterative radix-2 FFT. The length must be a power of two.
+    pub fn fft(re: &mut [f32], im: &mut [f32]) {
+        let n = re.len();
+        debug_assert!(n.is_power_of_two() && im.len() == n);
+
+        // Bit-reversal permutation
+        let mut j = 0;
+        for i in 1..n {
+            let mut bit = n >> 1;
+            while j & bit != 0 {
+                j ^= bit;
+                bit >>= 1;
+            }
+            j |= bit;
+            if i < j {
+                re.swap(i, j);
+                im.swap(i, j);
+            }
+        }
+
+        let mut len = 2;
+        while len <= n {
+            let angle = -2.0 * PI / len as f32;
+            for start in (0..n).step_by(len) {
+                for k in 0..len / 2 {
+                    let (w_im, w_re) = (angle * k as f32).sin_cos();
+                    let (a, b) = (start + k, start + k + len / 2);
+                    let t_re = re[b] * w_re - im[b] * w_im;
+                    let t_im = re[b] * w_im + im[b] * w_re;
+                    re[b] = re[a] - t_re;
+                    im[b] = im[a] - t_im;
+                    re[a] += t_re;
+                    im[a] += t_im;
+                }
+            }
+            len <<= 1;
+        }
+    }
+
+    /// Taper applied to a block before the FFT.
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+    pub enum WindowFn {
+        Rectangular,
+        #[default]
+        Hann,
+        Hamming,
+        Blackman,
+        FlatTop,  // Widest main lobe, but near-exact amplitude for tones between bins
+    }
+
+    impl WindowFn {
+        /// Window coefficient at index `i` of an `n`-point periodic window.
+        pub fn coefficient(self, i: usize, n: usize) -> f32 {
+            let x = 2.0 * PI * i as f32 / n as f32;
+            let cosine_sum = |a: &[f32]| -> f32 {
+                a.iter()
+                    .enumerate()
+                    .map(|(k, &ak)| if k % 2 == 0 { ak } else { -ak } * (k as f32 * x).cos())
+                    .sum()
+            };
+            match self {
+                WindowFn::Rectangular => 1.0,
+                WindowFn::Hann => cosine_sum(&[0.5, 0.5]),
+                WindowFn::Hamming => cosine_sum(&[0.54, 0.46]),
+                WindowFn::Blackman => cosine_sum(&[0.42, 0.5, 0.08]),
+                WindowFn::FlatTop => cosine_sum(&[0.215_578_95, 0.416_631_58, 0.277_263_16, 0.083_578_95, 0.006_947_37]),
+            }
+        }
+
+        /// Bins either side of a tone's peak that hold most of its power.
+        pub fn main_lobe_half_width(self) -> usize {
+            match self {
+                WindowFn::Rectangular => 1,
+                WindowFn::Hann | WindowFn::Hamming => 2,
+                WindowFn::Blackman => 3,
+                WindowFn::FlatTop => 5,
+            }
+        }
+    }
+
+    /// Single-sided amplitude spectrum of the most recent power-of-two block of `values`.
+    ///
+    /// The mean is removed and `window` applied, with amplitudes divided by the
+    /// window's coherent gain so a tone of amplitude A peaks near A (exactly, for
+    /// `FlatTop`). Bin `k` corresponds to `k * sample_rate / n` where `n` is twice
+    /// the returned length.
+    pub fn magnitude_spectrum(values: &[f32], window: WindowFn) -> Vec<f32> {
+        if values.len() < 4 {
+            return Vec::new();
+        }
+        let n = (values.len().min(MAX_FFT_SIZE) + 1).next_power_of_two() / 2;
+        let block = &values[values.len() - n..];
+        let mean = block.iter().sum::<f32>() / n as f32;
+
+        let coefficients: Vec<f32> = (0..n).map(|i| window.coefficient(i, n)).collect();
+        let coherent_gain = coefficients.iter().sum::<f32>() / n as f32;
+
+        let mut re: Vec<f32> = block.iter()
+            .zip(&coefficients)
+            .map(|(&v, &w)| (v - mean) * w)
+            .collect();
+        let mut im = vec![0.0; n];
+        fft(&mut re, &mut im);
+
+        // 2/n for the single-sided spectrum, then undo the window's coherent gain
+        let scale = 2.0 / (n as f32 * coherent_gain);
+        (0..n / 2).map(|k| (re[k] * re[k] + im[k] * im[k]).sqrt() * scale).collect()
+    }
+
+    /// `spectrum` with `floor` removed bin by bin, in power since uncorrelated noise
+    /// adds in power. Bins at or under the floor come out as zero.
+    ///
+    /// `None` when the two weren't taken with the same FFT size, window and bin spacing.
+    pub fn subtract_spectrum(spectrum: &Spectrum, floor: &Spectrum) -> Option<Spectrum> {
+        let same_bins = ((spectrum.bin_hz - floor.bin_hz) / floor.bin_hz).abs() < NOISE_FLOOR_BIN_TOLERANCE;
+        if spectrum.magnitudes.len() != floor.magnitudes.len() || spectrum.window != floor.window || !same_bins {
+            return None;
+        }
+        let magnitudes = spectrum.magnitudes.iter()
+            .zip(&floor.magnitudes)
+            .map(|(m, f)| (m * m - f * f).max(0.0).sqrt())
+            .collect();
+        Some(Spectrum { magnitudes, ..spectrum.clone() })
+    }
+
+    /// Amplitude spectra of up to `num_frames` blocks of `fft_size` values, oldest first.
+    ///
+    /// Frames advance by half the FFT size (50% overlap) and end at the newest
+    /// value; when there isn't enough data for every frame, only the most recent
+    /// ones that fit are returned. `fft_size` must be a power of two.
+    pub fn spectrogram(values: &[f32], fft_size: usize, num_frames: usize, window: WindowFn) -> Vec<Vec<f32>> {
+        let hop = spectrogram_hop(fft_size);
+        let available = if values.len() < fft_size { 0 } else { (values.len() - fft_size) / hop + 1 };
+        let frames = num_frames.min(available);
+        let end = values.len();
+        (0..frames)
+            .rev()
+            .map(|i| {
+                let stop = end - i * hop;
+                magnitude_spectrum(&values[stop - fft_size..stop], window)
+            })
+            .collect()
+    }
+
+    pub fn spectrogram_hop(fft_size: usize) -> usize {
+        (fft_size / 2).max(1)
+    }
+
+    /// Group delay of `response` relative to a swept `stimulus` sampled alongside it.
+    ///
+    /// The signals are cut into spectrogram frames. In each frame the stimulus'
+    /// dominant bin is where the sweep currently is, and the phase of the cross
+    /// spectrum `response * conj(stimulus)` at that bin is the system's phase shift
+    /// there, whatever the stimulus' own phase. Frames landing in the same bin are
+    /// averaged, the phases are unwrapped in order of frequency, and the delay
+    /// `-dphase / domega` between neighbouring bins is reported at their midpoint.
+    /// Unwrapping assumes the delay is under half a frame. Errors unless the
+    /// stimulus visits at least `MIN_SWEEP_BINS` distinct bins.
+    pub fn group_delay(stimulus: &[f32], response: &[f32], sample_rate: f32, fft_size: usize, window: WindowFn) -> Result<Vec<GroupDelayPoint>, String> {
+        let n = stimulus.len().min(response.len());
+        let (stimulus, response) = (&stimulus[stimulus.len() - n..], &response[response.len() - n..]);
+        let hop = spectrogram_hop(fft_size);
+        let frames = if n < fft_size { 0 } else { (n - fft_size) / hop + 1 };
+
+        // Summed cross-spectrum phasor per stimulus bin
+        let mut bins: Vec<(usize, f32, f32)> = Vec::new();
+        for frame in 0..frames {
+            let range = frame * hop..frame * hop + fft_size;
+            let (s_re, s_im) = windowed_fft(&stimulus[range.clone()], window);
+            let (r_re, r_im) = windowed_fft(&response[range], window);
+            let magnitudes: Vec<f32> = (0..fft_size / 2).map(|k| s_re[k].hypot(s_im[k])).collect();
+            let Some(k) = fundamental_bin(&magnitudes, window) else {
+                continue;
+            };
+            let re = r_re[k] * s_re[k] + r_im[k] * s_im[k];
+            let im = r_im[k] * s_re[k] - r_re[k] * s_im[k];
+            match bins.iter_mut().find(|(bin, _, _)| *bin == k) {
+                Some((_, sum_re, sum_im)) => {
+                    *sum_re += re;
+                    *sum_im += im;
+                }
+                None => bins.push((k, re, im)),
+            }
+        }
+        if bins.len() < MIN_SWEEP_BINS {
+            return Err(format!(
+                "No frequency sweep on the stimulus: it covered {} FFT bins, at least {} are needed",
+                bins.len(), MIN_SWEEP_BINS
+            ));
+        }
+        bins.sort_by_key(|&(bin, _, _)| bin);
+
+        let bin_hz = sample_rate / fft_size as f32;
+        let mut phase = bins[0].2.atan2(bins[0].1);
+        Ok(bins.windows(2)
+            .map(|pair| {
+                let (lo, hi) = (pair[0], pair[1]);
+                // Step to the next phase by the shortest way round
+                let step = (hi.2.atan2(hi.1) - phase + PI).rem_euclid(2.0 * PI) - PI;
+                phase += step;
+                let (f_lo, f_hi) = (lo.0 as f32 * bin_hz, hi.0 as f32 * bin_hz);
+                GroupDelayPoint {
+                    frequency_hz: (f_lo + f_hi) / 2.0,
+                    delay_s: -step / (2.0 * PI * (f_hi - f_lo)),
+                }
+            })
+            .collect())
+    }
+
+    /// Complex spectrum of one block, mean removed and windowed.
+    fn windowed_fft(block: &[f32], window: WindowFn) -> (Vec<f32>, Vec<f32>) {
+        let n = block.len();
+        let mean = block.iter().sum::<f32>() / n as f32;
+        let mut re: Vec<f32> = block.iter()
+            .enumerate()
+            .map(|(i, &v)| (v - mean) * window.coefficient(i, n))
+            .collect();
+        let mut im = vec![0.0; n];
+        fft(&mut re, &mut im);
+        (re, im)
+    }
+
+    /// Index of the strongest non-DC bin, if it stands clearly above the noise floor.
+    fn fundamental_bin(spectrum: &[f32], window: WindowFn) -> Option<usize> {
+        let (peak, &peak_mag) = spectrum.iter()
+            .enumerate()
+            .skip(1)
+            .max_by(|a, b| a.1.total_cmp(b.1))?;
+
+        let mut sorted: Vec<f32> = spectrum[1..].to_vec();
+        sorted.sort_by(f32::total_cmp);
+        let floor = sorted[sorted.len() / 2];
+
+        let clear = peak_mag > 0.0 && peak_mag * peak_mag >= MIN_PEAK_TO_FLOOR * floor * floor;
+        (peak > window.main_lobe_half_width() && clear).then_some(peak)
+    }
+
+    /// Power held in the main lobe around `center`.
+    fn band_power(spectrum: &[f32], center: usize, window: WindowFn) -> f32 {
+        let half_width = window.main_lobe_half_width();
+        let lo = center.saturating_sub(half_width);
+        let hi = (center + half_width).min(spectrum.len() - 1);
+        spectrum[lo..=hi].iter().map(|m| m * m).sum()
+    }
+
+    /// Frequency in Hz of the dominant spectral component.
+    pub fn dominant_frequency(values: &[f32], sample_rate: f32, window: WindowFn) -> Option<f32> {
+        let spectrum = magnitude_spectrum(values, window);
+        let bin = fundamental_bin(&spectrum, window)?;
+        Some(bin as f32 * sample_rate / (2 * spectrum.len()) as f32)
+    }
+
+    /// Total harmonic distortion relative to the fundamental.
+    ///
+    /// Sums the power of the 2nd through 10th harmonics (those below Nyquist) and
+    /// returns `None` when no fundamental stands clearly above the noise floor.
+    pub fn thd(values: &[f32], sample_rate: f32, window: WindowFn) -> Option<ThdMeasurement> {
+        let spectrum = magnitude_spectrum(values, window);
+        let fundamental = fundamental_bin(&spectrum, window)?;
+        let fundamental_power = band_power(&spectrum, fundamental, window);
+
+        let mut harmonic_power = 0.0;
+        let mut harmonics = 0;
+        for h in 2..=THD_HARMONICS + 1 {
+            // The fundamental is only known to half a bin, so look around the expected bin
+            let expected = h * fundamental;
+            let lo = expected.saturating_sub(h / 2);
+            let hi = expected + h / 2;
+            if hi + window.main_lobe_half_width() >= spectrum.len() {
+                break;
+            }
+            let peak = (lo..=hi).max_by(|&a, &b| spectrum[a].total_cmp(&spectrum[b]))?;
+            harmonic_power += band_power(&spectrum, peak, window);
+            harmonics += 1;
+        }
+
+        if harmonics == 0 {
+            return None;  // Fundamental too close to Nyquist to see any harmonics
+        }
+
+        let ratio = (harmonic_power / fundamental_power).sqrt();
+        Some(ThdMeasurement {
+            fundamental_hz: fundamental as f32 * sample_rate / (2 * spectrum.len()) as f32,
+            thd_percent: ratio * 100.0,
+            thd_db: 20.0 * ratio.max(1e-6).log10(),  // Floor at -120 dB
+            harmonics,
+        })
+    }
+
+    /// Effective number of bits from the SINAD of a single tone.
+    ///
+    /// SINAD compares the fundamental's main lobe against every other bin outside
+    /// DC (noise plus distortion), and ENOB = (SINAD - 1.76 dB) / 6.02 dB. The formula
+    /// assumes a full-scale sine, so a smaller tone understates ENOB. Sampling needn't
+    /// be coherent, but leakage counts as noise, so a window such as Blackman gives
+    /// a truer figure than Rectangular. `None` unless one tone dominates the spectrum.
+    pub fn enob(values: &[f32], sample_rate: f32, window: WindowFn) -> Option<EnobMeasurement> {
+        let spectrum = magnitude_spectrum(values, window);
+        let fundamental = fundamental_bin(&spectrum, window)?;
+        let signal = band_power(&spectrum, fundamental, window);
+
+        let half_width = window.main_lobe_half_width();
+        let noise: f32 = spectrum.iter()
+            .enumerate()
+            .filter(|&(k, _)| k > half_width && k.abs_diff(fundamental) > half_width)
+            .map(|(_, m)| m * m)
+            .sum();
+        if noise >= signal {
+            return None;
+        }
+
+        let sinad_db = 10.0 * (signal / noise.max(signal * 1e-15)).log10();  // Cap at 150 dB
+        Some(EnobMeasurement {
+            fundamental_hz: fundamental as f32 * sample_rate / (2 * spectrum.len()) as f32,
+            sinad_db,
+            enob: (sinad_db - 1.76) / 6.02,
+        })
+    }
+
+    /// Coupling from `driven` into `victim` in dB, measured at the driven tone's frequency.
+    ///
+    /// Both signals are cut to their common (most recent) length, and the victim's
+    /// power in the driven fundamental's main lobe is compared against the driven
+    /// channel's, so unrelated signals on the victim at other frequencies don't count.
+    /// `None` unless a tone stands clearly above the driven channel's noise floor.
+    pub fn crosstalk(driven: &[f32], victim: &[f32], window: WindowFn) -> Option<f32> {
+        let n = driven.len().min(victim.len());
+        let driven_spectrum = magnitude_spectrum(&driven[driven.len() - n..], window);
+        let victim_spectrum = magnitude_spectrum(&victim[victim.len() - n..], window);
+        let fundamental = fundamental_bin(&driven_spectrum, window)?;
+
+        let driven_power = band_power(&driven_spectrum, fundamental, window);
+        let victim_power = band_power(&victim_spectrum, fundamental, window);
+        Some(10.0 * (victim_power / driven_power).max(1e-15).log10())  // Floor at -150 dB
+    }
+}
+
+mod logger {
+    use std::{
+        fs::{File, OpenOptions},
+        io::{self, BufWriter, Write},
+        path::Path,
+        sync::{atomic::{AtomicBool, Ordering}, Mutex},
+        time::{Duration, Instant},
+    };
+    use serde::{Serialize, Deserialize};
+    use crate::adc;  // Import the adc module
+
+    const LOG_PATH: &str = "log.csv";
+    const OVERVIEW_PATH: &str = "log_overview.csv";
+    const SAMPLE_HEADER: &str = "timestamp,channel,voltage,raw_value\n";
+
+    /// One sample as a line of the sample log.
+    fn csv_line(sample: &adc::AdcSample) -> String {
+        format!("{},{},{:.4},{}\n", sample.timestamp, sample.channel, sample.voltage, sample.raw_value)
+    }
+
+    /// Trade-off between logging throughput and what survives a crash.
+    #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+    pub struct LogOptions {
+        /// Flush every line as a single write so a crash can't leave half a line behind
+        pub atomic_lines: bool,
+        /// Also fsync at most this often; None leaves syncing to the OS
+        pub fsync_interval_ms: Option<u64>,
+        /// Roll over to a new file once the current one reaches this many bytes
+        #[serde(default)]
+        pub rotate_bytes: Option<u64>,
+        /// Roll over to a new file once the current one has been open this long
+        #[serde(default)]
+        pub rotate_interval_s: Option<u64>,
+        /// Skip samples less than this long after the last one logged on their channel; 0 logs all
+        #[serde(default)]
+        pub min_log_interval_us: u64,
+    }
+
+    impl Default for LogOptions {
+        fn default() -> Self {
+            Self {
+                atomic_lines: true,
+                fsync_interval_ms: None,
+                rotate_bytes: None,
+                rotate_interval_s: None,
+                min_log_interval_us: 0,
+            }
+        }
+    }
+
+    /// One CSV output, with its own buffer and decimation.
+    struct LogFile {
+        writer: BufWriter<File>,
+        base: &'static str,  // Path of the first file; rotated ones get a timestamp suffix
+        bytes: u64,          // Written to the current file, header included
+        opened: Instant,
+        last_sync: Instant,
+        decimation: usize,   // Keep every Nth sample of each channel
+        seen: [usize; 4],
+        last_logged: [Option<u128>; 4],  // Timestamp of each channel's last written sample
+    }
+
+    impl LogFile {
+        fn create(base: &'static str, decimation: usize) -> Result<Self, String> {
+            let (writer, bytes) = Self::open(base)?;
+            let now = Instant::now();
+            Ok(Self { writer, base, bytes, opened: now, last_sync: now, decimation, seen: [0; 4], last_logged: [None; 4] })
+        }
+
+        /// Creates `path` and writes the CSV header, returning the header's length.
+        fn open(path: &str) -> Result<(BufWriter<File>, u64), String> {
+            let file = File::create(path)
+                .map_err(|e| format!("Failed to create {}: {}", path, e))?;
+            let mut writer = BufWriter::new(file);
+
+            writer.write_all(SAMPLE_HEADER.as_bytes())
+                .and_then(|_| writer.flush())
+                .map_err(|e| format!("Failed to write CSV header: {}", e))?;
+            Ok((writer, SAMPLE_HEADER.len() as u64))
+        }
+
+        fn rotation_due(&self, options: &LogOptions) -> bool {
+            options.rotate_bytes.is_some_and(|limit| self.bytes >= limit)
+                || options.rotate_interval_s.is_some_and(|s| self.opened.elapsed() >= Duration::from_secs(s))
+        }
+
+        /// Closes the current file and continues in `<base>_<unix ms>.<ext>`.
+        ///
+        /// The decimation counters carry over, so the overview keeps its spacing
+        /// across files.
+        fn rotate(&mut self) -> Result<(), String> {
+            self.sync()?;
+            let (stem, ext) = self.base.rsplit_once('.').unwrap_or((self.base, "csv"));
+            let mut millis = crate::adc::now_nanos() / 1_000_000;
+            let path = loop {
+                let path = format!("{}_{}.{}", stem, millis, ext);
+                if !Path::new(&path).exists() {
+                    break path;
+                }
+                millis += 1;  // Two rotations within a millisecond mustn't overwrite each other
+            };
+
+            (self.writer, self.bytes) = Self::open(&path)?;
+            self.opened = Instant::now();
+            tracing::info!("Log rotated to {}", path);
+            Ok(())
+        }
+
+        /// Says whether this file records `sample`.
+        ///
+        /// Samples inside the minimum interval are dropped before decimation counts
+        /// them, so the two combine as "every Nth of the ones far enough apart".
+        fn keeps(&mut self, sample: &adc::AdcSample, options: &LogOptions) -> bool {
+            let ch = sample.channel as usize & 3;
+            let interval_ns = options.min_log_interval_us as u128 * 1000;
+            // A timestamp before the last one means the clock was switched; start over
+            let too_soon = self.last_logged[ch]
+                .and_then(|last| sample.timestamp.checked_sub(last))
+                .is_some_and(|since| since < interval_ns);
+            if too_soon {
+                return false;
+            }
+            let seen = &mut self.seen[ch];
+            let keep = seen.is_multiple_of(self.decimation);
+            *seen += 1;
+            if keep {
+                self.last_logged[ch] = Some(sample.timestamp);
+            }
+            keep
+        }
+
+        fn write_line(&mut self, line: &str, options: &LogOptions) -> Result<(), String> {
+            if self.rotation_due(options) {
+                self.rotate()?;
+            }
+            if let Err(e) = self.writer.write_all(line.as_bytes()) {
+                return Err(format!("Failed to write to log file: {}", e));
+            }
+            self.bytes += line.len() as u64;
+            // The whole line fits in the buffer, so flushing here issues it as one write
+            if options.atomic_lines {
+                if let Err(e) = self.writer.flush() {
+                    return Err(format!("Failed to flush log file: {}", e));
+                }
+            }
+            if let Some(interval) = options.fsync_interval_ms.map(Duration::from_millis) {
+                if self.last_sync.elapsed() >= interval {
+                    self.sync()?;
+                }
+            }
+            Ok(())
+        }
+
+        fn sync(&mut self) -> Result<(), String> {
+            self.writer.flush()
+                .map_err(|e| format!("Failed to flush log file: {}", e))?;
+            self.writer.get_ref().sync_data()
+                .map_err(|e| format!("Failed to sync log file: {}", e))?;
+            self.last_sync = Instant::now();
+            Ok(())
+        }
+    }
+
+    /// One channel's measurements at one tick of the measurement log.
+    pub struct MeasurementRow {
+        pub timestamp: u128,  // Newest sample the measurements cover
+        pub channel: u8,
+        pub frequency_hz: Option<f32>,
+        pub vpp: f32,
+        pub rms: f32,
+        pub mean: f32,
+    }
+
+    /// Trend CSV of periodic measurements, separate from the sample log.
+    pub struct MeasurementLog {
+        writer: BufWriter<File>,
+    }
+
+    impl MeasurementLog {
+        pub fn create(path: &Path) -> Result<Self, String> {
+            let file = File::create(path)
+                .map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+            let mut writer = BufWriter::new(file);
+            writeln!(writer, "timestamp,channel,frequency_hz,vpp,rms,mean")
+                .and_then(|_| writer.flush())
+                .map_err(|e| format!("Failed to write CSV header: {}", e))?;
+            Ok(Self { writer })
+        }
+
+        /// Appends `rows` and flushes, leaving the frequency empty where none was found.
+        pub fn append(&mut self, rows: &[MeasurementRow]) -> Result<(), String> {
+            for row in rows {
+                let frequency = row.frequency_hz.map(|f| format!("{:.4}", f)).unwrap_or_default();
+                writeln!(self.writer, "{},{},{},{:.4},{:.4},{:.4}",
+                    row.timestamp, row.channel, frequency, row.vpp, row.rms, row.mean
+                ).map_err(|e| format!("Failed to write to measurement log: {}", e))?;
+            }
+            self.writer.flush()
+                .map_err(|e| format!("Failed to flush measurement log: {}", e))
+        }
+    }
+
+    /// How `PipeOutput` writes samples.
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+    #[serde(rename_all = "lowercase")]
+    pub enum PipeFormat {
+        #[default]
+        Csv,     // The sample log's header and lines
+        Binary,  // `adc::encode_samples` records, no header
+    }
+
+    /// The decoded sample stream written to stdout or a named pipe for other tools.
+    pub struct PipeOutput {
+        writer: Box<dyn Write + Send>,
+        target: String,
+        format: PipeFormat,
+    }
+
+    impl PipeOutput {
+        /// Opens `target` for writing; `-` or `stdout` is standard output.
+        ///
+        /// Opening a named pipe blocks until something opens it for reading.
+        pub fn open(target: &str, format: PipeFormat) -> Result<Self, String> {
+            let writer: Box<dyn Write + Send> = if target == "-" || target == "stdout" {
+                Box::new(io::stdout())
+            } else {
+                let file = OpenOptions::new().write(true).create(true).truncate(true).open(target)
+                    .map_err(|e| format!("Failed to open {}: {}", target, e))?;
+                Box::new(BufWriter::new(file))
+            };
+            let mut output = Self { writer, target: target.to_string(), format };
+            if format == PipeFormat::Csv {
+                output.flushed(|w| w.write_all(SAMPLE_HEADER.as_bytes()))?;
+            }
+            Ok(output)
+        }
+
+        /// Writes a batch and flushes it so the reader sees it straight away.
+        pub fn write(&mut self, samples: &[adc::AdcSample]) -> Result<(), String> {
+            match self.format {
+                PipeFormat::Csv => self.flushed(|w| {
+                    samples.iter().try_for_each(|s| w.write_all(csv_line(s).as_bytes()))
+                }),
+                PipeFormat::Binary => self.flushed(|w| w.write_all(&adc::encode_samples(samples))),
+            }
+        }
+
+        fn flushed(&mut self, write: impl FnOnce(&mut dyn Write) -> io::Result<()>) -> Result<(), String> {
+            write(&mut *self.writer)
+                .and_then(|_| self.writer.flush())
+                .map_err(|e| match e.kind() {
+                    io::ErrorKind::BrokenPipe => format!("Reader of {} went away", self.target),
+                    _ => format!("Failed to write to {}: {}", self.target, e),
+                })
+        }
+    }
+
+    pub struct Logger {
+        recording: AtomicBool,
+        files: Mutex<Vec<LogFile>>,  // Full rate first, then the overview if there is one
+        options: Mutex<LogOptions>,
+    }
+
+    impl Logger {
+        pub fn new() -> Self {
+            Self {
+                recording: AtomicBool::new(false),
+                files: Mutex::new(Vec::new()),
+                options: Mutex::new(LogOptions::default()),
+            }
+        }
+
+        pub fn set_options(&self, options: LogOptions) -> Result<(), String> {
+            if options.fsync_interval_ms == Some(0) {
+                return Err("Fsync interval must be greater than zero".to_string());
+            }
+            if options.rotate_bytes == Some(0) || options.rotate_interval_s == Some(0) {
+                return Err("Rotation size and interval must be greater than zero".to_string());
+            }
+            *self.options.lock().map_err(|_| "Failed to lock log options".to_string())? = options;
+            Ok(())
+        }
+
+        pub fn options(&self) -> LogOptions {
+            self.options.lock().map(|o| *o).unwrap_or_default()
+        }
+
+        /// Starts `log.csv` at full rate and, with `overview_decimation`, a second
+        /// `log_overview.csv` keeping every Nth sample of each channel.
+        pub fn start(&self, overview_decimation: Option<usize>) -> Result<(), String> {
+            if overview_decimation.is_some_and(|n| n < 2) {
+                return Err("Overview decimation must be at least 2".to_string());
+            }
+            let mut files = vec![LogFile::create(LOG_PATH, 1)?];
+            if let Some(decimation) = overview_decimation {
+                files.push(LogFile::create(OVERVIEW_PATH, decimation)?);
+            }
+
+            let mut lock = self.files.lock()
+                .map_err(|_| "Failed to lock file".to_string())?;
+            *lock = files;
+
+            self.recording.store(true, Ordering::Relaxed);
+            Ok(())
+        }
+
+        pub fn stop(&self) -> Result<(), String> {
+            self.recording.store(false, Ordering::Relaxed);
+            let mut lock = self.files.lock()
+                .map_err(|_| "Failed to lock file".to_string())?;
+            let result = lock.iter_mut().try_for_each(LogFile::sync);
+            lock.clear();
+            result
+        }
+
+        /// Stops writing but keeps the files open, flushed, for `resume`.
+        pub fn pause(&self) -> Result<(), String> {
+            self.recording.store(false, Ordering::Relaxed);
+            let mut lock = self.files.lock()
+                .map_err(|_| "Failed to lock file".to_string())?;
+            lock.iter_mut().try_for_each(|file| {
+                file.writer.flush().map_err(|e| format!("Failed to flush log file: {}", e))
+            })
+        }
+
+        /// Appends to the files left open by `pause`.
+        pub fn resume(&self) -> Result<(), String> {
+            let lock = self.files.lock()
+                .map_err(|_| "Failed to lock file".to_string())?;
+            if lock.is_empty() {
+                return Err("No log is open; start logging first".to_string());
+            }
+            self.recording.store(true, Ordering::Relaxed);
+            Ok(())
+        }
+
+        pub fn is_recording(&self) -> bool {
+            self.recording.load(Ordering::Relaxed)
+        }
+
+        pub fn log_sample(&self, sample: &adc::AdcSample) -> Result<(), String> {
+            if !self.is_recording() {
+                return Ok(());
+            }
+            let options = self.options();
+
+            if let Ok(mut files) = self.files.lock() {
+                let csv_line = csv_line(sample);
+                for file in files.iter_mut() {
+                    if file.keeps(sample, &options) {
+                        file.write_line(&csv_line, &options)?;
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        /// Writes a marker as a `# marker,<timestamp>,<label>` comment line to every file.
+        pub fn log_marker(&self, marker: &adc::Marker) -> Result<(), String> {
+            if !self.is_recording() {
+                return Ok(());
+            }
+            let options = LogOptions { atomic_lines: true, ..self.options() };
+            if let Ok(mut files) = self.files.lock() {
+                let line = format!("# marker,{},{}\n", marker.timestamp, marker.label);
+                for file in files.iter_mut() {
+                    file.write_line(&line, &options)?;
+                }
+            }
+            Ok(())
+        }
+    }
+
+    impl Drop for Logger {
+        fn drop(&mut self) {
+            if let Ok(files) = self.files.get_mut() {
+                for file in files.iter_mut() {
+                    if let Err(e) = file.sync() {
+                        tracing::error!("{}", e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+mod presets {
+    use std::{fs, path::{Path, PathBuf}};
+    use crate::adc::FullConfig;
+
+    const EXTENSION: &str = "json";
+
+    /// Preset names become file names, so keep them to a safe character set.
+    fn preset_path(dir: &Path, name: &str) -> Result<PathBuf, String> {
+        let valid = !name.is_empty()
+            && name.len() <= 64
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | ' '));
+        if !valid {
+            return Err(format!(
+                "Invalid preset name '{}': use letters, digits, spaces, '-' or '_'",
+                name
+            ));
+        }
+        Ok(dir.join(format!("{}.{}", name, EXTENSION)))
+    }
+
+    pub fn save(dir: &Path, name: &str, config: &FullConfig) -> Result<(), String> {
+        let path = preset_path(dir, name)?;
+        fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create preset directory: {}", e))?;
+        let json = serde_json::to_string_pretty(config)
+            .map_err(|e| format!("Failed to serialize preset: {}", e))?;
+        fs::write(&path, json)
+            .map_err(|e| format!("Failed to write preset {}: {}", path.display(), e))
+    }
+
+    pub fn load(dir: &Path, name: &str) -> Result<FullConfig, String> {
+        let path = preset_path(dir, name)?;
+        let json = fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read preset '{}': {}", name, e))?;
+        serde_json::from_str(&json)
+            .map_err(|e| format!("Preset '{}' is malformed: {}", name, e))
+    }
+
+    /// Names of all saved presets, sorted. A missing directory just means none yet.
+    pub fn list(dir: &Path) -> Result<Vec<String>, String> {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(format!("Failed to list presets: {}", e)),
+        };
+
+        let mut names: Vec<String> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == EXTENSION))
+            .filter_map(|path| path.file_stem().and_then(|stem| stem.to_str()).map(String::from))
+            .collect();
+        names.sort();
+        Ok(names)
+    }
+}
+
+mod session {
+    use std::{fs, path::Path};
+    use serde::{Serialize, Deserialize};
+    use crate::adc::{AdcSample, FullConfig, Marker};
+    use crate::analysis::ThdMeasurement;
+
+    /// Bumped whenever the archive layout changes incompatibly.
+    pub const VERSION: u32 = 1;
+
+    /// Measurements taken on one channel when the session was exported.
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct ChannelMeasurements {
+        pub channel: u8,
+        pub sample_rate: Option<f32>,
+        pub thd: Option<ThdMeasurement>,
+        pub crest_factor: Option<f32>,
+    }
+
+    /// Everything needed to review an acquisition without the hardware.
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct SessionArchive {
+        pub version: u32,
+        pub exported_at: u128,  // Nanoseconds since the Unix epoch
+        pub config: FullConfig,
+        pub history: Vec<Vec<AdcSample>>,  // One per channel, then the differential channel
+        pub single_capture: Vec<AdcSample>,
+        pub markers: Vec<Marker>,
+        pub measurements: Vec<ChannelMeasurements>,
+    }
+
+    pub fn save(path: &Path, archive: &SessionArchive) -> Result<(), String> {
+        let json = serde_json::to_string(archive)
+            .map_err(|e| format!("Failed to serialize session: {}", e))?;
+        fs::write(path, json)
+            .map_err(|e| format!("Failed to write session {}: {}", path.display(), e))
+    }
+
+    pub fn load(path: &Path) -> Result<SessionArchive, String> {
+        let json = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read session {}: {}", path.display(), e))?;
+        // Check the version first so a newer archive gets a clearer error than a parse failure
+        let version = serde_json::from_str::<serde_json::Value>(&json)
+            .map_err(|e| format!("Session {} is malformed: {}", path.display(), e))?
+            .get("version")
+            .and_then(|v| v.as_u64());
+        if version != Some(VERSION as u64) {
+            return Err(format!(
+                "Session {} has format version {:?}, expected {}",
+                path.display(), version, VERSION
+            ));
+        }
+        serde_json::from_str(&json)
+            .map_err(|e| format!("Session {} is malformed: {}", path.display(), e))
+    }
+}
+
+mod transport {
+    use std::{
+        collections::{hash_map::RandomState, VecDeque},
+        f32::consts::PI,
+        hash::{BuildHasher, Hasher},
+        io::{self, Read, Write},
+        net::{TcpStream, ToSocketAddrs},
+        thread,
+        time::{Duration, Instant},
+    };
+    use crate::adc::{self, volts_to_code};
+    use serde::{Serialize, Deserialize};
+
+    const READ_TIMEOUT: Duration = Duration::from_millis(100);
+    const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+    /// Where the packet stream comes from.
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    #[serde(tag = "type", rename_all = "lowercase")]
+    pub enum Transport {
+        Serial {
+            #[serde(default)]
+            device: Option<DeviceId>,  // None picks the first COM port
+        },
+        Tcp { address: String },  // host:port
+        Mock(MockParams),
+    }
+
+    impl Default for Transport {
+        fn default() -> Self {
+            Transport::Serial { device: None }
+        }
+    }
+
+    /// Identifies a USB serial device whatever port name the OS gives it.
+    ///
+    /// Without a serial number it is a VID/PID filter matching the first such device.
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct DeviceId {
+        pub vid: u16,
+        pub pid: u16,
+        #[serde(default)]
+        pub serial_number: Option<String>,
+    }
+
+    impl DeviceId {
+        fn matches(&self, usb: &serialport::UsbPortInfo) -> bool {
+            self.vid == usb.vid
+                && self.pid == usb.pid
+                && self.serial_number.as_ref().is_none_or(|serial| usb.serial_number.as_ref() == Some(serial))
+        }
+    }
+
+    impl std::fmt::Display for DeviceId {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{:04x}:{:04x}", self.vid, self.pid)?;
+            if let Some(serial) = &self.serial_number {
+                write!(f, " ({})", serial)?;
+            }
+            Ok(())
+        }
+    }
+
+    /// A detected serial port, described well enough to pick the right one.
+    #[derive(Debug, Clone, Serialize)]
+    pub struct DeviceInfo {
+        pub name: String,
+        pub port_type: &'static str,  // "usb", "pci", "bluetooth" or "unknown"
+        pub manufacturer: Option<String>,
+        pub product: Option<String>,
+        pub id: Option<DeviceId>,     // USB devices only
+    }
+
+    pub fn list_devices() -> Result<Vec<DeviceInfo>, String> {
+        let ports = serialport::available_ports()
+            .map_err(|e| format!("Failed to list ports: {}", e))?;
+
+        Ok(ports.into_iter()
+            .map(|port| {
+                let name = port.port_name;
+                match port.port_type {
+                    serialport::SerialPortType::UsbPort(usb) => DeviceInfo {
+                        name,
+                        port_type: "usb",
+                        id: Some(DeviceId { vid: usb.vid, pid: usb.pid, serial_number: usb.serial_number }),
+                        manufacturer: usb.manufacturer,
+                        product: usb.product,
+                    },
+                    other => DeviceInfo {
+                        name,
+                        port_type: match other {
+                            serialport::SerialPortType::PciPort => "pci",
+                            serialport::SerialPortType::BluetoothPort => "bluetooth",
+                            _ => "unknown",
+                        },
+                        manufacturer: None,
+                        product: None,
+                        id: None,
+                    },
+                }
+            })
+            .collect())
+    }
+
+    /// A byte stream carrying the packet protocol.
+    ///
+    /// Reads time out (see `is_timeout`) instead of blocking forever so the reader
+    /// loop can notice configuration changes between reads.
+    pub trait DataSource: Read + Write + Send {
+        fn description(&self) -> String;
+    }
+
+    pub fn is_timeout(e: &io::Error) -> bool {
+        matches!(e.kind(), io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock)
+    }
+
+    /// Exponential backoff between connection attempts.
+    #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+    pub struct RetryPolicy {
+        pub initial_ms: u64,
+        pub max_ms: u64,
+        pub jitter: f32,  // Each delay is randomly moved by up to this fraction either way
+    }
+
+    impl Default for RetryPolicy {
+        fn default() -> Self {
+            Self { initial_ms: 1000, max_ms: 60_000, jitter: 0.1 }
+        }
+    }
+
+    impl RetryPolicy {
+        pub fn validate(&self) -> Result<(), String> {
+            if self.initial_ms == 0 || self.max_ms < self.initial_ms {
+                return Err(format!(
+                    "Retry delays must satisfy 0 < initial ({} ms) <= max ({} ms)",
+                    self.initial_ms, self.max_ms
+                ));
+            }
+            if !(0.0..=1.0).contains(&self.jitter) {
+                return Err(format!("Retry jitter must be between 0 and 1, got {}", self.jitter));
+            }
+            Ok(())
+        }
+
+        /// Delay before retry number `retry` (starting at 1): doubling from the initial
+        /// delay up to the max, then jittered.
+        pub fn delay(&self, retry: u32) -> Duration {
+            let doublings = retry.saturating_sub(1).min(63);
+            let base = self.initial_ms.saturating_mul(1u64 << doublings).min(self.max_ms);
+            // A fresh RandomState is randomly keyed, which is plenty for spreading retries
+            let random = RandomState::new().build_hasher().finish();
+            let offset = (random as f64 / u64::MAX as f64) * 2.0 - 1.0;
+            Duration::from_millis((base as f64 * (1.0 + self.jitter as f64 * offset)) as u64)
+        }
+    }
+
+    /// Why a source couldn't be opened.
+    #[derive(Debug)]
+    pub enum OpenError {
+        NoPorts,
+        NoComPort { ports: Vec<String> },  // Ports exist, but none looks like the default COM port
+        DeviceNotFound(DeviceId),
+        PermissionDenied { port: String },
+        Failed(String),
+    }
+
+    impl OpenError {
+        /// Nothing usable is attached; retrying right away won't change that.
+        pub fn is_unavailable(&self) -> bool {
+            matches!(
+                self,
+                OpenError::NoPorts | OpenError::NoComPort { .. } | OpenError::DeviceNotFound(_) | OpenError::PermissionDenied { .. }
+            )
+        }
+    }
+
+    impl std::fmt::Display for OpenError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                OpenError::NoPorts => write!(f, "No serial ports found; check the device is plugged in and its driver is installed"),
+                OpenError::NoComPort { ports } => write!(
+                    f,
+                    "No COM port among {}; choose the device from the device list or set a USB VID/PID filter",
+                    ports.join(", ")
+                ),
+                OpenError::DeviceNotFound(id) => write!(f, "Selected device {} is not attached", id),
+                OpenError::PermissionDenied { port } => write!(
+                    f,
+                    "Permission denied opening {}; on Linux add your user to the dialout group \
+                     (sudo usermod -aG dialout $USER) and log in again",
+                    port
+                ),
+                OpenError::Failed(message) => write!(f, "{}", message),
+            }
+        }
+    }
+
+    impl From<String> for OpenError {
+        fn from(message: String) -> Self {
+            OpenError::Failed(message)
+        }
+    }
+
+    /// Opens the configured source. With `mock_fallback`, a machine with no serial
+    /// ports at all starts the mock device instead of failing; attached ports that
+    /// merely don't match the selection still report an error.
+    pub fn open(transport: &Transport, baud_rate: u32, mock_fallback: bool) -> Result<Box<dyn DataSource>, OpenError> {
+        Ok(match transport {
+            Transport::Serial { device } => match SerialSource::open(baud_rate, device.as_ref()) {
+                Err(OpenError::NoPorts) if mock_fallback => {
+                    tracing::warn!("No serial ports found, starting the mock device");
+                    Box::new(MockSource::new(MockParams::default()))
+                }
+                source => Box::new(source?),
+            },
+            Transport::Tcp { address } => Box::new(TcpSource::connect(address)?),
+            Transport::Mock(params) => Box::new(MockSource::new(params.clone())),
+        })
+    }
+
+    pub struct SerialSource {
+        port: Box<dyn serialport::SerialPort>,
+        name: String,
+    }
+
+    impl SerialSource {
+        /// Opens `device` wherever it is attached, or the first COM port without one.
+        pub fn open(baud_rate: u32, device: Option<&DeviceId>) -> Result<Self, OpenError> {
+            let available_ports = serialport::available_ports()
+                .map_err(|e| format!("Failed to list ports: {}", e))?;
+
+            tracing::info!("Available ports: {:?}", available_ports);
+            if available_ports.is_empty() {
+                return Err(OpenError::NoPorts);
+            }
+
+            let names: Vec<String> = available_ports.iter().map(|p| p.port_name.clone()).collect();
+            let port_info = available_ports
+                .into_iter()
+                .find(|p| match (device, &p.port_type) {
+                    (Some(id), serialport::SerialPortType::UsbPort(usb)) => id.matches(usb),
+                    (Some(_), _) => false,
+                    (None, _) => p.port_name.to_uppercase().starts_with("COM"),
+                })
+                .ok_or_else(|| match device {
+                    Some(id) => OpenError::DeviceNotFound(id.clone()),
+                    None => OpenError::NoComPort { ports: names },
+                })?;
+
+            tracing::info!("Selected port: {}", port_info.port_name);
+
+            let port = serialport::new(&port_info.port_name, baud_rate)
+                .timeout(READ_TIMEOUT)
+                .open()
+                .map_err(|e| match e.kind() {
+                    serialport::ErrorKind::Io(io::ErrorKind::PermissionDenied) => {
+                        OpenError::PermissionDenied { port: port_info.port_name.clone() }
+                    }
+                    _ => OpenError::Failed(format!("Failed to open {}: {}", port_info.port_name, e)),
+                })?;
+
+            Ok(Self { port, name: port_info.port_name })
+        }
+    }
+
+    impl Read for SerialSource {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.port.read(buf)
+        }
+    }
+
+    impl Write for SerialSource {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.port.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.port.flush()
+        }
+    }
+
+    impl DataSource for SerialSource {
+        fn description(&self) -> String {
+            format!("serial port {}", self.name)
+        }
+    }
+
+    /// Firmware streaming the same packets over a socket (e.g. an ESP32).
+    pub struct TcpSource {
+        stream: TcpStream,
+        address: String,
+    }
+
+    impl TcpSource {
+        pub fn connect(address: &str) -> Result<Self, String> {
+            let addrs = address.to_socket_addrs()
+                .map_err(|e| format!("Invalid address {}: {}", address, e))?;
+
+            let mut last_error = format!("{} did not resolve to any address", address);
+            for addr in addrs {
+                match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+                    Ok(stream) => {
+                        stream.set_read_timeout(Some(READ_TIMEOUT))
+                            .map_err(|e| format!("Failed to configure {}: {}", address, e))?;
+                        let _ = stream.set_nodelay(true);
+                        return Ok(Self { stream, address: address.to_string() });
+                    }
+                    Err(e) => last_error = format!("Failed to connect to {}: {}", addr, e),
+                }
+            }
+            Err(last_error)
+        }
+    }
+
+    impl Read for TcpSource {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            // Unlike a serial port, a zero-length read means the peer hung up
+            match self.stream.read(buf)? {
+                0 if !buf.is_empty() => Err(io::Error::new(
+                    io::ErrorKind::ConnectionAborted,
+                    "Connection closed by device",
+                )),
+                n => Ok(n),
+            }
+        }
+    }
+
+    impl Write for TcpSource {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.stream.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.stream.flush()
+        }
+    }
+
+    impl DataSource for TcpSource {
+        fn description(&self) -> String {
+            format!("TCP {}", self.address)
+        }
+    }
+
+    /// Signal and impairment settings for the simulated device.
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    #[serde(default)]
+    pub struct MockParams {
+        pub seed: u64,
+        pub sample_rate: u32,          // Samples per second on each channel
+        pub channels: [bool; 4],       // Channels the mock streams; keep in step with the active channels
+        pub noise_volts: f32,          // Standard deviation of the added Gaussian noise
+        pub drop_probability: f32,     // Chance that each byte is lost
+        pub corrupt_probability: f32,  // Chance that each byte is replaced by a random one
+        pub waveforms: [MockWaveform; 4],
+    }
+
+    /// `dc + amplitude * sin(2π * frequency_hz * t + phase)` on one mock channel.
+    #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+    pub struct MockWaveform {
+        pub amplitude: f32,
+        pub frequency_hz: f32,
+        pub phase_deg: f32,
+        pub dc: f32,
+    }
+
+    impl MockWaveform {
+        fn at(&self, t: f64) -> f32 {
+            // Wrap in f64 so the phase stays precise over long runs
+            let cycles = (self.frequency_hz as f64 * t).fract() as f32;
+            self.dc + self.amplitude * (2.0 * PI * cycles + self.phase_deg.to_radians()).sin()
+        }
+    }
+
+    impl Default for MockParams {
+        fn default() -> Self {
+            Self {
+                seed: 1,
+                sample_rate: 1000,
+                channels: [true, true, false, false],
+                noise_volts: 0.0,
+                drop_probability: 0.0,
+                corrupt_probability: 0.0,
+                // 5 V sines at 10 Hz on Ch1, 20 Hz on Ch2, ...
+                waveforms: std::array::from_fn(|ch| MockWaveform {
+                    amplitude: 5.0,
+                    frequency_hz: 10.0 * (ch + 1) as f32,
+                    phase_deg: 0.0,
+                    dc: 0.0,
+                }),
+            }
+        }
+    }
+
+    impl MockParams {
+        pub fn validate(&self) -> Result<(), String> {
+            if self.sample_rate == 0 {
+                return Err("Mock sample rate must be greater than zero".to_string());
+            }
+            if !self.noise_volts.is_finite() || self.noise_volts < 0.0 {
+                return Err(format!("Mock noise must be a non-negative voltage, got {}", self.noise_volts));
+            }
+            for (name, p) in [("drop", self.drop_probability), ("corrupt", self.corrupt_probability)] {
+                if !(0.0..=1.0).contains(&p) {
+                    return Err(format!("Mock {} probability must be between 0 and 1, got {}", name, p));
+                }
+            }
+            let nyquist = self.sample_rate as f32 / 2.0;
+            for (ch, wave) in self.waveforms.iter().enumerate() {
+                if ![wave.amplitude, wave.phase_deg, wave.dc].iter().all(|v| v.is_finite()) {
+                    return Err(format!("Mock waveform on channel {} must be finite", ch));
+                }
+                if !(0.0..nyquist).contains(&wave.frequency_hz) {
+                    return Err(format!(
+                        "Mock frequency on channel {} must be below half the sample rate ({} Hz), got {}",
+                        ch, nyquist, wave.frequency_hz
+                    ));
+                }
+            }
+            Ok(())
+        }
+    }
+
+    /// SplitMix64, so a given seed always produces the same impaired stream.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next_u64(&mut self) -> u64 {
+            self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+            let mut z = self.0;
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+            z ^ (z >> 31)
+        }
+
+        /// Uniform in [0, 1).
+        fn next_f32(&mut self) -> f32 {
+            (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+        }
+
+        /// Standard normal via Box-Muller.
+        fn gaussian(&mut self) -> f32 {
+            let u1 = 1.0 - self.next_f32();  // (0, 1], keeps ln finite
+            let u2 = self.next_f32();
+            (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos()
+        }
+    }
+
+    /// Simulated device streaming each channel's `MockWaveform`.
+    ///
+    /// Samples are paced against the wall clock; written commands are accepted and ignored.
+    pub struct MockSource {
+        params: MockParams,
+        rng: Rng,
+        started: Instant,
+        emitted: u64,  // Sample frames generated so far
+        pending: VecDeque<u8>,
+    }
+
+    impl MockSource {
+        pub fn new(params: MockParams) -> Self {
+            Self {
+                rng: Rng(params.seed),
+                params,
+                started: Instant::now(),
+                emitted: 0,
+                pending: VecDeque::new(),
+            }
+        }
+
+        fn push_frame(&mut self, channel: u8, t: f64) {
+            let wave = self.params.waveforms[channel as usize];
+            let voltage = wave.at(t) + self.params.noise_volts * self.rng.gaussian();
+            let [hi, lo] = volts_to_code(voltage, true).to_be_bytes();
+
+            for byte in [0xAA, channel, hi, lo, 0x55] {  // start, channel, data, stop
+                if self.rng.next_f32() < self.params.drop_probability {
+                    continue;
+                }
+                if self.rng.next_f32() < self.params.corrupt_probability {
+                    self.pending.push_back(self.rng.next_u64() as u8);
+                } else {
+                    self.pending.push_back(byte);
+                }
+            }
+        }
+
+        /// Queues the next sample frame on every enabled channel.
+        fn push_frames(&mut self) {
+            let t = self.emitted as f64 / self.params.sample_rate as f64;
+            for channel in 0..4u8 {
+                if self.params.channels[channel as usize] {
+                    self.push_frame(channel, t);
+                }
+            }
+            self.emitted += 1;
+        }
+
+        /// Reply value for a capability query, in the units `DeviceCapabilities` expects.
+        fn capability(&self, opcode: u8) -> Option<u16> {
+            match opcode {
+                adc::CAP_CHANNELS_OPCODE => Some(4),
+                adc::CAP_SAMPLE_RATE_OPCODE => Some((self.params.sample_rate / 100).min(u16::MAX as u32) as u16),
+                adc::CAP_BAUD_OPCODE => Some((adc::DEFAULT_BAUD_RATE / 100) as u16),
+                adc::CAP_RESOLUTION_OPCODE => Some(1 << 15),  // 16-bit samples only
+                adc::CAP_FEATURES_OPCODE => Some(0),
+                _ => None,
+            }
+        }
+
+        /// The bytes of the next `frames` sample frames, without waiting for the clock.
+        #[cfg(test)]
+        pub fn next_frames(&mut self, frames: usize) -> Vec<u8> {
+            for _ in 0..frames {
+                self.push_frames();
+            }
+            self.pending.drain(..).collect()
+        }
+    }
+
+    impl Read for MockSource {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let rate = self.params.sample_rate as u64;
+            let due = (self.started.elapsed().as_secs_f64() * rate as f64) as u64;
+            // Don't replay a long stall as one burst
+            self.emitted = self.emitted.max(due.saturating_sub(rate));
+
+            while self.emitted < due && self.pending.len() < buf.len() {
+                self.push_frames();
+            }
+
+            if self.pending.is_empty() {
+                let next = Duration::from_secs_f64((self.emitted + 1) as f64 / rate as f64);
+                thread::sleep(next.saturating_sub(self.started.elapsed()).min(READ_TIMEOUT));
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "No mock samples due yet"));
+            }
+
+            let n = buf.len().min(self.pending.len());
+            for (dst, src) in buf.iter_mut().zip(self.pending.drain(..n)) {
+                *dst = src;
+            }
+            Ok(n)
+        }
+    }
+
+    impl Write for MockSource {
+        /// Answers capability queries as firmware would; other commands are ignored.
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            for frame in buf.windows(3).filter(|f| f[0] == 0xAA && f[2] == 0x55) {
+                if let Some(value) = self.capability(frame[1]) {
+                    let [hi, lo] = value.to_be_bytes();
+                    self.pending.extend([0xAA, 0x80 | frame[1], hi, lo, 0x55]);
+                }
+            }
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl DataSource for MockSource {
+        fn description(&self) -> String {
+            format!("mock device (seed {})", self.params.seed)
+        }
+    }
+}
+
+mod debug_log {
+    use std::fs::File;
+    use std::io::{self, Write};
+    use std::sync::{Mutex, OnceLock};
+    use tracing_subscriber::{fmt, layer::SubscriberExt, reload, util::SubscriberInitExt, EnvFilter, Registry};
+
+    const DEFAULT_FILTER: &str = "info";
+    const PARSER_DEBUG_FILTER: &str = "info,oscilloscopegui_lib=debug";
+
+    static FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+    static CURRENT: Mutex<String> = Mutex::new(String::new());
+    static TRACE_FILE: Mutex<Option<File>> = Mutex::new(None);
+
+    /// Writes every event to stderr and, when one is open, the trace file.
+    struct TeeWriter;
+
+    impl Write for TeeWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let _ = io::stderr().write_all(buf);
+            if let Ok(mut file) = TRACE_FILE.lock() {
+                if let Some(file) = file.as_mut() {
+                    file.write_all(buf)?;
+                }
+            }
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            if let Ok(mut file) = TRACE_FILE.lock() {
+                if let Some(file) = file.as_mut() {
+                    file.flush()?;
+                }
+            }
+            io::stderr().flush()
+        }
+    }
+
+    /// Installs the subscriber, taking the initial filter from `RUST_LOG` when set.
+    pub fn init() {
+        let initial = std::env::var("RUST_LOG").unwrap_or_else(|_| DEFAULT_FILTER.to_string());
+        let filter = EnvFilter::try_new(&initial).unwrap_or_else(|_| EnvFilter::new(DEFAULT_FILTER));
+        let (filter, handle) = reload::Layer::new(filter);
+        let installed = tracing_subscriber::registry()
+            .with(filter)
+            .with(fmt::layer().with_writer(|| TeeWriter).with_ansi(false))
+            .try_init()
+            .is_ok();
+        if installed && FILTER.set(handle).is_ok() {
+            if let Ok(mut current) = CURRENT.lock() {
+                *current = initial;
+            }
+        }
+    }
+
+    /// Replaces the active filter, e.g. `"debug"` or `"info,oscilloscopegui_lib::adc=trace"`.
+    pub fn set_level(directives: &str) -> Result<(), String> {
+        let filter = EnvFilter::try_new(directives)
+            .map_err(|e| format!("Invalid log filter '{}': {}", directives, e))?;
+        FILTER.get()
+            .ok_or_else(|| "Tracing not initialized".to_string())?
+            .reload(filter)
+            .map_err(|e| format!("Failed to set log filter: {}", e))?;
+        *CURRENT.lock().map_err(|_| "Failed to lock log filter".to_string())? = directives.to_string();
+        Ok(())
+    }
+
+    pub fn level() -> String {
+        CURRENT.lock().map(|current| current.clone()).unwrap_or_default()
+    }
+
+    /// Also writes events to `path`, or stops doing so with `None`.
+    pub fn set_trace_file(path: Option<&str>) -> Result<(), String> {
+        let file = path
+            .map(|path| File::create(path).map_err(|e| format!("Failed to create trace file {}: {}", path, e)))
+            .transpose()?;
+        *TRACE_FILE.lock().map_err(|_| "Failed to lock trace file".to_string())? = file;
+        Ok(())
+    }
+
+    /// Turns on the parser's debug events: byte counts, sample summaries and resyncs.
+    pub fn set_enabled(enabled: bool) {
+        let _ = set_level(if enabled { PARSER_DEBUG_FILTER } else { DEFAULT_FILTER });
+    }
+
+    pub fn is_enabled() -> bool {
+        tracing::enabled!(target: "oscilloscopegui_lib::adc", tracing::Level::DEBUG)
+    }
+}
+
+static BUFFER: OnceLock<Arc<Mutex<Vec<String>>>> = OnceLock::new();
+static ADC_PROCESSOR: OnceLock<Arc<Mutex<adc::PacketProcessor>>> = OnceLock::new();
+static LOGGER: OnceLock<Arc<logger::Logger>> = OnceLock::new();
+static APP_HANDLE: OnceLock<tauri::AppHandle> = OnceLock::new();
+static SAMPLE_CHANNEL: Mutex<Option<tauri::ipc::Channel>> = Mutex::new(None);  // For binary streaming
+static MEASUREMENT_LOG: Mutex<Option<mpsc::Sender<()>>> = Mutex::new(None);  // Dropping it stops the log thread
+static PIPE_OUTPUT: Mutex<Option<logger::PipeOutput>> = Mutex::new(None);
+
+
+const BATCH_QUEUE_LEN: usize = 64;  // Parsed batches waiting for the output thread
+const AUTO_RANGE_PASSES: usize = 3;
+const AUTO_RANGE_WINDOW: usize = 1024;  // Newest samples the first auto-range pass looks at
+const AUTO_RANGE_SETTLE: Duration = Duration::from_millis(100);  // Time for fresh samples between passes
+const MIN_MEASUREMENT_LOG_INTERVAL_MS: u64 = 10;
+const DEFAULT_GROUP_DELAY_FFT: usize = 256;
+
+/// Everything one parsed batch produces, passed from the reader to the output thread.
+///
+/// Building this is the only work done under the processor lock on the hot
+/// path; display formatting, log writes and event emission happen after the
+/// lock is released, so commands don't wait behind file or IPC work.
+///
+/// This is an interim step short of a reader-owned processor: commands and the
+/// reader still share `Mutex<PacketProcessor>` and the reader locks it once per
+/// read. Replacing that with request/response messages to the reader is still
+/// to do, and no lock-contention numbers have been taken yet.
+struct BatchOutput {
+    samples: Vec<adc::AdcSample>,           // DC-coupled samples, for logging
+    display: Vec<adc::AdcSample>,           // From `take_display_samples`, minus the low-latency channel, with display gain
+    clipped: Vec<bool>,                     // Parallel to `display`: gained past the screen edge
+    alarms: Vec<adc::AlarmEvent>,
+    resync_warning: Option<String>,
+    units: [adc::ChannelUnit; 4],
+    diff_slot: u8,                          // Channel whose trace the differential line takes over
+    markers: Vec<adc::Marker>,
+    display_fill: adc::DisplayFill,
+    encoding: adc::StreamEncoding,
+    per_channel_events: bool,
+}
+
+/// Why the reader loop gave up on the current connection.
+enum AcquisitionError {
+    Open(transport::OpenError),
+    Stream(String),
+}
+
+impl From<transport::OpenError> for AcquisitionError {
+    fn from(e: transport::OpenError) -> Self {
+        AcquisitionError::Open(e)
+    }
+}
+
+impl From<String> for AcquisitionError {
+    fn from(message: String) -> Self {
+        AcquisitionError::Stream(message)
+    }
+}
+
+impl std::fmt::Display for AcquisitionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AcquisitionError::Open(e) => write!(f, "{}", e),
+            AcquisitionError::Stream(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+/// Payload of the `serial-error` event.
+#[derive(Debug, Clone, Serialize)]
+struct ErrorEvent {
+    kind: &'static str,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retry: Option<u32>,  // Consecutive failed connection attempts
+}
+
+/// Sends an event to the frontend; a no-op until the app has finished setup.
+fn emit_event<S: Serialize + Clone>(event: &str, payload: S) {
+    if let Some(app) = APP_HANDLE.get() {
+        let _ = app.emit(event, payload);
+    }
+}
+
+/// Logs an acquisition error and forwards it to the frontend.
+fn emit_error(kind: &'static str, message: String) {
+    tracing::error!("{}", message);
+    emit_event("serial-error", ErrorEvent { kind, message, retry: None });
+}
+
+/// Like `emit_error`, for a failure the reader is about to retry.
+fn emit_retry_error(kind: &'static str, message: String, retry: u32) {
+    tracing::warn!("{}", message);
+    emit_event("serial-error", ErrorEvent { kind, message, retry: Some(retry) });
+}
+
+#[cfg_attr(mobile, tauri::mobile_entry_point)]
+pub fn run() {
+    debug_log::init();
+    let buffer = Arc::new(Mutex::new(Vec::new()));
+    let adc_processor = Arc::new(Mutex::new(adc::PacketProcessor::new()));
+    let logger = Arc::new(logger::Logger::new());
+    
+    let _ = BUFFER.set(buffer.clone());
+    let _ = ADC_PROCESSOR.set(adc_processor.clone());
+    let _ = LOGGER.set(logger.clone());
+
+    // Bounded so a stalled log write slows the reader instead of growing memory
+    let (output, batches) = mpsc::sync_channel(BATCH_QUEUE_LEN);
+    thread::spawn(move || {
+        let _span = tracing::info_span!("output").entered();
+        run_output(batches, buffer, logger, 1024)
+    });
+
+    thread::spawn(move || {
+        let _span = tracing::info_span!("reader").entered();
+        // Failed opens back off; any successful open starts the count again
+        let mut retry: u32 = 0;
+        loop {
+            let result = read_serial_into_buffer(&adc_processor, &output);
+            let policy = match lock_ranked(&adc_processor, LockRank::Processor) {
+                Ok(mut processor) => {
+                    processor.set_connection(None);
+                    if let Err(e) = &result {
+                        processor.set_last_error(e.to_string());
+                    }
+                    processor.retry_policy()
+                }
+                Err(_) => transport::RetryPolicy::default(),
+            };
+
+            let (kind, message) = match result {
+                Ok(()) => {
+                    retry = 0;
+                    continue;
+                }
+                Err(AcquisitionError::Open(e)) => {
+                    let kind = if e.is_unavailable() { "unavailable" } else { "connection" };
+                    (kind, e.to_string())
+                }
+                Err(AcquisitionError::Stream(message)) => {
+                    retry = 0;
+                    ("connection", message)
+                }
+            };
+            retry = retry.saturating_add(1);
+            let delay = policy.delay(retry);
+            emit_retry_error(kind, format!(
+                "Acquisition error: {} (retry {} in {:.1} s)",
+                message, retry, delay.as_secs_f32()
+            ), retry);
+            thread::sleep(delay);
+        }
+    });
+
+    tauri::Builder::default()
+        .setup(|app| {
+            let _ = APP_HANDLE.set(app.handle().clone());
+            Ok(())
+        })
+        .invoke_handler(tauri::generate_handler![
+            get_serial_data,
+            toggle_log,
+            start_measurement_log,
+            stop_measurement_log,
+            start_pipe_output,
+            stop_pipe_output,
+            set_active_channels,
+            configure_trigger,
+            auto_detect_framing,
+            measure_thd,
+            get_resync_stats,
+            detect_packet_size,
+            suggest_baud,
+            set_differential,
+            set_calibration,
+            set_channel_lut,
+            get_channel_lut,
+            set_baud_rate,
+            apply_config,
+            get_full_config,
+            save_preset,
+            load_preset,
+            list_presets,
+            auto_range,
+            set_volts_per_div,
+            get_scope_view,
+            export_session,
+            import_session,
+            set_display_gain,
+            set_voltage_alarm,
+            clear_voltage_alarm,
+            set_low_latency_channel,
+            cross_correlate,
+            autocorrelate,
+            set_fft_window,
+            compute_fft,
+            capture_noise_floor,
+            clear_noise_floor,
+            set_coupling,
+            get_coupling,
+            get_trigger_status,
+            get_last_trigger_info,
+            set_backpressure,
+            get_buffer_stats,
+            get_throughput,
+            set_expected_sample_rate,
+            get_rate_health,
+            set_display_throttle,
+            set_stream_encoding,
+            subscribe_samples,
+            set_per_channel_events,
+            set_timestamp_clock,
+            get_timestamp_clock,
+            set_transport,
+            get_transport,
+            set_channel_skew,
+            integrate,
+            get_derivative_samples,
+            get_resampled_samples,
+            set_reference,
+            clear_reference,
+            get_references,
+            get_reference_difference,
+            set_deadband,
+            query_device,
+            validate_config,
+            get_device_capabilities,
+            set_trigger_level_raw,
+            set_trigger_coupling,
+            set_channel_trigger,
+            set_channel_trigger_enabled,
+            set_trigger_filter,
+            set_display_filter,
+            heartbeat,
+            set_heartbeat_timeout,
+            set_packet_timeout,
+            set_max_packets_per_call,
+            get_raw_hex,
+            benchmark_parser,
+            capture_raw,
+            get_capture,
+            set_log_options,
+            get_dc_offset,
+            set_mock_params,
+            mock_set_waveform,
+            get_envelope_samples,
+            set_signed_samples,
+            set_measurement_gate,
+            set_capture_length,
+            configure_capture,
+            get_capture_config,
+            arm_capture,
+            get_capture_progress,
+            set_rearm_delay,
+            get_rearm_delay,
+            get_single_capture,
+            set_pattern_trigger,
+            set_mock_fallback,
+            measure_settling_time,
+            get_diagnostics_report,
+            set_channel_unit,
+            get_channel_units,
+            get_spectrogram,
+            measure_group_delay,
+            set_capture_duration,
+            get_capture_duration,
+            add_marker,
+            get_markers,
+            set_retry_policy,
+            measure_crest_factor,
+            measure_ripple,
+            get_all_frequencies,
+            read_instant,
+            measure_jitter,
+            measure_pulse_widths,
+            measure_am_depth,
+            measure_enob,
+            measure_crosstalk,
+            measure_power,
+            set_expected_channel_order,
+            pause_log,
+            resume_log,
+            list_devices_detailed,
+            select_device,
+            set_usb_filter,
+            calibrate_zero,
+            set_debug_logging,
+            get_debug_logging,
+            set_log_level,
+            get_log_level,
+            set_trace_file
+        ])
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|_, event| {
+            // Statics are never dropped, so flush the log here rather than in Drop
+            if let tauri::RunEvent::Exit = event {
+                if let Some(logger) = LOGGER.get() {
+                    if let Err(e) = logger.stop() {
+                        tracing::error!("{}", e);
+                    }
+                }
+            }
+        });
+}
+
+#[tauri::command]
+#[tracing::instrument(err)]
+fn toggle_log(enable: bool, overview_decimation: Option<usize>) -> Result<(), String> {
+    if let Some(logger) = LOGGER.get() {
+        if enable {
+            logger.start(overview_decimation)?;
+        } else {
+            logger.stop()?;
+        }
+    }
+    Ok(())
+}
+
+/// Appends frequency, Vpp, RMS and mean of every active channel to `path` every `interval_ms`.
+///
+/// Replaces a measurement log that is already running.
+#[tauri::command]
+#[tracing::instrument(err)]
+fn start_measurement_log(interval_ms: u64, path: String) -> Result<(), String> {
+    if interval_ms < MIN_MEASUREMENT_LOG_INTERVAL_MS {
+        return Err(format!("Measurement interval must be at least {} ms", MIN_MEASUREMENT_LOG_INTERVAL_MS));
+    }
+    let mut log = logger::MeasurementLog::create(std::path::Path::new(&path))?;
+    let (stop, stopped) = mpsc::channel::<()>();
+    *MEASUREMENT_LOG.lock().map_err(|_| "Failed to lock measurement log".to_string())? = Some(stop);
+
+    let interval = Duration::from_millis(interval_ms);
+    thread::spawn(move || {
+        let _span = tracing::info_span!("measurement_log").entered();
+        while let Err(mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+            if let Err(e) = measurement_rows().and_then(|rows| log.append(&rows)) {
+                emit_error("measurement_log", e);
+                break;
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Streams every decoded sample to `target` ("-" for stdout, else a path such as a
+/// named pipe) until `stop_pipe_output` or the reader goes away.
+#[tauri::command(async)]
+fn start_pipe_output(target: String, format: Option<logger::PipeFormat>) -> Result<(), String> {
+    if PIPE_OUTPUT.lock().map_err(|_| "Failed to lock pipe output".to_string())?.is_some() {
+        return Err("Pipe output is already running".to_string());
+    }
+    // Opened outside the lock, since a named pipe blocks here until it has a reader
+    let output = logger::PipeOutput::open(&target, format.unwrap_or_default())?;
+    let mut pipe = PIPE_OUTPUT.lock().map_err(|_| "Failed to lock pipe output".to_string())?;
+    if pipe.is_some() {
+        return Err("Pipe output is already running".to_string());
+    }
+    *pipe = Some(output);
+    Ok(())
+}
+
+#[tauri::command]
+fn stop_pipe_output() -> Result<(), String> {
+    match PIPE_OUTPUT.lock().map_err(|_| "Failed to lock pipe output".to_string())?.take() {
+        Some(_) => Ok(()),
+        None => Err("No pipe output is running".to_string()),
+    }
+}
+
+#[tauri::command]
+fn stop_measurement_log() -> Result<(), String> {
+    let mut log = MEASUREMENT_LOG.lock().map_err(|_| "Failed to lock measurement log".to_string())?;
+    match log.take() {
+        Some(_) => Ok(()),  // The thread sees the sender dropped and exits
+        None => Err("No measurement log is running".to_string()),
+    }
+}
+
+/// Measurements for the measurement log, one row per active channel with samples.
+fn measurement_rows() -> Result<Vec<logger::MeasurementRow>, String> {
+    let (channels, window) = {
+        let proc = lock_processor()?;
+        let active = proc.get_active_channels();
+        let mut channels = Vec::new();
+        for ch in (0..4u8).filter(|&ch| active[ch as usize]) {
+            channels.push((ch, gated_samples(&proc, ch)?));
+        }
+        (channels, proc.fft_window())
+    };
+    Ok(channels.into_iter()
+        .filter_map(|(channel, samples)| {
+            let timestamp = samples.last()?.timestamp;
+            let voltages: Vec<f32> = samples.iter().map(|s| s.voltage).collect();
+            let (min, max) = voltages.iter().fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), &v| (lo.min(v), hi.max(v)));
+            Some(logger::MeasurementRow {
+                timestamp,
+                channel,
+                frequency_hz: analysis::estimate_sample_rate(&samples)
+                    .and_then(|rate| analysis::dominant_frequency(&voltages, rate, window)),
+                vpp: max - min,
+                rms: analysis::rms(&voltages)?,
+                mean: voltages.iter().sum::<f32>() / voltages.len() as f32,
+            })
+        })
+        .collect())
+}
+
+/// Stops writing to the log without closing it, so `resume_log` appends to the same file.
+#[tauri::command]
+fn pause_log() -> Result<(), String> {
+    LOGGER.get()
+        .ok_or_else(|| "Logger not initialized".to_string())?
+        .pause()
+}
+
+#[tauri::command]
+fn resume_log() -> Result<(), String> {
+    LOGGER.get()
+        .ok_or_else(|| "Logger not initialized".to_string())?
+        .resume()
+}
+
+/// Prints parser debug output (byte counts, decoded samples, resyncs) to the console.
+#[tauri::command]
+fn set_debug_logging(enabled: bool) {
+    debug_log::set_enabled(enabled);
+}
+
+#[tauri::command]
+fn get_debug_logging() -> bool {
+    debug_log::is_enabled()
+}
+
+/// Sets the tracing filter, e.g. `"debug"` or `"info,oscilloscopegui_lib::adc=trace"`.
+#[tauri::command]
+fn set_log_level(filter: String) -> Result<(), String> {
+    debug_log::set_level(&filter)
+}
+
+#[tauri::command]
+fn get_log_level() -> String {
+    debug_log::level()
+}
+
+/// Copies console log output to `path`, or stops with None.
+#[tauri::command]
+fn set_trace_file(path: Option<String>) -> Result<(), String> {
+    debug_log::set_trace_file(path.as_deref())
+}
+
+#[tauri::command]
+fn set_log_options(options: logger::LogOptions) -> Result<(), String> {
+    LOGGER.get()
+        .ok_or_else(|| "Logger not initialized".to_string())?
+        .set_options(options)
+}
+
+#[tauri::command]
+fn get_serial_data() -> Result<Vec<String>, String> {
+    Ok(std::mem::take(&mut *lock_buffer()?))
+}
+
+#[tauri::command]
+fn set_active_channels(channels: [bool; 4]) -> Result<(), String> {
+    lock_processor()?.set_active_channels(channels);
+    Ok(())
+}
+
+#[tauri::command]
+#[tracing::instrument(err)]
+fn configure_trigger(config: adc::TriggerConfig) -> Result<(), String> {
+    lock_processor()?.configure_trigger(config)
+}
+
+/// Filters what the level trigger sees without touching the displayed signal.
+#[tauri::command]
+fn set_trigger_coupling(coupling: adc::TriggerCoupling) -> Result<(), String> {
+    let mut proc = lock_processor()?;
+    let config = adc::TriggerConfig {
+        coupling,
+        ..proc.get_trigger_config()
+    };
+    proc.configure_trigger(config)
+}
+
+/// Configures a channel's own level trigger, on or off, next to the main trigger.
+#[tauri::command]
+fn set_channel_trigger(channel: u8, trigger: adc::ChannelTrigger) -> Result<(), String> {
+    lock_processor()?.set_channel_trigger(channel, trigger)
+}
+
+/// Switches a channel's own trigger without touching its level or edge.
+#[tauri::command]
+fn set_channel_trigger_enabled(channel: u8, enabled: bool) -> Result<(), String> {
+    let mut proc = lock_processor()?;
+    let trigger = *proc.get_trigger_config().channel_triggers.get(channel as usize)
+        .ok_or_else(|| format!("Invalid channel: {}", channel))?;
+    proc.set_channel_trigger(channel, adc::ChannelTrigger { enabled, ..trigger })
+}
+
+/// Smooths what the trigger sees on `channel`, independently of the display filter.
+#[tauri::command]
+fn set_trigger_filter(channel: u8, filter: adc::SmoothingFilter) -> Result<(), String> {
+    lock_processor()?.set_trigger_filter(channel, filter)
+}
+
+/// Smooths the displayed trace of `channel`, independently of the trigger filter.
+#[tauri::command]
+fn set_display_filter(channel: u8, filter: adc::SmoothingFilter) -> Result<(), String> {
+    lock_processor()?.set_display_filter(channel, filter)
+}
+
+/// Switches the trigger to compare raw ADC codes; `configure_trigger` goes back to volts.
+#[tauri::command]
+fn set_trigger_level_raw(code: u16) -> Result<(), String> {
+    let mut proc = lock_processor()?;
+    let config = adc::TriggerConfig {
+        level_raw: Some(code),
+        ..proc.get_trigger_config()
+    };
+    proc.configure_trigger(config)
+}
+
+#[tauri::command]
+#[tracing::instrument(err)]
+fn set_pattern_trigger(pattern: Option<adc::PatternTrigger>) -> Result<(), String> {
+    lock_processor()?.set_pattern_trigger(pattern)
+}
+
+#[tauri::command]
+fn auto_detect_framing() -> Result<Option<adc::FramingGuess>, String> {
+    let bytes = lock_processor()?.recent_raw_bytes();
+    Ok(adc::auto_detect_framing(&bytes))
+}
+
+/// Hex dump of the most recent raw bytes, for diagnosing framing problems.
+/// Every byte read over the next `duration_ms`, exactly as it arrived.
+///
+/// Runs off the main thread since it waits out the whole window.
+#[tauri::command(async)]
+fn capture_raw(duration_ms: u64) -> Result<Vec<u8>, String> {
+    if duration_ms == 0 || duration_ms > adc::MAX_RAW_CAPTURE_MS {
+        return Err(format!("Capture duration must be from 1 to {} ms", adc::MAX_RAW_CAPTURE_MS));
+    }
+    lock_processor()?.start_raw_capture()?;
+    thread::sleep(Duration::from_millis(duration_ms));
+    Ok(lock_processor()?.finish_raw_capture())
+}
+
+/// Times the parser on generated packets, away from the live processor.
+#[tauri::command(async)]
+fn benchmark_parser(num_packets: usize) -> Result<adc::ParserBenchmark, String> {
+    adc::benchmark_parser(num_packets)
+}
+
+#[tauri::command]
+fn get_raw_hex(n: usize) -> Result<String, String> {
+    let bytes = lock_processor()?.last_raw_bytes(n);
+    Ok(adc::hex_dump(&bytes))
+}
+
+#[tauri::command]
+fn measure_thd(channel: u8) -> Result<analysis::Checked<analysis::ThdMeasurement>, String> {
+    checked_measurement(channel, |samples, window| {
+        let voltages: Vec<f32> = samples.iter().map(|s| s.voltage).collect();
+        analysis::thd(&voltages, analysis::estimate_sample_rate(samples)?, window)
+    })
+}
+
+/// Effective number of bits of the ADC, measured from a clean sine on the channel.
+#[tauri::command]
+fn measure_enob(channel: u8) -> Result<analysis::Checked<analysis::EnobMeasurement>, String> {
+    checked_measurement(channel, |samples, window| {
+        let voltages: Vec<f32> = samples.iter().map(|s| s.voltage).collect();
+        analysis::enob(&voltages, analysis::estimate_sample_rate(samples)?, window)
+    })
+}
+
+/// How strongly a tone on `driven_channel` leaks into `victim_channel`, in dB.
+#[tauri::command]
+fn measure_crosstalk(driven_channel: u8, victim_channel: u8) -> Result<analysis::Checked<f32>, String> {
+    let (driven, victim, window, clipped) = {
+        let proc = lock_processor()?;
+        let clipped = window_clipped(&proc, driven_channel)? || window_clipped(&proc, victim_channel)?;
+        (gated_samples(&proc, driven_channel)?, gated_samples(&proc, victim_channel)?, proc.fft_window(), clipped)
+    };
+    let driven: Vec<f32> = driven.iter().map(|s| s.voltage).collect();
+    let victim: Vec<f32> = victim.iter().map(|s| s.voltage).collect();
+    Ok(analysis::Checked::new(analysis::crosstalk(&driven, &victim, window), clipped))
+}
+
+/// Average real power from a voltage channel and a current channel, in watts.
+#[tauri::command]
+fn measure_power(v_channel: u8, i_channel: u8) -> Result<analysis::Checked<f32>, String> {
+    let proc = lock_processor()?;
+    let units = proc.get_channel_units();
+    let unit = |ch: u8| units.get(ch as usize).map_or(adc::Unit::Volt, |u| u.unit.clone());
+    if unit(v_channel) != adc::Unit::Volt {
+        return Err(format!("Channel {} isn't measuring volts", v_channel + 1));
+    }
+    if i_channel > 3 || unit(i_channel) != adc::Unit::Amp {
+        return Err(format!("Channel {} isn't measuring amps", i_channel + 1));
+    }
+    let voltage = gated_samples(&proc, v_channel)?;
+    let current = gated_samples(&proc, i_channel)?;
+    let clipped = window_clipped(&proc, v_channel)? || window_clipped(&proc, i_channel)?;
+    Ok(analysis::Checked::new(analysis::average_power(&voltage, &current), clipped))
+}
+
+#[tauri::command]
+fn set_measurement_gate(start_fraction: f32, end_fraction: f32) -> Result<(), String> {
+    lock_processor()?.set_measurement_gate(start_fraction, end_fraction)
+}
+
+/// Time for a step on the channel to stay within `tolerance_pct` of the step height.
+#[tauri::command]
+fn measure_settling_time(channel: u8, tolerance_pct: f32) -> Result<analysis::Checked<analysis::SettlingTime>, String> {
+    if !tolerance_pct.is_finite() || tolerance_pct <= 0.0 {
+        return Err(format!("Tolerance must be a positive percentage, got {}", tolerance_pct));
+    }
+    checked_measurement(channel, |samples, _| analysis::settling_time(samples, tolerance_pct))
+}
+
+/// Average high and low times on a channel, split at `threshold` or its mid-level.
+#[tauri::command]
+fn measure_pulse_widths(channel: u8, threshold: Option<f32>) -> Result<analysis::Checked<analysis::PulseWidths>, String> {
+    if threshold.is_some_and(|t| !t.is_finite()) {
+        return Err("Pulse threshold must be finite".to_string());
+    }
+    checked_measurement(channel, |samples, _| analysis::pulse_widths(samples, threshold))
+}
+
+/// Standard deviation and spread of the channel's cycle periods.
+#[tauri::command]
+fn measure_jitter(channel: u8) -> Result<analysis::Checked<analysis::Jitter>, String> {
+    checked_measurement(channel, |samples, _| analysis::jitter(samples))
+}
+
+/// Dominant FFT frequency of every active channel, gathered under one lock.
+///
+/// Inactive channels and ones without a clear peak are None.
+#[tauri::command]
+fn get_all_frequencies() -> Result<[Option<f32>; 4], String> {
+    let (channels, window) = {
+        let proc = lock_processor()?;
+        let active = proc.get_active_channels();
+        let mut channels: [Option<Vec<adc::AdcSample>>; 4] = Default::default();
+        for ch in (0..4).filter(|&ch| active[ch]) {
+            channels[ch] = Some(gated_samples(&proc, ch as u8)?);
+        }
+        (channels, proc.fft_window())
+    };
+    // The FFTs run after the lock is released so acquisition isn't held up
+    Ok(channels.map(|samples| {
+        let samples = samples?;
+        let voltages: Vec<f32> = samples.iter().map(|s| s.voltage).collect();
+        analysis::dominant_frequency(&voltages, analysis::estimate_sample_rate(&samples)?, window)
+    }))
+}
+
+/// Multimeter-style reading: the latest sample on each active channel.
+#[tauri::command]
+fn read_instant() -> Result<[Option<adc::AdcSample>; 4], String> {
+    Ok(lock_processor()?.latest_samples())
+}
+
+#[tauri::command]
+fn measure_crest_factor(channel: u8) -> Result<analysis::Checked<f32>, String> {
+    checked_measurement(channel, |samples, _| {
+        let voltages: Vec<f32> = samples.iter().map(|s| s.voltage).collect();
+        analysis::crest_factor(&voltages)
+    })
+}
+
+/// Ripple on a DC level, e.g. a supply rail, over the measurement window.
+#[tauri::command]
+fn measure_ripple(channel: u8) -> Result<analysis::Checked<analysis::Ripple>, String> {
+    checked_measurement(channel, |samples, _| {
+        let voltages: Vec<f32> = samples.iter().map(|s| s.voltage).collect();
+        analysis::ripple(&voltages)
+    })
+}
+
+#[tauri::command]
+fn set_fft_window(window: analysis::WindowFn) -> Result<(), String> {
+    lock_processor()?.set_fft_window(window);
+    Ok(())
+}
+
+/// Amplitude spectrum of a channel, using the configured window unless one is given.
+///
+/// With `subtract_noise_floor` the spectrum from `capture_noise_floor` is removed.
+/// A stored floor that no longer matches the FFT size is dropped and reported.
+#[tauri::command]
+fn compute_fft(
+    channel: u8,
+    window: Option<analysis::WindowFn>,
+    subtract_noise_floor: Option<bool>,
+) -> Result<Option<analysis::Spectrum>, String> {
+    let Some(spectrum) = channel_spectrum(channel, window)? else {
+        return Ok(None);
+    };
+    if !subtract_noise_floor.unwrap_or(false) {
+        return Ok(Some(spectrum));
+    }
+    let mut proc = lock_processor()?;
+    let floor = proc.noise_floor(channel)
+        .ok_or_else(|| format!("No noise floor captured for channel {}", channel))?;
+    match analysis::subtract_spectrum(&spectrum, floor) {
+        Some(spectrum) => Ok(Some(spectrum)),
+        None => {
+            proc.set_noise_floor(channel, None)?;
+            Err(format!("Channel {} noise floor was taken with a different FFT size or window; capture it again", channel))
+        }
+    }
+}
+
+/// Stores the channel's current spectrum, taken with no signal applied, as its noise floor.
+#[tauri::command]
+fn capture_noise_floor(channel: u8, window: Option<analysis::WindowFn>) -> Result<(), String> {
+    let spectrum = channel_spectrum(channel, window)?
+        .ok_or_else(|| format!("Not enough samples on channel {} for a noise floor", channel))?;
+    lock_processor()?.set_noise_floor(channel, Some(spectrum))
+}
+
+#[tauri::command]
+fn clear_noise_floor(channel: u8) -> Result<(), String> {
+    lock_processor()?.set_noise_floor(channel, None)
+}
+
+/// Amplitude spectrum of the channel's measurement window.
+fn channel_spectrum(channel: u8, window: Option<analysis::WindowFn>) -> Result<Option<analysis::Spectrum>, String> {
+    let samples = measurement_samples(channel)?;
+    let window = match window {
+        Some(window) => window,
+        None => lock_processor()?.fft_window(),
+    };
+    let Some(rate) = analysis::estimate_sample_rate(&samples) else {
+        return Ok(None);
+    };
+
+    let voltages: Vec<f32> = samples.iter().map(|s| s.voltage).collect();
+    let magnitudes = analysis::magnitude_spectrum(&voltages, window);
+    if magnitudes.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(analysis::Spectrum {
+        bin_hz: rate / (2 * magnitudes.len()) as f32,
+        magnitudes,
+        window,
+    }))
+}
+
+/// Annotates the stream at the current time; it also goes to the log and a `marker` event.
+#[tauri::command]
+fn add_marker(label: String) -> Result<adc::Marker, String> {
+    lock_processor()?.add_marker(&label)
+}
+
+#[tauri::command]
+fn get_markers() -> Result<Vec<adc::Marker>, String> {
+    Ok(lock_processor()?.get_markers())
+}
+
+/// Everything worth attaching to a bug report, in one read-only snapshot.
+#[tauri::command]
+fn get_diagnostics_report() -> Result<adc::DiagnosticsReport, String> {
+    Ok(lock_processor()?.get_diagnostics_report())
+}
+
+/// Rolling FFT of the channel's recent samples for waterfall displays.
+#[tauri::command]
+fn get_spectrogram(channel: u8, fft_size: usize, num_frames: usize) -> Result<Option<analysis::Spectrogram>, String> {
+    if !fft_size.is_power_of_two() || !(8..=analysis::MAX_FFT_SIZE).contains(&fft_size) {
+        return Err(format!("FFT size must be a power of two from 8 to {}, got {}", analysis::MAX_FFT_SIZE, fft_size));
+    }
+    let samples = channel_samples(channel)?;
+    let window = lock_processor()?.fft_window();
+    let Some(rate) = analysis::estimate_sample_rate(&samples) else {
+        return Ok(None);
+    };
+
+    let voltages: Vec<f32> = samples.iter().map(|s| s.voltage).collect();
+    let frames = analysis::spectrogram(&voltages, fft_size, num_frames, window);
+    if frames.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(analysis::Spectrogram {
+        bin_hz: rate / fft_size as f32,
+        hop_s: analysis::spectrogram_hop(fft_size) as f32 / rate,
+        fft_size,
+        frames,
+        window,
+    }))
+}
+
+/// Group delay from `stimulus` (a frequency sweep) to `channel`, per FFT bin pair.
+#[tauri::command]
+fn measure_group_delay(channel: u8, stimulus: u8, fft_size: Option<usize>) -> Result<Vec<analysis::GroupDelayPoint>, String> {
+    let fft_size = fft_size.unwrap_or(DEFAULT_GROUP_DELAY_FFT);
+    if !fft_size.is_power_of_two() || !(8..=analysis::MAX_FFT_SIZE).contains(&fft_size) {
+        return Err(format!("FFT size must be a power of two from 8 to {}, got {}", analysis::MAX_FFT_SIZE, fft_size));
+    }
+    let (stimulus, response, window) = {
+        let proc = lock_processor()?;
+        (scaled_samples(&proc, stimulus)?, scaled_samples(&proc, channel)?, proc.fft_window())
+    };
+    let rate = analysis::estimate_sample_rate(&stimulus)
+        .ok_or_else(|| "Not enough samples on the stimulus channel".to_string())?;
+    let stimulus: Vec<f32> = stimulus.iter().map(|s| s.voltage).collect();
+    let response: Vec<f32> = response.iter().map(|s| s.voltage).collect();
+    analysis::group_delay(&stimulus, &response, rate, fft_size, window)
+}
+
+#[tauri::command]
+fn detect_packet_size() -> Result<adc::PacketSizes, String> {
+    Ok(lock_processor()?.packet_sizes())
+}
+
+#[tauri::command]
+fn get_resync_stats() -> Result<adc::ResyncStats, String> {
+    Ok(lock_processor()?.get_resync_stats())
+}
+
+#[tauri::command]
+fn suggest_baud() -> Result<Vec<u32>, String> {
+    let proc = lock_processor()?;
+    Ok(adc::suggest_baud(&proc.get_resync_stats(), proc.baud_rate()))
+}
+
+#[tauri::command]
+fn set_differential(a: u8, b: u8, enabled: bool) -> Result<(), String> {
+    lock_processor()?.set_differential(adc::DifferentialConfig {
+        enabled,
+        channel_a: a,
+        channel_b: b,
+    })
+}
+
+#[tauri::command]
+fn set_calibration(channel: u8, gain: f32, offset: f32) -> Result<(), String> {
+    lock_processor()?.set_calibration(channel, adc::Calibration { gain, offset })
+}
+
+/// Maps a nonlinear sensor's raw codes to physical values by interpolating `table`,
+/// clamped at its ends; None goes back to the channel's calibration.
+#[tauri::command]
+fn set_channel_lut(channel: u8, table: Option<Vec<(u16, f32)>>) -> Result<(), String> {
+    lock_processor()?.set_channel_lut(channel, table.as_deref())
+}
+
+#[tauri::command]
+fn get_channel_lut(channel: u8) -> Result<Option<Vec<(u16, f32)>>, String> {
+    Ok(lock_processor()?.channel_lut(channel))
+}
+
+/// Zeroes a grounded channel by folding its mean into the calibration offset.
+#[tauri::command]
+#[tracing::instrument(err)]
+fn calibrate_zero(channel: u8) -> Result<f32, String> {
+    lock_processor()?.calibrate_zero(channel)
+}
+
+#[tauri::command]
+#[tracing::instrument(err)]
+fn set_baud_rate(baud_rate: u32) -> Result<(), String> {
+    lock_processor()?.set_baud_rate(baud_rate)
+}
+
+#[tauri::command]
+#[tracing::instrument(err)]
+fn apply_config(config: adc::FullConfig) -> Result<(), String> {
+    lock_processor()?.apply_config(config)
+}
+
+#[tauri::command]
+fn get_full_config() -> Result<adc::FullConfig, String> {
+    Ok(lock_processor()?.get_full_config())
+}
+
+#[tauri::command]
+fn save_preset(name: String) -> Result<(), String> {
+    let config = lock_processor()?.get_full_config();
+    presets::save(&presets_dir()?, &name, &config)
+}
+
+#[tauri::command]
+#[tracing::instrument(err)]
+fn load_preset(name: String) -> Result<(), String> {
+    let config = presets::load(&presets_dir()?, &name)?;
+    lock_processor()?.apply_config(config)
+}
+
+#[tauri::command]
+fn list_presets() -> Result<Vec<String>, String> {
+    presets::list(&presets_dir()?)
+}
+
+/// Writes the config, buffered history, markers and measurements to one JSON file.
+#[tauri::command]
+fn export_session(path: String) -> Result<(), String> {
+    let (config, history, single_capture, markers, window) = {
+        let mut proc = lock_processor()?;
+        let history = (0..=adc::DIFF_CHANNEL).map(|channel| proc.channel_samples(channel)).collect();
+        (proc.get_full_config(), history, proc.single_capture().to_vec(), proc.get_markers(), proc.fft_window())
+    };
+
+    let mut measurements = Vec::new();
+    for channel in (0..4u8).filter(|&ch| config.active_channels[ch as usize]) {
+        let samples = measurement_samples(channel)?;
+        let voltages: Vec<f32> = samples.iter().map(|s| s.voltage).collect();
+        let sample_rate = analysis::estimate_sample_rate(&samples);
+        measurements.push(session::ChannelMeasurements {
+            channel,
+            sample_rate,
+            thd: sample_rate.and_then(|rate| analysis::thd(&voltages, rate, window)),
+            crest_factor: analysis::crest_factor(&voltages),
+        });
+    }
+
+    let archive = session::SessionArchive {
+        version: session::VERSION,
+        exported_at: adc::now_nanos(),
+        config,
+        history,
+        single_capture,
+        markers,
+        measurements,
+    };
+    session::save(std::path::Path::new(&path), &archive)
+}
+
+/// Loads an exported session for offline review, keeping the current transport.
+///
+/// Returns the measurements recorded at export time.
+#[tauri::command]
+#[tracing::instrument(err)]
+fn import_session(path: String) -> Result<Vec<session::ChannelMeasurements>, String> {
+    let archive = session::load(std::path::Path::new(&path))?;
+    let mut proc = lock_processor()?;
+    // The archive's transport may name hardware this machine doesn't have
+    let config = adc::FullConfig { transport: proc.transport().clone(), ..archive.config };
+    proc.apply_config(config)?;
+    proc.restore_session(archive.history, archive.single_capture, archive.markers)?;
+    Ok(archive.measurements)
+}
+
+fn presets_dir() -> Result<std::path::PathBuf, String> {
+    let app = APP_HANDLE.get().ok_or("App not initialized")?;
+    let config_dir = app.path()
+        .app_config_dir()
+        .map_err(|e| format!("Failed to resolve config directory: {}", e))?;
+    Ok(config_dir.join("presets"))
+}
+
+/// Digitally magnifies a channel on screen; measurements and the log keep the real values.
+#[tauri::command]
+fn set_display_gain(channel: u8, factor: f32) -> Result<(), String> {
+    lock_processor()?.set_display_gain(channel, factor)
+}
+
+#[tauri::command]
+fn set_voltage_alarm(channel: u8, threshold: f32, above: bool) -> Result<(), String> {
+    lock_processor()?.set_voltage_alarm(channel, Some(adc::VoltageAlarm { threshold, above }))
+}
+
+#[tauri::command]
+fn clear_voltage_alarm(channel: u8) -> Result<(), String> {
+    lock_processor()?.set_voltage_alarm(channel, None)
+}
+
+#[tauri::command]
+fn set_low_latency_channel(channel: Option<u8>) -> Result<(), String> {
+    lock_processor()?.set_low_latency_channel(channel)
+}
+
+#[tauri::command]
+fn cross_correlate(a: u8, b: u8, max_lag: usize) -> Result<Option<analysis::CrossCorrelation>, String> {
+    let samples_a = measurement_samples(a)?;
+    let samples_b = measurement_samples(b)?;
+    let volts_a: Vec<f32> = samples_a.iter().map(|s| s.voltage).collect();
+    let volts_b: Vec<f32> = samples_b.iter().map(|s| s.voltage).collect();
+
+    Ok(analysis::cross_correlate(&volts_a, &volts_b, max_lag).map(|mut result| {
+        result.delay_s = analysis::estimate_sample_rate(&samples_a)
+            .map(|rate| result.peak_lag as f32 / rate);
+        result
+    }))
+}
+
+/// Autocorrelation of a channel and the dominant period it finds.
+#[tauri::command]
+fn autocorrelate(channel: u8, max_lag: usize) -> Result<Option<analysis::Autocorrelation>, String> {
+    let samples = measurement_samples(channel)?;
+    let volts: Vec<f32> = samples.iter().map(|s| s.voltage).collect();
+    Ok(analysis::autocorrelate(&volts, max_lag, analysis::estimate_sample_rate(&samples)))
+}
+
+#[tauri::command]
+fn set_coupling(channel: u8, coupling: adc::Coupling) -> Result<(), String> {
+    lock_processor()?.set_coupling(channel, coupling)
+}
+
+#[tauri::command]
+fn get_coupling() -> Result<[adc::Coupling; 4], String> {
+    Ok(lock_processor()?.get_coupling())
+}
+
+#[tauri::command]
+fn set_channel_unit(channel: u8, unit: adc::Unit, factor: f32) -> Result<(), String> {
+    lock_processor()?.set_channel_unit(channel, unit, factor)
+}
+
+#[tauri::command]
+fn get_channel_units() -> Result<[adc::ChannelUnit; 4], String> {
+    Ok(lock_processor()?.get_channel_units())
+}
+
+/// Channel order the firmware round-robins through, used to correct mislabeled samples.
+#[tauri::command]
+fn set_expected_channel_order(order: Option<Vec<u8>>) -> Result<(), String> {
+    lock_processor()?.set_expected_channel_order(order)
+}
+
+#[tauri::command]
+fn set_signed_samples(signed: bool) -> Result<(), String> {
+    lock_processor()?.set_signed_samples(signed);
+    Ok(())
+}
+
+#[tauri::command]
+fn get_dc_offset(channel: u8) -> Result<Option<f32>, String> {
+    lock_processor()?.get_dc_offset(channel)
+}
+
+#[tauri::command]
+fn get_trigger_status() -> Result<adc::TriggerStatus, String> {
+    Ok(lock_processor()?.get_trigger_status())
+}
+
+/// Channel, time, edge and source of the most recent trigger; None before any has fired.
+#[tauri::command]
+fn get_last_trigger_info() -> Result<Option<adc::TriggerInfo>, String> {
+    Ok(lock_processor()?.last_trigger_info())
+}
+
+#[tauri::command]
+fn set_backpressure(policy: adc::BackpressurePolicy, capacity: usize) -> Result<(), String> {
+    lock_processor()?.set_backpressure(policy, capacity)
+}
+
+#[tauri::command]
+fn get_buffer_stats() -> Result<adc::BufferStats, String> {
+    Ok(lock_processor()?.get_buffer_stats())
+}
+
+/// Bytes per second actually read, and how much of the baud rate that uses.
+#[tauri::command]
+fn get_throughput() -> Result<adc::Throughput, String> {
+    Ok(lock_processor()?.get_throughput())
+}
+
+/// Chooses between the JSON `samples` event and packed binary over `subscribe_samples`.
+#[tauri::command]
+fn set_expected_sample_rate(rate: Option<f32>) -> Result<(), String> {
+    lock_processor()?.set_expected_sample_rate(rate)
+}
+
+#[tauri::command]
+fn get_rate_health() -> Result<adc::RateHealth, String> {
+    Ok(lock_processor()?.get_rate_health())
+}
+
+#[tauri::command]
+fn set_stream_encoding(encoding: adc::StreamEncoding) -> Result<(), String> {
+    lock_processor()?.set_stream_encoding(encoding);
+    Ok(())
+}
+
+/// Registers the channel binary sample batches are sent on, replacing any earlier one.
+/// Emits JSON samples on `adc-samples-ch<n>` per channel (4 is the differential
+/// trace) instead of the combined `samples` event. Binary streaming is unaffected.
+#[tauri::command]
+fn set_per_channel_events(enabled: bool) -> Result<(), String> {
+    lock_processor()?.set_per_channel_events(enabled);
+    Ok(())
+}
+
+#[tauri::command]
+fn set_timestamp_clock(clock: adc::TimestampClock) -> Result<(), String> {
+    lock_processor()?.set_timestamp_clock(clock);
+    Ok(())
+}
+
+#[tauri::command]
+fn get_timestamp_clock() -> Result<adc::TimestampClock, String> {
+    Ok(lock_processor()?.timestamp_clock())
+}
+
+#[tauri::command]
+fn subscribe_samples(channel: tauri::ipc::Channel) -> Result<(), String> {
+    *SAMPLE_CHANNEL.lock().map_err(|_| "Failed to lock sample channel".to_string())? = Some(channel);
+    Ok(())
+}
+
+#[tauri::command]
+fn set_display_throttle(interval_ms: Option<u64>) -> Result<(), String> {
+    lock_processor()?.set_display_throttle(interval_ms.map(Duration::from_millis))
+}
+
+/// Switches between the serial port and a TCP stream; the reader reconnects right away.
+#[tauri::command]
+#[tracing::instrument(err)]
+fn set_transport(transport: transport::Transport) -> Result<(), String> {
+    lock_processor()?.set_transport(transport)
+}
+
+/// Serial ports with their USB product details, for choosing a device by name.
+#[tauri::command]
+fn list_devices_detailed() -> Result<Vec<transport::DeviceInfo>, String> {
+    transport::list_devices()
+}
+
+/// Reads from the USB device with this identity across reconnects, whatever its port
+/// name; `None` goes back to the first COM port.
+#[tauri::command]
+#[tracing::instrument(err)]
+fn select_device(id: Option<transport::DeviceId>) -> Result<(), String> {
+    lock_processor()?.set_transport(transport::Transport::Serial { device: id })
+}
+
+/// Opens the first port whose USB VID/PID match, on any platform; `None` goes back
+/// to the first COM port.
+#[tauri::command]
+fn set_usb_filter(filter: Option<(u16, u16)>) -> Result<(), String> {
+    let device = filter.map(|(vid, pid)| transport::DeviceId { vid, pid, serial_number: None });
+    lock_processor()?.set_transport(transport::Transport::Serial { device })
+}
+
+#[tauri::command]
+fn set_retry_policy(policy: transport::RetryPolicy) -> Result<(), String> {
+    lock_processor()?.set_retry_policy(policy)
+}
+
+#[tauri::command]
+fn set_mock_fallback(enabled: bool) -> Result<(), String> {
+    lock_processor()?.set_mock_fallback(enabled);
+    Ok(())
+}
+
+/// Switches to the simulated device with the given impairments (reconnects if already mocked).
+#[tauri::command]
+fn set_mock_params(params: transport::MockParams) -> Result<(), String> {
+    lock_processor()?.set_transport(transport::Transport::Mock(params))
+}
+
+/// Sets one channel's mock waveform, switching to the mock device if it isn't in use.
+#[tauri::command]
+fn mock_set_waveform(channel: u8, waveform: transport::MockWaveform) -> Result<(), String> {
+    if channel > 3 {
+        return Err(format!("Invalid channel: {}", channel));
+    }
+    let mut proc = lock_processor()?;
+    let mut params = match proc.transport() {
+        transport::Transport::Mock(params) => params.clone(),
+        _ => transport::MockParams::default(),
+    };
+    params.waveforms[channel as usize] = waveform;
+    proc.set_transport(transport::Transport::Mock(params))
+}
+
+#[tauri::command]
+fn get_transport() -> Result<transport::Transport, String> {
+    Ok(lock_processor()?.transport().clone())
+}
+
+#[tauri::command]
+fn set_channel_skew(channel: u8, nanoseconds: i64) -> Result<(), String> {
+    lock_processor()?.set_channel_skew(channel, nanoseconds)
+}
+
+/// Rectify-and-low-pass envelope of the channel's most recent `count` samples.
+///
+/// The cutoff defaults to 1% of the sample rate.
+#[tauri::command]
+fn get_envelope_samples(channel: u8, count: usize, cutoff_hz: Option<f32>) -> Result<Option<Vec<analysis::TracePoint>>, String> {
+    if cutoff_hz.is_some_and(|hz| !hz.is_finite() || hz <= 0.0) {
+        return Err("Envelope cutoff must be a positive frequency".to_string());
+    }
+    let samples = channel_samples(channel)?;
+    let recent = &samples[samples.len().saturating_sub(count)..];
+    let Some(rate) = analysis::estimate_sample_rate(recent) else {
+        return Ok(None);
+    };
+    Ok(analysis::envelope(recent, cutoff_hz.unwrap_or(rate * 0.01)))
+}
+
+/// AM depth of the channel; `cutoff_hz` defaults to 1% of the sample rate as for envelopes.
+#[tauri::command]
+fn measure_am_depth(channel: u8, cutoff_hz: Option<f32>) -> Result<analysis::Checked<f32>, String> {
+    if cutoff_hz.is_some_and(|hz| !hz.is_finite() || hz <= 0.0) {
+        return Err("Envelope cutoff must be a positive frequency".to_string());
+    }
+    checked_measurement(channel, |samples, _| {
+        let rate = analysis::estimate_sample_rate(samples)?;
+        analysis::am_depth(samples, cutoff_hz.unwrap_or(rate * 0.01))
+    })
+}
+
+#[tauri::command]
+fn integrate(channel: u8, start_ts: u128, end_ts: u128) -> Result<Option<analysis::Integral>, String> {
+    Ok(analysis::integrate(&channel_samples(channel)?, start_ts, end_ts))
+}
+
+/// Single-shot capture length in samples per channel; None returns to free-running.
+#[tauri::command]
+fn set_capture_length(length: Option<usize>) -> Result<(), String> {
+    lock_processor()?.set_capture_length(length)
+}
+
+/// Single-shot capture length as a time span, converted with the measured sample rate.
+#[tauri::command]
+fn set_capture_duration(microseconds: u64) -> Result<(), String> {
+    lock_processor()?.set_capture_duration(Duration::from_micros(microseconds))
+}
+
+#[tauri::command]
+fn get_capture_duration() -> Result<Option<u64>, String> {
+    Ok(lock_processor()?.get_capture_duration().map(|d| d.as_micros() as u64))
+}
+
+/// Dead time after a single-shot capture fills before the trigger can start another,
+/// measured in sample time. Zero (the default) re-arms immediately.
+#[tauri::command]
+fn set_rearm_delay(microseconds: u64) -> Result<(), String> {
+    lock_processor()?.set_rearm_delay(Duration::from_micros(microseconds));
+    Ok(())
+}
+
+#[tauri::command]
+fn get_rearm_delay() -> Result<u64, String> {
+    Ok(lock_processor()?.rearm_delay().as_micros() as u64)
+}
+
+#[tauri::command]
+fn arm_capture() -> Result<(), String> {
+    lock_processor()?.arm_capture();
+    Ok(())
+}
+
+#[tauri::command]
+fn get_capture_progress() -> Result<f32, String> {
+    Ok(lock_processor()?.get_capture_progress())
+}
+
+#[tauri::command]
+fn get_single_capture() -> Result<Vec<adc::AdcSample>, String> {
+    Ok(lock_processor()?.single_capture().to_vec())
+}
+
+#[tauri::command]
+fn configure_capture(config: adc::CaptureConfig) -> Result<(), String> {
+    lock_processor()?.configure_capture(config)
+}
+
+#[tauri::command]
+fn get_capture_config() -> Result<adc::CaptureConfig, String> {
+    Ok(lock_processor()?.capture_config())
+}
+
+/// Decimated capture around the newest trigger edge the buffered samples fully cover.
+///
+/// Length and pre-trigger default to the `configure_capture` geometry.
+#[tauri::command]
+fn get_capture(channel: u8, length: Option<usize>, pre_trigger: Option<usize>, decimation: Option<usize>) -> Result<Option<analysis::Capture>, String> {
+    let (configured_length, configured_pre_trigger) = {
+        let proc = lock_processor()?;
+        (proc.capture_length(), proc.capture_config().pre_trigger)
+    };
+    let length = length.or(configured_length).ok_or("No capture length given or configured")?;
+    let pre_trigger = pre_trigger.unwrap_or(configured_pre_trigger);
+    let decimation = decimation.unwrap_or(1);
+    if decimation == 0 {
+        return Err("Decimation must be at least 1".to_string());
+    }
+    if pre_trigger >= length {
+        return Err(format!("Pre-trigger length {} must be less than capture length {}", pre_trigger, length));
+    }
+
+    let samples = channel_samples(channel)?;
+    let trigger_history = {
+        let proc = lock_processor()?;
+        proc.channel_samples(proc.get_trigger_config().channel)
+    };
+
+    // Level triggering marks every sample past the level; only the first of each run is an edge
+    Ok(trigger_history.windows(2)
+        .rev()
+        .filter(|w| w[1].is_trigger && !w[0].is_trigger)
+        .find_map(|w| analysis::capture(&samples, w[1].timestamp, length, pre_trigger, decimation)))
+}
+
+/// The newest `count` points of a channel, interpolated onto a grid at `rate` per second.
+#[tauri::command]
+fn get_resampled_samples(channel: u8, count: usize, rate: f32) -> Result<Option<analysis::Resampled>, String> {
+    if !rate.is_finite() || rate <= 0.0 {
+        return Err(format!("Resample rate must be positive, got {}", rate));
+    }
+    Ok(analysis::resample(&channel_samples(channel)?, count, rate))
+}
+
+/// dV/dt over the channel's most recent `count` samples, optionally smoothed first.
+#[tauri::command]
+fn get_derivative_samples(channel: u8, count: usize, smoothing: Option<usize>) -> Result<Vec<analysis::TracePoint>, String> {
+    let samples = channel_samples(channel)?;
+    let recent = &samples[samples.len().saturating_sub(count)..];
+    Ok(analysis::derivative(recent, smoothing.unwrap_or(0)))
+}
+
+/// Freezes the channel's buffered trace as its reference and sends the set to the frontend.
+#[tauri::command]
+fn set_reference(channel: u8) -> Result<analysis::ReferenceTrace, String> {
+    let reference = analysis::reference_trace(channel, &channel_samples(channel)?)
+        .ok_or_else(|| format!("No samples on channel {} to use as a reference", channel))?;
+    let references = {
+        let mut proc = lock_processor()?;
+        proc.set_reference(reference.clone())?;
+        proc.references()
+    };
+    emit_event("reference", references);
+    Ok(reference)
+}
+
+#[tauri::command]
+fn clear_reference() -> Result<(), String> {
+    lock_processor()?.clear_references();
+    emit_event("reference", Vec::<analysis::ReferenceTrace>::new());
+    Ok(())
+}
+
+#[tauri::command]
+fn get_references() -> Result<Vec<analysis::ReferenceTrace>, String> {
+    Ok(lock_processor()?.references())
+}
+
+/// Live data minus the channel's reference, for go/no-go comparison.
+#[tauri::command]
+fn get_reference_difference(channel: u8) -> Result<Option<Vec<analysis::TracePoint>>, String> {
+    let samples = channel_samples(channel)?;
+    let proc = lock_processor()?;
+    Ok(proc.reference(channel).and_then(|reference| analysis::reference_difference(&samples, reference)))
+}
+
+#[tauri::command]
+fn set_deadband(channel: u8, volts: f32) -> Result<(), String> {
+    lock_processor()?.set_deadband(channel, volts)
+}
+
+/// Keeps the device streaming while the heartbeat watchdog is enabled.
+#[tauri::command]
+fn heartbeat() -> Result<(), String> {
+    lock_processor()?.heartbeat()
+}
+
+/// How long a partial packet may wait for its remaining bytes; None waits forever.
+#[tauri::command]
+fn set_packet_timeout(timeout_ms: Option<u64>) -> Result<(), String> {
+    lock_processor()?.set_packet_timeout(timeout_ms.map(Duration::from_millis))
+}
+
+#[tauri::command]
+fn set_max_packets_per_call(max: Option<usize>) -> Result<(), String> {
+    lock_processor()?.set_max_packets_per_call(max)
+}
+
+#[tauri::command]
+fn set_heartbeat_timeout(timeout_ms: Option<u64>) -> Result<(), String> {
+    lock_processor()?.set_heartbeat_timeout(timeout_ms.map(Duration::from_millis))
+}
+
+/// Sends a firmware query and waits for its reply value.
+#[tauri::command]
+#[tracing::instrument(err)]
+fn query_device(opcode: u8, timeout_ms: Option<u64>) -> Result<u16, String> {
+    let (id, reply) = lock_processor()?.request(opcode)?;
+    let timeout = timeout_ms.map(Duration::from_millis).unwrap_or(adc::DEFAULT_REPLY_TIMEOUT);
+    reply.recv_timeout(timeout).map_err(|_| {
+        if let Ok(mut proc) = lock_processor() {
+            proc.cancel_request(opcode, id);
+        }
+        format!("No reply to opcode {:#04x} within {} ms", opcode, timeout.as_millis())
+    })
+}
+
+/// Lists what in `config` the connected device can't do, without applying any of it.
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+fn validate_config(config: adc::FullConfig) -> Result<Vec<String>, String> {
+    Ok(query_capabilities(adc::DEFAULT_REPLY_TIMEOUT)?.incompatibilities(&config))
+}
+
+/// Asks a newly connected device what it supports and records the answer.
+///
+/// Firmware without the capability queries just doesn't reply, which leaves the
+/// usual assumptions in place.
+fn negotiate_capabilities(connection_id: u64) {
+    let _span = tracing::info_span!("negotiate", connection_id).entered();
+    match query_capabilities(adc::DEFAULT_REPLY_TIMEOUT) {
+        Ok(capabilities) => {
+            let current = lock_processor()
+                .is_ok_and(|mut proc| proc.set_capabilities(connection_id, capabilities.clone()));
+            if current {
+                tracing::info!("Device capabilities: {:?}", capabilities);
+                emit_event("device-capabilities", capabilities);
+            }
+        }
+        Err(e) => tracing::info!("Device didn't report its capabilities: {}", e),
+    }
+}
+
+#[tauri::command]
+fn get_device_capabilities() -> Result<Option<adc::DeviceCapabilities>, String> {
+    Ok(lock_processor()?.capabilities())
+}
+
+/// Sends every capability query at once and waits up to `timeout` for all the replies.
+fn query_capabilities(timeout: Duration) -> Result<adc::DeviceCapabilities, String> {
+    let requests = {
+        let mut proc = lock_processor()?;
+        adc::CAPABILITY_OPCODES.iter()
+            .map(|&opcode| proc.request(opcode).map(|(id, reply)| (opcode, id, reply)))
+            .collect::<Result<Vec<_>, String>>()?
+    };
+    let deadline = Instant::now() + timeout;
+    let mut replies = [0; 5];
+    for (slot, (opcode, _, reply)) in replies.iter_mut().zip(&requests) {
+        match reply.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(value) => *slot = value,
+            Err(_) => {
+                if let Ok(mut proc) = lock_processor() {
+                    requests.iter().for_each(|&(opcode, id, _)| proc.cancel_request(opcode, id));
+                }
+                return Err(format!("No reply to capability query {:#04x} within {} ms", opcode, timeout.as_millis()));
+            }
+        }
+    }
+    Ok(adc::DeviceCapabilities::from_replies(replies))
+}
+
+// Lock ordering
+//
+// The processor and the display buffer are only locked through `lock_ranked`
+// (usually via `lock_processor` and `lock_buffer`), in `LockRank` order: a thread
+// holding the buffer never goes on to take the processor, and neither is taken
+// twice. The remaining mutexes (logger files and options, the sample channel,
+// the measurement log, debug_log's) are leaves: they're held briefly and nothing
+// else is locked under them. Debug builds check the order at runtime and panic
+// on an inversion instead of deadlocking some of the time.
+
+/// Position in the lock order; lower ranks are taken first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LockRank {
+    Processor = 0,
+    Buffer = 1,
+}
+
+thread_local! {
+    static HELD_RANKS: Cell<u8> = const { Cell::new(0) };  // Bit per LockRank held by this thread
+}
+
+/// A guard from `lock_ranked`, releasing its rank when dropped.
+struct RankedGuard<'a, T> {
+    guard: MutexGuard<'a, T>,
+    rank: LockRank,
+}
+
+impl<T> Deref for RankedGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for RankedGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T> Drop for RankedGuard<'_, T> {
+    fn drop(&mut self) {
+        HELD_RANKS.with(|held| held.set(held.get() & !(1 << self.rank as u8)));
+    }
+}
+
+/// Locks `mutex` as `rank`, checking in debug builds that nothing at or after it is held.
+fn lock_ranked<T>(mutex: &Mutex<T>, rank: LockRank) -> Result<RankedGuard<'_, T>, String> {
+    HELD_RANKS.with(|held| {
+        let later = held.get() >> rank as u8;
+        debug_assert!(later == 0, "lock order violated: taking {:?} while holding {:#b}", rank, held.get());
+        held.set(held.get() | 1 << rank as u8);
+    });
+    match mutex.lock() {
+        Ok(guard) => Ok(RankedGuard { guard, rank }),
+        Err(_) => {
+            HELD_RANKS.with(|held| held.set(held.get() & !(1 << rank as u8)));
+            Err(match rank {
+                LockRank::Processor => "Failed to lock ADC processor".to_string(),
+                LockRank::Buffer => "Failed to lock buffer".to_string(),
+            })
+        }
+    }
+}
+
+fn lock_processor() -> Result<RankedGuard<'static, adc::PacketProcessor>, String> {
+    let processor = ADC_PROCESSOR.get().ok_or_else(|| "ADC processor not initialized".to_string())?;
+    lock_ranked(processor, LockRank::Processor)
+}
+
+fn lock_buffer() -> Result<RankedGuard<'static, Vec<String>>, String> {
+    let buffer = BUFFER.get().ok_or_else(|| "Buffer not initialized".to_string())?;
+    lock_ranked(buffer, LockRank::Buffer)
+}
+
+/// Samples for a physical channel or, while differential mode is on, the virtual one.
+///
+/// Values are scaled into the channel's unit, so `voltage` may hold amps or another
+/// quantity and every measurement built on this reports in that unit.
+fn channel_samples(channel: u8) -> Result<Vec<adc::AdcSample>, String> {
+    scaled_samples(&*lock_processor()?, channel)
+}
+
+/// `channel_samples` for a caller already holding the processor lock.
+fn scaled_samples(proc: &adc::PacketProcessor, channel: u8) -> Result<Vec<adc::AdcSample>, String> {
+    let is_virtual = channel == adc::DIFF_CHANNEL && proc.get_differential().enabled;
+    if channel > 3 && !is_virtual {
+        return Err(format!("Invalid channel: {}", channel));
+    }
+    let mut samples = proc.channel_samples(channel);
+    let factor = proc.unit_factor(channel);
+    if factor != 1.0 {
+        samples.iter_mut().for_each(|s| s.voltage *= factor);
+    }
+    Ok(samples)
+}
+
+/// Picks the smallest volts/div that shows the channel without clipping.
+///
+/// Re-checks against fresh samples until two passes agree, so a range change made
+/// while the signal was still moving settles before it's reported.
+#[tauri::command]
+fn auto_range(channel: u8) -> Result<f32, String> {
+    if channel > 3 {
+        return Err(format!("Invalid channel: {}", channel));
+    }
+    let mut chosen = None;
+    let mut newest = None;
+    for _ in 0..AUTO_RANGE_PASSES {
+        let samples = channel_samples(channel)?;
+        let fresh: Vec<f32> = match newest {
+            None => samples.iter().rev().take(AUTO_RANGE_WINDOW).map(|s| s.voltage).collect(),
+            Some(after) => samples.iter().filter(|s| s.timestamp > after).map(|s| s.voltage).collect(),
+        };
+        if fresh.is_empty() {
+            break;  // Nothing new arriving, so go with what we have
+        }
+        newest = samples.last().map(|s| s.timestamp);
+        let min = fresh.iter().copied().fold(f32::INFINITY, f32::min);
+        let max = fresh.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        let range = adc::fit_volts_per_div(min, max);
+        if chosen == Some(range) {
+            break;
+        }
+        chosen = Some(range);
+        thread::sleep(AUTO_RANGE_SETTLE);
+    }
+    let range = chosen.ok_or_else(|| format!("No samples on channel {} to range from", channel))?;
+    lock_processor()?.set_volts_per_div(channel, range)?;
+    Ok(range)
+}
+
+#[tauri::command]
+fn set_volts_per_div(channel: u8, volts_per_div: f32) -> Result<(), String> {
+    lock_processor()?.set_volts_per_div(channel, volts_per_div)
+}
+
+#[tauri::command]
+fn get_scope_view() -> Result<adc::ScopeView, String> {
+    Ok(lock_processor()?.scope_view())
+}
+
+/// `channel_samples` narrowed to the triggered capture, then to the measurement gate within it.
+fn measurement_samples(channel: u8) -> Result<Vec<adc::AdcSample>, String> {
+    gated_samples(&*lock_processor()?, channel)
+}
+
+/// Runs `measure` on the channel's measurement window, outside the processor lock.
+///
+/// The result carries `Clipping` when any sample in the window sat at an ADC rail
+/// and `InsufficientData` when `measure` couldn't produce a value.
+fn checked_measurement<T>(
+    channel: u8,
+    measure: impl FnOnce(&[adc::AdcSample], analysis::WindowFn) -> Option<T>,
+) -> Result<analysis::Checked<T>, String> {
+    let (samples, window, clipped) = {
+        let proc = lock_processor()?;
+        (gated_samples(&proc, channel)?, proc.fft_window(), window_clipped(&proc, channel)?)
+    };
+    Ok(analysis::Checked::new(measure(&samples, window), clipped))
+}
+
+/// Whether the channel's measurement window holds a sample at an ADC rail.
+///
+/// The differential trace is computed, so its two inputs are checked instead.
+fn window_clipped(proc: &adc::PacketProcessor, channel: u8) -> Result<bool, String> {
+    let diff = proc.get_differential();
+    let inputs = if channel == adc::DIFF_CHANNEL && diff.enabled {
+        vec![diff.channel_a, diff.channel_b]
+    } else {
+        vec![channel]
+    };
+    for input in inputs {
+        if gated_samples(proc, input)?.iter().any(|s| proc.is_clipped(s)) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// `measurement_samples` for a caller already holding the processor lock.
+fn gated_samples(proc: &adc::PacketProcessor, channel: u8) -> Result<Vec<adc::AdcSample>, String> {
+    let samples = scaled_samples(proc, channel)?;
+    let ((start, end), span) = (proc.measurement_gate(), proc.capture_span());
+    let captured = match span {
+        Some((from, to)) => samples.into_iter().filter(|s| (from..=to).contains(&s.timestamp)).collect(),
+        None => samples,
+    };
+    Ok(analysis::gate(&captured, start, end).to_vec())
+}
+
+#[tracing::instrument(skip_all)]
+fn read_serial_into_buffer(
+    adc_processor: &Mutex<adc::PacketProcessor>,
+    output: &mpsc::SyncSender<BatchOutput>,
+) -> Result<(), AcquisitionError> {
+    let (baud_rate, transport, mock_fallback) = {
+        let processor = lock_ranked(adc_processor, LockRank::Processor)?;
+        (processor.baud_rate(), processor.transport().clone(), processor.mock_fallback())
+    };
+
+    // A fallback mock keeps running until the connection settings change
+    let mut source = transport::open(&transport, baud_rate, mock_fallback)?;
+
+    tracing::info!("Connected to {}", source.description());
+    let connection_id = {
+        let mut processor = lock_ranked(adc_processor, LockRank::Processor)?;
+        processor.set_connection(Some(source.description()));
+        processor.connection_id()
+    };
+    // Replies are parsed by this thread, so the wait happens on another
+    thread::spawn(move || negotiate_capabilities(connection_id));
+
+    let mut buffer_read = vec![0; 1024];
+    let mut unrecorded_bytes = 0;
+
+    loop {
+        let (reconnect, read_budget, outbound) = {
+            let mut processor = lock_ranked(adc_processor, LockRank::Processor)?;
+            let reconnect = processor.baud_rate() != baud_rate || *processor.transport() != transport;
+            // Counted here rather than per read so throughput needs no lock of its own
+            processor.record_read(std::mem::take(&mut unrecorded_bytes));
+            if processor.check_heartbeat() {
+                tracing::warn!("No heartbeat from the UI, stopping the stream");
+            }
+            (reconnect, processor.read_budget(), processor.take_outbound())
+        };
+
+        // Hand back to the retry loop so the source is reopened with the new settings
+        if reconnect {
+            tracing::info!("Connection settings changed, reconnecting");
+            return Ok(());
+        }
+
+        for command in outbound {
+            source.write_all(&command)
+                .map_err(|e| format!("Write error on {}: {}", source.description(), e))?;
+        }
+
+        // Block policy with a full buffer: let the parser catch up before reading more
+        if read_budget == 0 {
+            process_batch(&[], adc_processor, output, baud_rate)?;
+            thread::sleep(Duration::from_millis(1));
+            continue;
+        }
+
+        let read_len = buffer_read.len().min(read_budget);
+        match source.read(&mut buffer_read[..read_len]) {
+            Ok(bytes_read) => {
+                if bytes_read > 0 {
+                    tracing::debug!("Read {} bytes", bytes_read);
+                    unrecorded_bytes += bytes_read;
+                    process_batch(&buffer_read[..bytes_read], adc_processor, output, baud_rate)?;
+                }
+            }
+            Err(ref e) if transport::is_timeout(e) => {
+                // Don't print on timeout to avoid console spam, but let a stalled partial packet expire
+                process_batch(&[], adc_processor, output, baud_rate)?;
+            }
+            Err(e) => {
+                return Err(format!("Read error on {}: {}", source.description(), e).into());
+            }
+        }
+    }
+}
+
+/// Parses one read's worth of bytes and queues the results for the output thread.
+///
+/// Only parsing and the stateful checks (resync, alarms) happen under the
+/// processor lock; everything slow is left to `run_output`.
+#[tracing::instrument(level = "trace", skip_all, fields(bytes = bytes.len()))]
+fn process_batch(
+    bytes: &[u8],
+    adc_processor: &Mutex<adc::PacketProcessor>,
+    output: &mpsc::SyncSender<BatchOutput>,
+    baud_rate: u32,
+) -> Result<(), String> {
+    let (batch, low_latency_sample) = {
+        let mut processor = lock_ranked(adc_processor, LockRank::Processor)?;
+        processor.add_bytes(bytes);
+        let samples = processor.process_packets();
+        processor.refresh_capture_duration();
+
+        let low_latency = processor.low_latency_channel();
+        let low_latency_sample = samples.iter().rev().find(|s| Some(s.channel) == low_latency).cloned();
+
+        let resync_warning = processor.update_resync_stats().and_then(|stats| {
+            let suggestions = adc::suggest_baud(&stats, baud_rate);
+            (!suggestions.is_empty()).then(|| format!(
+                "Discarding {:.0}% of bytes while resyncing; the baud rate may be wrong (try {:?})",
+                stats.discard_ratio * 100.0,
+                &suggestions[..suggestions.len().min(3)]
+            ))
+        });
+
+        // The low-latency channel goes out on its own event instead
+        let mut display: Vec<adc::AdcSample> = processor.take_display_samples()
+            .into_iter()
+            .filter(|s| Some(s.channel) != low_latency)
+            .collect();
+        let clipped = processor.apply_display_gain(&mut display);
+        let alarms = samples.iter().filter_map(|s| processor.check_alarm(s)).collect();
+
+        let batch = BatchOutput {
+            samples,
+            display,
+            clipped,
+            alarms,
+            resync_warning,
+            units: processor.get_channel_units(),
+            diff_slot: processor.get_differential().channel_a,
+            markers: processor.take_new_markers(),
+            display_fill: processor.display_fill(),
+            encoding: processor.stream_encoding(),
+            per_channel_events: processor.per_channel_events(),
+        };
+        (batch, low_latency_sample)
+    };
+
+    // Straight from the reader, so it never waits behind queued batches
+    if let Some(latest) = low_latency_sample {
+        emit_event("low-latency-sample", latest);
+    }
+
+    if tracing::enabled!(tracing::Level::DEBUG) && !batch.samples.is_empty() {
+        let mut per_channel = [0usize; 4];
+        batch.samples.iter().for_each(|s| per_channel[s.channel as usize & 3] += 1);
+        tracing::debug!("Decoded {} samples {:?}, last {:?}", batch.samples.len(), per_channel, batch.samples.last());
+    }
 
-    println!("Selected port: {}", port_info.port_name);
+    if batch.samples.is_empty() && batch.display.is_empty() && batch.resync_warning.is_none() && batch.markers.is_empty() {
+        return Ok(());
+    }
+    output.send(batch).map_err(|_| "Output thread has stopped".to_string())
+}
 
-    let mut port = serialport::new(&port_info.port_name, 1000000)  // Changed to match Arduino's baud rate
-        .timeout(Duration::from_millis(100))
-        .open()
-        .map_err(|e| format!("Failed to open {}: {}", port_info.port_name, e))?;
+/// Fans parsed batches out to the display buffer, the log and frontend events.
+fn run_output(
+    batches: mpsc::Receiver<BatchOutput>,
+    buffer: Arc<Mutex<Vec<String>>>,
+    logger: Arc<logger::Logger>,
+    buffer_size: usize,
+) {
+    for batch in batches {
+        if let Some(warning) = batch.resync_warning {
+            emit_error("resync", warning);
+        }
 
-    println!("Port opened successfully");
+        if let Ok(mut buf) = lock_ranked(&buffer, LockRank::Buffer) {
+            for (sample, &clipped) in batch.display.iter().zip(&batch.clipped) {
+                // Format for frontend display
+                // The differential trace replaces channel_a's, under its label so the plot shows it
+                let mut line = if sample.channel == adc::DIFF_CHANNEL {
+                    format!("Ch{}: {:.4} V", batch.diff_slot + 1, sample.voltage)
+                } else {
+                    let unit = &batch.units[sample.channel as usize];
+                    format!("Ch{}: {:.4} {}", sample.channel + 1, sample.voltage * unit.factor, unit.unit.symbol())
+                };
+                if clipped {
+                    line.push_str(" clipped");
+                }
+                push_display_line(&mut buf, line, buffer_size, batch.display_fill);
+            }
+        }
 
-    let mut buffer_read = vec![0; 1024];
+        if !batch.display.is_empty() {
+            send_samples(&batch.display, batch.encoding, batch.per_channel_events);
+        }
 
-    loop {
-        match port.read(&mut buffer_read) {
-            Ok(bytes_read) => {
-                if bytes_read > 0 {
-                    //println!("Read {} bytes", bytes_read);
-                    //println!("Raw data: {:?}", &buffer_read[..bytes_read]);
-
-                    let mut processor = adc_processor.lock()
-                        .map_err(|_| "Failed to lock ADC processor".to_string())?;
-                    processor.add_bytes(&buffer_read[..bytes_read]);
-                    let samples = processor.process_packets();
-                    
-                    //println!("Processed {} samples", samples.len());
-                    
-                    let mut buf = buffer.lock()
-                        .map_err(|_| "Failed to lock buffer".to_string())?;
-                    
-                    for sample in samples {
-                        // Format for frontend display
-                        let line = format!("Ch{}: {:.4} V", 
-                            sample.channel + 1, 
-                            sample.voltage
-                        );
-                        
-                        //println!("Formatted sample: {}", line);
-                        
-                        if buf.len() >= buffer_size {
-                            buf.remove(0);
-                        }
-                        buf.push(line);
+        let mut clipped_channels: Vec<u8> = batch.display.iter()
+            .zip(&batch.clipped)
+            .filter_map(|(sample, &clipped)| clipped.then_some(sample.channel))
+            .collect();
+        if !clipped_channels.is_empty() {
+            clipped_channels.sort_unstable();
+            clipped_channels.dedup();
+            emit_event("display-clipped", clipped_channels);
+        }
 
-                        // Log the sample if recording
-                        if let Err(e) = logger.log_sample(&sample) {
-                            eprintln!("Logging error: {}", e);
-                        }
+        for sample in &batch.samples {
+            // Log the sample if recording
+            if let Err(e) = logger.log_sample(sample) {
+                tracing::error!("Logging error: {}", e);
+            }
+        }
+        if !batch.samples.is_empty() {
+            write_pipe_output(&batch.samples);
+        }
+
+        for marker in batch.markers {
+            if let Err(e) = logger.log_marker(&marker) {
+                tracing::error!("Logging error: {}", e);
+            }
+            emit_event("marker", marker);
+        }
+
+        for alarm in batch.alarms {
+            emit_event("alarm", alarm);
+        }
+    }
+}
+
+fn send_samples(samples: &[adc::AdcSample], encoding: adc::StreamEncoding, per_channel: bool) {
+    match encoding {
+        adc::StreamEncoding::Json if per_channel => {
+            for (channel, samples) in split_by_channel(samples).iter().enumerate() {
+                if !samples.is_empty() {
+                    emit_event(&format!("adc-samples-ch{}", channel), samples);
+                }
+            }
+        }
+        adc::StreamEncoding::Json => emit_event("samples", samples),
+        adc::StreamEncoding::Binary => {
+            if let Ok(channel) = SAMPLE_CHANNEL.lock() {
+                if let Some(channel) = channel.as_ref() {
+                    let body = tauri::ipc::InvokeResponseBody::Raw(adc::encode_samples(samples));
+                    if let Err(e) = channel.send(body) {
+                        tracing::warn!("Failed to send binary samples: {}", e);
                     }
                 }
             }
-            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => {
-                // Don't print on timeout to avoid console spam
+        }
+    }
+}
+
+/// Sends samples to the pipe output, if one is running, stopping it once writes fail.
+fn write_pipe_output(samples: &[adc::AdcSample]) {
+    let Ok(mut pipe) = PIPE_OUTPUT.lock() else {
+        return;
+    };
+    if let Some(Err(e)) = pipe.as_mut().map(|output| output.write(samples)) {
+        *pipe = None;
+        emit_error("pipe-output", format!("Pipe output stopped: {}", e));
+    }
+}
+
+/// Samples grouped per channel, the differential trace last, each in arrival order.
+fn split_by_channel(samples: &[adc::AdcSample]) -> [Vec<adc::AdcSample>; 5] {
+    let mut channels: [Vec<adc::AdcSample>; 5] = Default::default();
+    for sample in samples {
+        channels[sample.channel as usize].push(sample.clone());
+    }
+    channels
+}
+
+/// Adds a line to the display buffer, making room or refusing it by `fill` once full.
+fn push_display_line(buf: &mut Vec<String>, line: String, capacity: usize, fill: adc::DisplayFill) {
+    if buf.len() >= capacity {
+        match fill {
+            adc::DisplayFill::Scroll => {
+                buf.remove(0);
             }
-            Err(e) => {
-                eprintln!("Serial read error: {}", e);
-                return Err(format!("Serial read error: {}", e));
+            adc::DisplayFill::Hold => return,
+        }
+    }
+    buf.push(line);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    /// `n` samples of `amplitude * sin`, `cycles` periods over the block.
+    fn tone(n: usize, cycles: f32, amplitude: f32) -> Vec<f32> {
+        (0..n).map(|i| amplitude * (2.0 * PI * cycles * i as f32 / n as f32).sin()).collect()
+    }
+
+    fn sample(channel: u8, voltage: f32, timestamp: u128) -> adc::AdcSample {
+        adc::AdcSample { channel, raw_value: 0, voltage, timestamp, is_trigger: false }
+    }
+
+    /// Voltages decoded from one packet per raw code on channel 0.
+    fn decode(processor: &mut adc::PacketProcessor, codes: &[u16]) -> Vec<f32> {
+        for &code in codes {
+            let [hi, lo] = code.to_be_bytes();
+            processor.add_bytes(&[0xAA, 0, hi, lo, 0x55]);
+        }
+        processor.process_packets().iter().map(|s| s.voltage).collect()
+    }
+
+    /// Feeds one packet per call and reports which ones the trigger fired on.
+    fn fired(processor: &mut adc::PacketProcessor, packets: &[(u8, f32)]) -> Vec<bool> {
+        packets.iter()
+            .map(|&(channel, volts)| {
+                let [hi, lo] = adc::volts_to_code(volts, true).to_be_bytes();
+                processor.add_bytes(&[0xAA, channel, hi, lo, 0x55]);
+                // Samples before the trigger point are held back, so anything returned fired
+                !processor.process_packets().is_empty()
+            })
+            .collect()
+    }
+
+    fn pattern_processor(conditions: [adc::PatternLevel; 4]) -> adc::PacketProcessor {
+        let mut processor = adc::PacketProcessor::new();
+        processor.configure_trigger(adc::TriggerConfig {
+            enabled: true,
+            channel: 0,
+            level: 0.0,
+            rising_edge: true,
+            level_raw: None,
+            pattern: Some(adc::PatternTrigger { conditions, thresholds: [1.0; 4] }),
+            coupling: adc::TriggerCoupling::Dc,
+            any_edge: false,
+            channel_triggers: Default::default(),
+        }).unwrap();
+        processor
+    }
+
+    #[test]
+    fn pattern_needs_both_channels() {
+        use adc::PatternLevel::{DontCare, High};
+        let mut processor = pattern_processor([High, High, DontCare, DontCare]);
+        let fired = fired(&mut processor, &[(0, 5.0), (1, 0.0), (0, 5.0), (1, 5.0), (0, 0.0), (1, 5.0)]);
+        assert_eq!(fired, [false, false, false, true, false, false]);
+    }
+
+    #[test]
+    fn pattern_ignores_dont_care_channels() {
+        use adc::PatternLevel::{DontCare, High};
+        let mut processor = pattern_processor([High, DontCare, DontCare, DontCare]);
+        let fired = fired(&mut processor, &[(1, 0.0), (0, 0.0), (1, -5.0), (0, 5.0)]);
+        assert_eq!(fired, [false, false, false, true]);
+    }
+
+    fn coupled_processor(coupling: adc::TriggerCoupling) -> adc::PacketProcessor {
+        let mut processor = adc::PacketProcessor::new();
+        let config = adc::TriggerConfig { enabled: true, level: 1.0, coupling, ..processor.get_trigger_config() };
+        processor.configure_trigger(config).unwrap();
+        processor
+    }
+
+    #[test]
+    fn trigger_coupling_rejects_a_lone_spike() {
+        let spike = [(0, 0.0), (0, 0.0), (0, 5.0), (0, 0.0), (0, 0.0)];
+        assert_eq!(fired(&mut coupled_processor(adc::TriggerCoupling::Dc), &spike), [false, false, true, false, false]);
+        for coupling in [adc::TriggerCoupling::HfReject, adc::TriggerCoupling::NoiseReject] {
+            assert!(!fired(&mut coupled_processor(coupling), &spike).contains(&true), "{:?}", coupling);
+        }
+    }
+
+    #[test]
+    fn lf_reject_ignores_a_steady_level() {
+        let steady = [(0, 3.0); 5];
+        assert!(fired(&mut coupled_processor(adc::TriggerCoupling::Dc), &steady).iter().all(|&f| f));
+        assert!(!fired(&mut coupled_processor(adc::TriggerCoupling::LfReject), &steady).contains(&true));
+    }
+
+    #[test]
+    fn any_edge_fires_on_rising_and_falling_crossings() {
+        let mut processor = adc::PacketProcessor::new();
+        let config = adc::TriggerConfig { enabled: true, level: 1.0, any_edge: true, ..processor.get_trigger_config() };
+        processor.configure_trigger(config).unwrap();
+        let fired = fired(&mut processor, &[(0, 0.0), (0, 5.0), (0, 5.0), (0, -2.0), (0, -2.0), (0, 3.0)]);
+        assert_eq!(fired, [false, true, false, true, false, true]);
+    }
+
+    #[test]
+    fn session_archive_round_trips_and_checks_its_version() {
+        let mut source = adc::PacketProcessor::new();
+        decode(&mut source, &[100, 200, 300]);
+        source.add_marker("step").unwrap();
+        let archive = session::SessionArchive {
+            version: session::VERSION,
+            exported_at: 0,
+            config: source.get_full_config(),
+            history: (0..=adc::DIFF_CHANNEL).map(|ch| source.channel_samples(ch)).collect(),
+            single_capture: Vec::new(),
+            markers: source.get_markers(),
+            measurements: Vec::new(),
+        };
+        let path = std::env::temp_dir().join(format!("session-test-{}.json", std::process::id()));
+        session::save(&path, &archive).unwrap();
+
+        let loaded = session::load(&path).unwrap();
+        let mut replay = adc::PacketProcessor::new();
+        replay.apply_config(loaded.config).unwrap();
+        replay.restore_session(loaded.history, loaded.single_capture, loaded.markers).unwrap();
+        let raw: Vec<u16> = replay.channel_samples(0).iter().map(|s| s.raw_value).collect();
+        assert_eq!(raw, [100, 200, 300]);
+        assert_eq!(replay.get_markers()[0].label, "step");
+
+        let newer = session::SessionArchive { version: session::VERSION + 1, ..archive };
+        session::save(&path, &newer).unwrap();
+        assert!(session::load(&path).unwrap_err().contains("format version"));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn auto_range_picks_the_smallest_fitting_step() {
+        assert_eq!(adc::fit_volts_per_div(-3.0, 2.0), 1.0);
+        assert_eq!(adc::fit_volts_per_div(0.1, 0.3), 0.1);
+        assert_eq!(adc::fit_volts_per_div(-10.0, 10.0), 5.0);
+        // Exactly four divisions above zero still fits
+        assert_eq!(adc::fit_volts_per_div(0.0, 8.0), 2.0);
+    }
+
+    /// A square wave sampled every microsecond, one `(high, low)` sample count per cycle.
+    fn square(cycles: &[(usize, usize)]) -> Vec<adc::AdcSample> {
+        cycles.iter()
+            .flat_map(|&(high, low)| std::iter::repeat_n(1.0, high).chain(std::iter::repeat_n(-1.0, low)))
+            .enumerate()
+            .map(|(i, v)| sample(0, v, i as u128 * 1000))
+            .collect()
+    }
+
+    #[test]
+    fn jitter_measures_period_spread() {
+        let steady = analysis::jitter(&square(&[(50, 50); 6])).unwrap();
+        assert!((steady.mean_period_s - 100e-6).abs() < 1e-9);
+        assert!(steady.std_dev_s < 1e-9 && steady.peak_to_peak_s < 1e-9);
+
+        let wobbly = analysis::jitter(&square(&[(50, 50), (60, 60), (50, 50), (60, 60), (50, 50)])).unwrap();
+        assert_eq!(wobbly.periods, 3);
+        assert!((wobbly.peak_to_peak_s - 20e-6).abs() < 1e-9);
+
+        assert!(analysis::jitter(&square(&[(50, 50); 2])).is_none());
+    }
+
+    #[test]
+    fn pulse_widths_split_high_and_low_times() {
+        let pwm = square(&[(30, 70); 4]);
+        let widths = analysis::pulse_widths(&pwm, None).unwrap();
+        assert!((widths.positive_s - 30e-6).abs() < 1e-9);
+        assert!((widths.negative_s - 70e-6).abs() < 1e-9);
+        assert_eq!((widths.positive_pulses, widths.negative_pulses), (3, 3));
+
+        // A threshold above the square's top is never crossed
+        assert!(analysis::pulse_widths(&pwm, Some(2.0)).is_none());
+        assert!(analysis::pulse_widths(&square(&[(30, 70)]), None).is_none());
+    }
+
+    #[test]
+    fn reference_difference_lines_up_the_newest_span() {
+        let ramp: Vec<_> = (0..10).map(|i| sample(0, i as f32, i * 1000)).collect();
+        let reference = analysis::reference_trace(0, &ramp).unwrap();
+        // The same ramp a second later, one volt higher
+        let live: Vec<_> = (0..20).map(|i| sample(0, (i % 10) as f32 + 1.0, 5_000 + i * 1000)).collect();
+        let difference = analysis::reference_difference(&live, &reference).unwrap();
+        assert_eq!(difference.len(), 10);
+        assert_eq!(difference[0].timestamp, 15_000);
+        assert!(difference.iter().all(|p| (p.value - 1.0).abs() < 1e-6));
+        assert!(analysis::reference_difference(&live[..5], &reference).is_none());
+    }
+
+    fn fill_display(fill: adc::DisplayFill) -> Vec<String> {
+        let mut buf = Vec::new();
+        for i in 0..5 {
+            push_display_line(&mut buf, i.to_string(), 3, fill);
+        }
+        buf
+    }
+
+    #[test]
+    fn full_display_scrolls_or_holds() {
+        assert_eq!(fill_display(adc::DisplayFill::Scroll), ["2", "3", "4"]);
+        assert_eq!(fill_display(adc::DisplayFill::Hold), ["0", "1", "2"]);
+    }
+
+    #[test]
+    fn display_holds_in_triggered_and_single_shot_modes() {
+        let mut processor = adc::PacketProcessor::new();
+        assert_eq!(processor.display_fill(), adc::DisplayFill::Scroll);
+        processor.set_capture_length(Some(100)).unwrap();
+        assert_eq!(processor.display_fill(), adc::DisplayFill::Hold);
+        processor.set_capture_length(None).unwrap();
+        let config = adc::TriggerConfig { enabled: true, ..processor.get_trigger_config() };
+        processor.configure_trigger(config).unwrap();
+        assert_eq!(processor.display_fill(), adc::DisplayFill::Hold);
+    }
+
+    #[test]
+    fn am_depth_reads_the_modulation_index() {
+        // 1 kHz carrier at 100 kHz, 50% modulated at 10 Hz over two modulation periods
+        let samples: Vec<_> = (0..20_000u32)
+            .map(|i| {
+                let t = i as f32 / 100_000.0;
+                let v = (1.0 + 0.5 * (2.0 * PI * 10.0 * t).sin()) * (2.0 * PI * 1000.0 * t).sin();
+                sample(0, v, i as u128 * 10_000)
+            })
+            .collect();
+        let depth = analysis::am_depth(&samples, 100.0).unwrap();
+        assert!((depth - 0.5).abs() < 0.05, "depth {}", depth);
+        assert_eq!(analysis::am_depth(&samples[..10], 100.0), None);
+    }
+
+    /// Channel 0 voltages after `frames` mock frames go through the packet parser.
+    fn mock_pipeline(waveform: transport::MockWaveform, sample_rate: u32, frames: usize) -> Vec<f32> {
+        let mut waveforms = transport::MockParams::default().waveforms;
+        waveforms[0] = waveform;
+        let params = transport::MockParams {
+            sample_rate,
+            channels: [true, false, false, false],
+            waveforms,
+            ..Default::default()
+        };
+        params.validate().unwrap();
+        let mut processor = adc::PacketProcessor::new();
+        processor.add_bytes(&transport::MockSource::new(params).next_frames(frames));
+        processor.process_packets().iter().map(|s| s.voltage).collect()
+    }
+
+    #[test]
+    fn mock_waveform_measures_end_to_end() {
+        let wave = transport::MockWaveform { amplitude: 2.0, frequency_hz: 250.0, phase_deg: 90.0, dc: 0.5 };
+        let values = mock_pipeline(wave, 8000, 4096);
+        assert_eq!(values.len(), 4096);
+        // Cosine phase: the first sample is the crest
+        assert!((values[0] - 2.5).abs() < 1e-3);
+
+        let frequency = analysis::dominant_frequency(&values, 8000.0, analysis::WindowFn::Hann).unwrap();
+        assert!((frequency - 250.0).abs() <= 8000.0 / 4096.0, "frequency {}", frequency);
+
+        let mean = values.iter().sum::<f32>() / values.len() as f32;
+        let ac: Vec<f32> = values.iter().map(|v| v - mean).collect();
+        assert!((mean - 0.5).abs() < 0.01, "mean {}", mean);
+        assert!((analysis::rms(&ac).unwrap() - 2.0 / 2f32.sqrt()).abs() < 0.01);
+    }
+
+    #[test]
+    fn mock_waveform_fft_peaks_at_its_bin() {
+        // 512 Hz at 8192 samples/s over 4096 samples lands exactly on bin 256
+        let wave = transport::MockWaveform { amplitude: 1.0, frequency_hz: 512.0, phase_deg: 0.0, dc: 0.0 };
+        let spectrum = analysis::magnitude_spectrum(&mock_pipeline(wave, 8192, 4096), analysis::WindowFn::Rectangular);
+        let peak = (0..spectrum.len()).max_by(|&a, &b| spectrum[a].total_cmp(&spectrum[b])).unwrap();
+        assert_eq!(peak, 256);
+    }
+
+    #[test]
+    fn mock_rejects_frequencies_past_nyquist() {
+        let mut params = transport::MockParams::default();
+        params.waveforms[1].frequency_hz = 600.0;
+        assert!(params.validate().is_err());
+    }
+
+    #[test]
+    fn re_enabled_channel_starts_clean() {
+        let mut processor = adc::PacketProcessor::new();
+        for channel in [0, 1] {
+            processor.add_bytes(&[0xAA, channel, 0x10, 0x00, 0x55]);
+        }
+        processor.process_packets();
+
+        processor.set_active_channels([true, false, false, false]);
+        processor.set_active_channels([true, true, false, false]);
+        assert_eq!(processor.channel_samples(0).len(), 1);
+        assert!(processor.channel_samples(1).is_empty());
+    }
+
+    #[test]
+    fn stalled_partial_packet_times_out() {
+        let mut processor = adc::PacketProcessor::new();
+        processor.set_packet_timeout(Some(std::time::Duration::from_millis(1))).unwrap();
+        processor.add_bytes(&[0xAA, 0, 0x12]);
+        assert!(processor.process_packets().is_empty());
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        assert!(processor.process_packets().is_empty());
+        assert_eq!(processor.get_resync_stats().packet_timeouts, 1);
+        assert_eq!(processor.get_buffer_stats().buffered_bytes, 0);
+
+        processor.add_bytes(&[0xAA, 1, 0, 0, 0x55]);
+        assert_eq!(processor.process_packets().len(), 1);
+        assert_eq!(processor.get_resync_stats().packet_timeouts, 1);
+    }
+
+    #[test]
+    fn capture_geometry_is_validated_together() {
+        let mut processor = adc::PacketProcessor::new();
+        let valid = adc::CaptureConfig { length: Some(100), duration_us: None, pre_trigger: 20, position: 0.2 };
+        processor.configure_capture(valid).unwrap();
+        assert_eq!(processor.capture_config(), valid);
+
+        let invalid = [
+            adc::CaptureConfig { pre_trigger: 100, ..valid },
+            adc::CaptureConfig { position: 1.5, ..valid },
+            adc::CaptureConfig { position: f32::NAN, ..valid },
+            adc::CaptureConfig { length: Some(0), ..valid },
+            adc::CaptureConfig { duration_us: Some(1000), ..valid },
+        ];
+        for config in invalid {
+            assert!(processor.configure_capture(config).is_err(), "{:?}", config);
+        }
+        // Nothing from a rejected config is half-applied
+        assert_eq!(processor.capture_config(), valid);
+
+        let error = processor.configure_capture(adc::CaptureConfig { pre_trigger: 200, position: 2.0, ..valid }).unwrap_err();
+        assert!(error.contains("Pre-trigger") && error.contains("position"), "{}", error);
+    }
+
+    #[test]
+    fn binary_samples_pack_little_endian() {
+        let samples = [
+            adc::AdcSample { channel: 2, raw_value: 0x1234, voltage: -1.5, timestamp: 0x0102_0304_0506_0708, is_trigger: true },
+            sample(1, 0.0, 9),
+        ];
+        let bytes = adc::encode_samples(&samples);
+        assert_eq!(bytes.len(), 2 * adc::ENCODED_SAMPLE_LEN);
+        assert_eq!(&bytes[..3], [2, 0x34, 0x12]);
+        assert_eq!(f32::from_le_bytes(bytes[3..7].try_into().unwrap()), -1.5);
+        assert_eq!(u64::from_le_bytes(bytes[7..15].try_into().unwrap()), 0x0102_0304_0506_0708);
+        assert_eq!(bytes[15], 1);
+    }
+
+    #[test]
+    fn integer_trigger_matches_float_comparison_at_the_boundary() {
+        for (gain, offset, rising) in [(1.0, 0.0, true), (0.37, 0.013, false), (-1.3, 0.2, true)] {
+            let mut processor = adc::PacketProcessor::new();
+            processor.set_calibration(0, adc::Calibration { gain, offset }).unwrap();
+            // A level exactly on a code, and one between two codes
+            let on_code = processor.code_to_volts(0, 1000);
+            for level in [on_code, on_code + 1e-4] {
+                let config = adc::TriggerConfig { enabled: true, level, rising_edge: rising, ..processor.get_trigger_config() };
+                processor.configure_trigger(config).unwrap();
+                let boundary = processor.volts_to_code(0, level) as i16;
+                for raw in (boundary - 3..=boundary + 3).map(|c| c as u16) {
+                    let volts = processor.code_to_volts(0, raw);
+                    let expected = if rising { volts > level } else { volts < level };
+                    let [hi, lo] = raw.to_be_bytes();
+                    processor.add_bytes(&[0xAA, 0, hi, lo, 0x55]);
+                    let fired = !processor.process_packets().is_empty();
+                    assert_eq!(fired, expected, "gain {}, level {}, code {}", gain, level, raw as i16);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn crosstalk_compares_power_at_the_driven_tone() {
+        let driven = tone(2048, 64.0, 5.0);
+        // -40 dB of the driven tone plus a louder unrelated tone that must not count
+        let victim: Vec<f32> = tone(2048, 64.0, 0.05).iter()
+            .zip(tone(2048, 300.0, 1.0))
+            .map(|(a, b)| a + b)
+            .collect();
+
+        let db = analysis::crosstalk(&driven, &victim, analysis::WindowFn::Hann).unwrap();
+        assert!((db + 40.0).abs() < 0.1, "crosstalk {db} dB");
+        assert!(analysis::crosstalk(&[0.0; 2048], &victim, analysis::WindowFn::Hann).is_none());
+    }
+
+    #[test]
+    fn monotonic_timestamps_never_decrease() {
+        let mut processor = adc::PacketProcessor::new();
+        assert_eq!(processor.timestamp_clock(), adc::TimestampClock::Monotonic);
+        processor.set_active_channels([true, true, false, false]);
+
+        let mut timestamps = Vec::new();
+        for i in 0..200u16 {
+            let [hi, lo] = i.to_be_bytes();
+            processor.add_bytes(&[0xAA, (i % 2) as u8, hi, lo, 0x55]);
+            if i % 50 == 0 {
+                timestamps.push(processor.add_marker("tick").unwrap().timestamp);
+            }
+            timestamps.extend(processor.process_packets().iter().map(|s| s.timestamp));
+        }
+
+        assert!(timestamps.len() > 200);
+        assert!(timestamps.windows(2).all(|w| w[0] <= w[1]), "timestamps went backward");
+        // Counted from acquisition start, not the epoch
+        assert!(timestamps.last().unwrap() < &adc::now_nanos());
+    }
+
+    #[test]
+    fn display_gain_magnifies_and_clips_only_the_display() {
+        let mut processor = adc::PacketProcessor::new();
+        processor.set_display_gain(0, 10.0).unwrap();
+        assert!(processor.set_display_gain(0, 0.0).is_err());
+        assert!(processor.set_display_gain(5, 2.0).is_err());
+
+        // 1 V/div puts the screen edge at 4 V
+        let mut display = vec![sample(0, 0.2, 0), sample(0, -0.5, 1), sample(1, 3.0, 2)];
+        let clipped = processor.apply_display_gain(&mut display);
+        let volts: Vec<f32> = display.iter().map(|s| s.voltage).collect();
+        assert_eq!(volts, [2.0, -4.0, 3.0]);
+        assert_eq!(clipped, [false, true, false]);
+    }
+
+    #[test]
+    fn packet_cap_leaves_the_rest_for_the_next_call() {
+        let mut processor = adc::PacketProcessor::new();
+        processor.set_max_packets_per_call(Some(2)).unwrap();
+        assert!(processor.set_max_packets_per_call(Some(0)).is_err());
+        for code in 0..5u16 {
+            processor.add_bytes(&[0xAA, 0, 0, code as u8, 0x55]);
+        }
+
+        let counts: Vec<usize> = (0..4).map(|_| processor.process_packets().len()).collect();
+        assert_eq!(counts, [2, 2, 1, 0]);
+        assert_eq!(processor.get_resync_stats().packet_timeouts, 0);
+    }
+
+    #[test]
+    fn capabilities_flag_settings_the_device_cant_honour() {
+        // 2 channels, 50 kS/s, 115200 baud, 12- and 16-bit samples, checksums
+        let caps = adc::DeviceCapabilities::from_replies([2, 500, 1152, 0x8800, adc::CAP_FEATURE_CHECKSUM]);
+        assert_eq!(caps.max_sample_rate, 50_000);
+        assert_eq!(caps.max_baud_rate, 115_200);
+        assert_eq!(caps.resolutions, [12, 16]);
+        assert!(caps.checksum && !caps.sequence_numbers);
+
+        let mut config = adc::PacketProcessor::new().get_full_config();
+        config.baud_rate = 115_200;
+        assert!(caps.incompatibilities(&config).is_empty());
+
+        config.active_channels = [true, false, true, false];
+        config.baud_rate = 1_000_000;
+        config.differential = adc::DifferentialConfig { enabled: true, channel_a: 0, channel_b: 3 };
+        let problems = caps.incompatibilities(&config);
+        assert_eq!(problems.len(), 3, "{problems:?}");
+        assert!(problems[0].contains("Channel 3"));
+    }
+
+    #[test]
+    fn mock_answers_capability_queries() {
+        let mut processor = adc::PacketProcessor::new();
+        let mut mock = transport::MockSource::new(transport::MockParams::default());
+        let replies: Vec<_> = adc::CAPABILITY_OPCODES.iter()
+            .map(|&opcode| processor.request(opcode).unwrap().1)
+            .collect();
+        for frame in processor.take_outbound() {
+            mock.write_all(&frame).unwrap();
+        }
+        processor.add_bytes(&mock.next_frames(0));
+        processor.process_packets();
+
+        let values: Vec<u16> = replies.iter().map(|reply| reply.try_recv().unwrap()).collect();
+        let caps = adc::DeviceCapabilities::from_replies(values.try_into().unwrap());
+        assert_eq!(caps.channels, 4);
+        assert_eq!(caps.max_sample_rate, 1000);
+        assert_eq!(caps.resolutions, [16]);
+    }
+
+    #[test]
+    fn capabilities_limit_channels_for_their_connection_only() {
+        let mut processor = adc::PacketProcessor::new();
+        processor.set_connection(Some("device".to_string()));
+        let stale = processor.connection_id();
+        processor.set_connection(Some("device".to_string()));
+        let caps = adc::DeviceCapabilities::from_replies([1, 10, 10, 1 << 15, 0]);
+
+        assert!(!processor.set_capabilities(stale, caps.clone()));
+        assert_eq!(processor.get_active_channels(), [true, true, false, false]);
+        assert!(processor.set_capabilities(processor.connection_id(), caps));
+        assert_eq!(processor.get_active_channels(), [true, false, false, false]);
+        processor.set_active_channels([false, true, false, false]);
+        assert_eq!(processor.get_active_channels(), [false; 4]);
+
+        processor.set_connection(None);
+        assert!(processor.capabilities().is_none());
+    }
+
+    #[test]
+    fn group_delay_recovers_a_pure_delay() {
+        // A stepped sweep over bin-centred tones, 512 samples per step
+        let mut phase = 0.0f32;
+        let stimulus: Vec<f32> = (8..=40).step_by(4)
+            .flat_map(|bin| std::iter::repeat_n(bin, 512))
+            .map(|bin| {
+                phase += 2.0 * PI * bin as f32 / 256.0;
+                phase.sin()
+            })
+            .collect();
+        let delay = 3;
+        let response: Vec<f32> = (0..stimulus.len()).map(|i| if i < delay { 0.0 } else { stimulus[i - delay] }).collect();
+
+        let points = analysis::group_delay(&stimulus, &response, 1000.0, 256, analysis::WindowFn::Hann).unwrap();
+        assert_eq!(points.len(), 8);
+        for point in points {
+            assert!((point.delay_s - 0.003).abs() < 0.0003, "{point:?}");
+        }
+
+        let steady = tone(4096, 64.0, 1.0);
+        assert!(analysis::group_delay(&steady, &steady, 1000.0, 256, analysis::WindowFn::Hann).is_err());
+    }
+
+    #[test]
+    fn packet_sizes_count_trigger_marked_packets() {
+        let mut processor = adc::PacketProcessor::new();
+        for i in 0..10u8 {
+            if i % 5 == 0 {
+                processor.add_bytes(&[0xAA, 0, 0, i, 0xCC, 0x55]);
+            } else {
+                processor.add_bytes(&[0xAA, 0, 0, i, 0x55]);
+            }
+        }
+        processor.process_packets();
+
+        let sizes = processor.packet_sizes();
+        assert_eq!(sizes.packets, 10);
+        assert_eq!(sizes.counts, [(5, 8), (6, 2)]);
+        assert_eq!(sizes.most_common, Some(5));
+    }
+
+    #[test]
+    fn concurrent_commands_finish_without_deadlock() {
+        let _ = ADC_PROCESSOR.set(Arc::new(Mutex::new(adc::PacketProcessor::new())));
+        let _ = BUFFER.set(Arc::new(Mutex::new(Vec::new())));
+
+        let (done, finished) = mpsc::channel();
+        for worker in 0..8 {
+            let done = done.clone();
+            thread::spawn(move || {
+                for i in 0..200u16 {
+                    match (worker + i as usize) % 5 {
+                        0 => set_active_channels([true, i % 2 == 0, false, false]).unwrap(),
+                        1 => lock_buffer().unwrap().push(format!("Ch1: {} V", i)),
+                        2 => drop(get_serial_data().unwrap()),
+                        3 => drop(read_instant().unwrap()),
+                        _ => {
+                            let [hi, lo] = i.to_be_bytes();
+                            let mut proc = lock_processor().unwrap();
+                            proc.add_bytes(&[0xAA, 0, hi, lo, 0x55]);
+                            proc.process_packets();
+                        }
+                    }
+                }
+                done.send(()).unwrap();
+            });
+        }
+        for _ in 0..8 {
+            finished.recv_timeout(Duration::from_secs(10)).expect("commands deadlocked");
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "lock order violated")]
+    fn taking_the_processor_under_the_buffer_panics() {
+        let (processor, buffer) = (Mutex::new(()), Mutex::new(()));
+        let _buffer = lock_ranked(&buffer, LockRank::Buffer).unwrap();
+        let _processor = lock_ranked(&processor, LockRank::Processor);
+    }
+
+    #[test]
+    fn resample_interpolates_onto_an_even_grid() {
+        // A ramp of 1 V/ms sampled at jittery times
+        let samples: Vec<adc::AdcSample> = [0u128, 900, 2100, 2900, 4000, 5200, 6000]
+            .iter()
+            .map(|&us| sample(0, us as f32 / 1000.0, us * 1000))
+            .collect();
+
+        let grid = analysis::resample(&samples, 100, 1000.0).unwrap();
+        assert_eq!(grid.start_ts, 0);
+        assert_eq!(grid.values.len(), 7);
+        for (i, v) in grid.values.iter().enumerate() {
+            assert!((v - i as f32).abs() < 1e-4, "{:?}", grid.values);
+        }
+
+        let newest = analysis::resample(&samples, 3, 1000.0).unwrap();
+        assert_eq!(newest.start_ts, 4_000_000);
+        assert!(analysis::resample(&samples[..1], 3, 1000.0).is_none());
+    }
+
+    #[test]
+    fn raw_capture_records_bytes_the_parser_drops() {
+        let mut processor = adc::PacketProcessor::new();
+        processor.add_bytes(&[0x01]);
+        processor.start_raw_capture().unwrap();
+        assert!(processor.start_raw_capture().is_err());
+
+        processor.set_backpressure(adc::BackpressurePolicy::DropNewest, 64).unwrap();
+        let burst: Vec<u8> = (0..100).collect();
+        processor.add_bytes(&burst);
+        processor.process_packets();
+
+        assert_eq!(processor.finish_raw_capture(), burst);
+        assert!(processor.finish_raw_capture().is_empty());
+    }
+
+    #[test]
+    fn autocorrelation_finds_the_period_through_noise() {
+        // 40 samples per cycle plus deterministic noise at a third of the amplitude
+        let mut seed = 1u32;
+        let noisy: Vec<f32> = tone(4000, 100.0, 1.0).iter()
+            .map(|v| {
+                seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                v + (seed >> 8) as f32 / (1 << 24) as f32 * 0.66 - 0.33
+            })
+            .collect();
+
+        let result = analysis::autocorrelate(&noisy, 100, Some(1000.0)).unwrap();
+        assert_eq!(result.values.len(), 101);
+        assert!((result.values[0] - 1.0).abs() < 1e-4);
+        assert_eq!(result.peak_lag, Some(40));
+        assert!((result.period_s.unwrap() - 0.040).abs() < 0.0005);
+        assert!(analysis::autocorrelate(&[1.0; 100], 10, Some(1000.0)).is_none());
+    }
+
+    #[test]
+    fn per_channel_split_keeps_each_channels_order() {
+        let mixed = [sample(1, 1.0, 0), sample(0, 2.0, 1), sample(4, 3.0, 2), sample(1, 4.0, 3)];
+        let split = split_by_channel(&mixed);
+        let volts = |ch: usize| split[ch].iter().map(|s| s.voltage).collect::<Vec<_>>();
+        assert_eq!(volts(0), [2.0]);
+        assert_eq!(volts(1), [1.0, 4.0]);
+        assert!(split[2].is_empty() && split[3].is_empty());
+        assert_eq!(volts(4), [3.0]);
+    }
+
+    #[test]
+    fn rearm_delay_ignores_the_tail_of_the_last_burst() {
+        let mut processor = adc::PacketProcessor::new();
+        let config = adc::TriggerConfig { enabled: true, ..processor.get_trigger_config() };
+        processor.configure_trigger(config).unwrap();
+        processor.set_capture_length(Some(2)).unwrap();
+        processor.set_rearm_delay(Duration::from_secs(3600));
+        fired(&mut processor, &[(0, 1.0), (0, 1.0)]);
+        assert_eq!(processor.get_capture_progress(), 1.0);
+
+        processor.arm_capture();
+        fired(&mut processor, &[(0, 1.0)]);
+        assert_eq!(processor.get_capture_progress(), 0.0);
+
+        processor.set_rearm_delay(Duration::ZERO);
+        fired(&mut processor, &[(0, 1.0)]);
+        assert_eq!(processor.get_capture_progress(), 0.5);
+    }
+
+    #[test]
+    fn average_power_multiplies_aligned_voltage_and_current() {
+        // 2 V DC into a current sampled at other instants, ramping 0 to 2 A
+        let voltage: Vec<_> = (0..=10).map(|i| sample(0, 2.0, i * 1000)).collect();
+        let current: Vec<_> = (0..=4).map(|i| sample(1, i as f32 * 0.5, i * 2500)).collect();
+        let power = analysis::average_power(&voltage, &current).unwrap();
+        assert!((power - 2.0).abs() < 1e-4, "power {}", power);
+        assert_eq!(analysis::average_power(&voltage, &current[..1]), None);
+    }
+
+    #[test]
+    fn measurements_flag_clipping_and_missing_values() {
+        let mut processor = adc::PacketProcessor::new();
+        processor.add_bytes(&[0xAA, 0, 0x12, 0x34, 0x55, 0xAA, 0, 0x7F, 0xFF, 0x55, 0xAA, 0, 0x80, 0x00, 0x55]);
+        let clipped: Vec<bool> = processor.process_packets().iter().map(|s| processor.is_clipped(s)).collect();
+        assert_eq!(clipped, [false, true, true]);
+
+        let ok = analysis::Checked::new(Some(1.0), false);
+        assert!(ok.valid && ok.warnings.is_empty());
+        let bad = analysis::Checked::<f32>::new(None, true);
+        assert!(!bad.valid);
+        assert_eq!(bad.warnings, [analysis::MeasurementWarning::Clipping, analysis::MeasurementWarning::InsufficientData]);
+    }
+
+    #[test]
+    fn pipe_output_writes_the_log_csv() {
+        let path = std::env::temp_dir().join(format!("pipe-test-{}.csv", std::process::id()));
+        let target = path.to_str().unwrap();
+        let mut csv = logger::PipeOutput::open(target, logger::PipeFormat::Csv).unwrap();
+        csv.write(&[sample(2, 1.5, 7)]).unwrap();
+        drop(csv);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "timestamp,channel,voltage,raw_value\n7,2,1.5000,0\n");
+
+        let mut binary = logger::PipeOutput::open(target, logger::PipeFormat::Binary).unwrap();
+        binary.write(&[sample(2, 1.5, 7)]).unwrap();
+        drop(binary);
+        assert_eq!(std::fs::read(&path).unwrap(), adc::encode_samples(&[sample(2, 1.5, 7)]));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn trigger_and_display_smooth_independently() {
+        let mut processor = adc::PacketProcessor::new();
+        let config = adc::TriggerConfig { enabled: true, level: 0.6, ..processor.get_trigger_config() };
+        processor.configure_trigger(config).unwrap();
+        processor.set_trigger_filter(0, adc::SmoothingFilter::MovingAverage { width: 4 }).unwrap();
+        // A lone spike averages to 0.25 V on the trigger path, under the level
+        assert_eq!(fired(&mut processor, &[(0, 0.0), (0, 0.0), (0, 0.0), (0, 1.0)]), [false; 4]);
+        assert_eq!(fired(&mut processor, &[(0, 1.0), (0, 1.0)]), [false, true]);
+        // Display smoothing is still off, so the display shows the raw samples
+        let shown: Vec<f32> = processor.take_display_samples().iter().map(|s| s.voltage).collect();
+        assert!((shown[0] - 1.0).abs() < 0.01, "shown {:?}", shown);
+
+        let mut processor = adc::PacketProcessor::new();
+        processor.set_display_filter(0, adc::SmoothingFilter::MovingAverage { width: 2 }).unwrap();
+        let raw = decode(&mut processor, &[0, 2000]);
+        let shown: Vec<f32> = processor.take_display_samples().iter().map(|s| s.voltage).collect();
+        assert_eq!(shown, [raw[0], (raw[0] + raw[1]) / 2.0]);
+        assert!(processor.set_display_filter(0, adc::SmoothingFilter::Exponential { alpha: 0.0 }).is_err());
+    }
+
+    #[test]
+    fn ripple_removes_the_dc_level() {
+        let rail: Vec<f32> = tone(1000, 10.0, 0.05).iter().map(|v| 12.0 + v).collect();
+        let ripple = analysis::ripple(&rail).unwrap();
+        assert!((ripple.dc - 12.0).abs() < 1e-3, "dc {}", ripple.dc);
+        assert!((ripple.vpp - 0.1).abs() < 1e-3, "vpp {}", ripple.vpp);
+        assert!((ripple.rms - 0.05 / 2f32.sqrt()).abs() < 1e-3, "rms {}", ripple.rms);
+        assert!(analysis::ripple(&[12.0]).is_none());
+    }
+
+    #[test]
+    fn lookup_table_interpolates_and_clamps() {
+        let mut processor = adc::PacketProcessor::new();
+        processor.set_channel_lut(0, Some(&[(2000, 150.0), (0, 0.0), (1000, 100.0)])).unwrap();
+        assert_eq!(decode(&mut processor, &[500, 1500, 3000, 0xFFFF]), [50.0, 125.0, 150.0, 0.0]);
+        assert!(processor.set_channel_lut(0, Some(&[(0, 0.0), (1000, 100.0), (2000, 50.0)])).is_err());
+
+        // Trigger levels are checked against, and converted through, the table
+        let config = adc::TriggerConfig { enabled: true, level: 120.0, ..processor.get_trigger_config() };
+        processor.configure_trigger(config).unwrap();
+        processor.add_bytes(&[0xAA, 0, 0x03, 0xE8, 0x55, 0xAA, 0, 0x05, 0xDC, 0x55]);
+        let fired: Vec<bool> = processor.process_packets().iter().map(|s| s.is_trigger).collect();
+        assert_eq!(fired, [true]);
+
+        processor.set_channel_lut(0, None).unwrap();
+        assert_eq!(processor.channel_lut(0), None);
+    }
+
+    #[test]
+    fn parser_benchmark_decodes_every_packet() {
+        let result = adc::benchmark_parser(10_000).unwrap();
+        assert_eq!((result.packets, result.mismatches), (10_000, 0));
+        assert!(result.packets_per_sec > 0.0);
+        assert!(adc::benchmark_parser(0).is_err());
+    }
+
+    #[test]
+    fn noise_floor_subtracts_in_power_and_needs_matching_bins() {
+        let spectrum = |magnitudes: Vec<f32>| analysis::Spectrum { bin_hz: 10.0, magnitudes, window: analysis::WindowFn::default() };
+        let floor = spectrum(vec![3.0, 3.0, 1.0]);
+        let cleaned = analysis::subtract_spectrum(&spectrum(vec![5.0, 2.0, 1.0]), &floor).unwrap();
+        assert_eq!(cleaned.magnitudes, [4.0, 0.0, 0.0]);
+        assert!(analysis::subtract_spectrum(&spectrum(vec![5.0; 4]), &floor).is_none());
+        let other_rate = analysis::Spectrum { bin_hz: 20.0, ..spectrum(vec![5.0; 3]) };
+        assert!(analysis::subtract_spectrum(&other_rate, &floor).is_none());
+    }
+
+    #[test]
+    fn channel_triggers_fire_only_while_enabled() {
+        let mut processor = adc::PacketProcessor::new();
+        let on = adc::ChannelTrigger { enabled: true, level: 0.5, rising_edge: true };
+        processor.set_channel_trigger(0, on).unwrap();
+        processor.set_channel_trigger(1, adc::ChannelTrigger { enabled: false, ..on }).unwrap();
+        assert!(!processor.get_trigger_config().enabled);
+        assert_eq!(processor.display_fill(), adc::DisplayFill::Hold);
+        assert_eq!(fired(&mut processor, &[(1, 1.0), (0, 1.0), (0, 0.0)]), [false, true, false]);
+
+        // Swapping which one is on keeps both levels
+        processor.set_channel_trigger(0, adc::ChannelTrigger { enabled: false, ..on }).unwrap();
+        processor.set_channel_trigger(1, on).unwrap();
+        assert_eq!(fired(&mut processor, &[(0, 1.0), (1, 1.0)]), [false, true]);
+        assert_eq!(processor.get_trigger_config().channel_triggers[0].level, 0.5);
+
+        let out_of_range = adc::ChannelTrigger { level: 1e6, ..on };
+        assert!(processor.set_channel_trigger(2, out_of_range).is_err());
+        assert!(processor.set_channel_trigger(2, adc::ChannelTrigger { enabled: false, ..out_of_range }).is_ok());
+    }
+
+    #[test]
+    fn last_trigger_info_names_the_source_and_edge() {
+        let mut processor = adc::PacketProcessor::new();
+        assert!(processor.last_trigger_info().is_none());
+        let config = adc::TriggerConfig { enabled: true, level: 1.0, any_edge: true, ..processor.get_trigger_config() };
+        processor.configure_trigger(config).unwrap();
+        processor.set_channel_trigger(1, adc::ChannelTrigger { enabled: true, level: -0.5, rising_edge: false }).unwrap();
+
+        let last = |processor: &adc::PacketProcessor| {
+            let info = processor.last_trigger_info().unwrap();
+            (info.channel, info.edge, info.source)
+        };
+        fired(&mut processor, &[(0, 0.0), (0, 2.0)]);
+        assert_eq!(last(&processor), (0, adc::TriggerEdge::Rising, adc::TriggerSource::Main));
+        fired(&mut processor, &[(1, -1.0)]);
+        assert_eq!(last(&processor), (1, adc::TriggerEdge::Falling, adc::TriggerSource::Channel));
+        fired(&mut processor, &[(0, 0.0)]);
+        assert_eq!(last(&processor), (0, adc::TriggerEdge::Falling, adc::TriggerSource::Main));
+    }
+
+    /// Channel labels that come out of `process_packets` for `labels` sent in one read.
+    fn labels_after_interleave(labels: &[u8]) -> (Vec<u8>, u64) {
+        let mut processor = adc::PacketProcessor::new();
+        processor.set_expected_channel_order(Some(vec![0, 1])).unwrap();
+        for &label in labels {
+            processor.add_bytes(&[0xAA, label, 0, 0, 0x55]);
+        }
+        let channels = processor.process_packets().iter().map(|s| s.channel).collect();
+        (channels, processor.get_resync_stats().interleave_corrections)
+    }
+
+    #[test]
+    fn interleave_fixes_corrupt_labels_but_follows_lost_packets() {
+        // The fourth label should have been 1
+        assert_eq!(labels_after_interleave(&[0, 1, 0, 0, 0, 1]), (vec![0, 1, 0, 1, 0, 1], 1));
+        // A 1 was lost between the third and fourth packets
+        assert_eq!(labels_after_interleave(&[0, 1, 0, 0, 1]), (vec![0, 1, 0, 0, 1], 0));
+    }
+
+    #[test]
+    fn relabeled_samples_use_their_own_calibration() {
+        let mut processor = adc::PacketProcessor::new();
+        processor.set_expected_channel_order(Some(vec![0, 1])).unwrap();
+        processor.set_calibration(1, adc::Calibration { gain: 1.0, offset: 2.0 }).unwrap();
+        for label in [0, 1, 0, 0, 0] {
+            processor.add_bytes(&[0xAA, label, 0, 0, 0x55]);
+        }
+        let relabeled = processor.process_packets().into_iter().nth(3).unwrap();
+        assert_eq!((relabeled.channel, relabeled.voltage), (1, 2.0));
+    }
+
+    #[test]
+    fn calibrate_zero_twice_stays_at_zero() {
+        let mut processor = adc::PacketProcessor::new();
+        let code = adc::volts_to_code(1.5, true).to_be_bytes();
+        processor.add_bytes(&[0xAA, 0, code[0], code[1], 0x55]);
+        processor.process_packets();
+
+        let offset = processor.calibrate_zero(0).unwrap();
+        assert!((offset + 1.5).abs() < 1e-3);
+        // Nothing new has arrived, so there's nothing to recalibrate from
+        assert!(processor.calibrate_zero(0).is_err());
+        assert_eq!(processor.get_calibration()[0].offset, offset);
+    }
+
+    #[test]
+    fn codes_round_trip_through_volts() {
+        for signed in [true, false] {
+            for raw in 0..=u16::MAX {
+                assert_eq!(adc::volts_to_code(adc::code_to_volts(raw, signed), signed), raw);
+            }
+        }
+    }
+
+    #[test]
+    fn volts_to_code_saturates() {
+        assert_eq!(adc::volts_to_code(25.0, true) as i16, i16::MAX);
+        assert_eq!(adc::volts_to_code(-25.0, true) as i16, i16::MIN);
+        assert_eq!(adc::volts_to_code(-1.0, false), 0);
+        assert_eq!(adc::volts_to_code(25.0, false), u16::MAX);
+    }
+
+    #[test]
+    fn calibrated_codes_round_trip() {
+        let mut processor = adc::PacketProcessor::new();
+        processor.set_calibration(2, adc::Calibration { gain: 0.5, offset: -1.25 }).unwrap();
+        for signed in [true, false] {
+            processor.set_signed_samples(signed);
+            for raw in [0, 1, 0x7FFF, 0x8000, 0xFFFF] {
+                let volts = processor.code_to_volts(2, raw);
+                assert_eq!(processor.volts_to_code(2, volts), raw, "code {:#06x}, signed {}", raw, signed);
             }
         }
     }
+
+    #[test]
+    fn out_of_range_trigger_channel_is_rejected() {
+        let mut processor = adc::PacketProcessor::new();
+        let config = adc::TriggerConfig { channel: 7, ..processor.get_trigger_config() };
+        assert!(processor.configure_trigger(config).is_err());
+    }
+
+    #[test]
+    fn midscale_codes_follow_signedness() {
+        let mut processor = adc::PacketProcessor::new();
+        let signed = decode(&mut processor, &[0x8000, 0x7FFF]);
+        assert_eq!(signed[0], -10.0);
+        assert!((signed[1] - 10.0).abs() < 1e-3);
+
+        processor.set_signed_samples(false);
+        let unsigned = decode(&mut processor, &[0x8000, 0x7FFF]);
+        assert_eq!(unsigned[0], 10.0);
+        assert!((unsigned[1] - 10.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn unsigned_trigger_reaches_upper_half() {
+        let mut processor = adc::PacketProcessor::new();
+        let mut config = processor.get_full_config();
+        config.trigger.level = 15.0;
+        assert!(processor.apply_config(config.clone()).is_err());
+
+        config.signed_samples = false;
+        assert!(processor.apply_config(config).is_ok());
+    }
+
+    #[test]
+    fn decimated_captures_overlay() {
+        // A ramp repeating every 12 samples, 1 µs apart
+        let samples: Vec<_> = (0..200u128).map(|i| sample(0, (i % 12) as f32, i * 1000)).collect();
+        let first = analysis::capture(&samples, 30_000, 10, 4, 3).unwrap();
+        let second = analysis::capture(&samples, 30_000 + 12 * 5 * 1000, 10, 4, 3).unwrap();
+        // Between samples the trigger snaps to the next one instead of shifting the phase
+        let between = analysis::capture(&samples, 90_000 - 400, 10, 4, 3).unwrap();
+
+        for capture in [&first, &second, &between] {
+            assert_eq!(capture.trigger_index, 4);
+            assert_eq!(capture.samples.len(), 10);
+            assert_eq!(capture.samples[4].timestamp, capture.trigger_ts);
+        }
+        assert_eq!(between.trigger_ts, 90_000);
+        let values = |c: &analysis::Capture| c.samples.iter().map(|s| s.voltage).collect::<Vec<_>>();
+        assert_eq!(values(&first), values(&second));
+        assert_eq!(values(&first), values(&between));
+    }
+
+    #[test]
+    fn flat_top_reads_off_bin_tone_amplitude() {
+        // Halfway between bins is the worst case for scalloping loss
+        let spectrum = analysis::magnitude_spectrum(&tone(1024, 50.5, 3.0), analysis::WindowFn::FlatTop);
+        let peak = spectrum.iter().cloned().fold(0.0, f32::max);
+        assert!((peak - 3.0).abs() < 0.01, "flat-top peak {}", peak);
+
+        let hann = analysis::magnitude_spectrum(&tone(1024, 50.5, 3.0), analysis::WindowFn::Hann);
+        assert!(hann.iter().cloned().fold(0.0, f32::max) < 2.8, "Hann should scallop between bins");
+    }
 }
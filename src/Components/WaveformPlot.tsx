@@ -66,7 +66,7 @@ function parseChannels(rawData: string[]): ChannelData {
     };
 
     for (const line of rawData) {
-        const match = line.match(/Ch(\d+):\s*([-\d.]+)\s*V/);
+        const match = line.match(/Ch(\d+):\s*([-\d.]+)\s*\S+/);
         if (match) {
             const [, channel, value] = match;
             const channelNum = parseInt(channel);